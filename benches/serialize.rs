@@ -0,0 +1,142 @@
+//! Serialization and crc benchmarks on multi-megabyte files. The
+//! `collect` cases rebuild bytes the way `Chunk::as_bytes` and
+//! `Png::as_bytes` used to, through chained iterators and intermediate
+//! vectors, as a baseline for the streaming `write_to`. Parsing compares
+//! owned chunks with the borrowed views of `PngRef`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::png::{Png, PngRef};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+const IDAT_LENGTH: usize = 1 << 16;
+
+/// Deterministic noise, so that runs compare.
+fn noise(length: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2_654_435_761) | 1;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// A file of about `megabytes` MiB, its image data split into 64 KiB
+/// `IDAT` chunks as encoders commonly do.
+fn large_png(megabytes: usize) -> Png {
+    let chunk = |t: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&4096u32.to_be_bytes());
+    ihdr.extend_from_slice(&((megabytes as u32) << 6).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    let mut chunks = vec![chunk("IHDR", ihdr)];
+    for i in 0..megabytes * (1 << 20) / IDAT_LENGTH {
+        chunks.push(chunk("IDAT", noise(IDAT_LENGTH, i as u32)));
+    }
+    chunks.push(chunk("IEND", Vec::new()));
+    Png::from_chunks(chunks)
+}
+
+fn collect_chunk(chunk: &Chunk) -> Vec<u8> {
+    chunk
+        .length()
+        .to_be_bytes()
+        .iter()
+        .chain(chunk.chunk_type().bytes().iter())
+        .chain(chunk.data().iter())
+        .chain(chunk.crc().to_be_bytes().iter())
+        .cloned()
+        .collect()
+}
+
+fn collect_png(png: &Png) -> Vec<u8> {
+    png.header()
+        .iter()
+        .chain(
+            png.chunks()
+                .iter()
+                .flat_map(|chunk| collect_chunk(chunk).into_iter())
+                .collect::<Vec<u8>>()
+                .iter(),
+        )
+        .cloned()
+        .collect()
+}
+
+fn serialize(c: &mut Criterion) {
+    for megabytes in [4, 16] {
+        let png = large_png(megabytes);
+        let mut group = c.benchmark_group(format!("serialize/{}MiB", megabytes));
+        group.throughput(Throughput::Bytes(png.encoded_len() as u64));
+        group.sample_size(20);
+        // Into a reused buffer, as when streaming to a file or socket.
+        let mut buffer = Vec::with_capacity(png.encoded_len());
+        group.bench_function("write_to", |b| {
+            b.iter(|| {
+                buffer.clear();
+                png.write_to(&mut buffer).unwrap();
+                black_box(&buffer);
+            })
+        });
+        group.bench_function("as_bytes", |b| b.iter(|| png.as_bytes()));
+        group.bench_function("collect", |b| b.iter(|| collect_png(&png)));
+        group.finish();
+    }
+}
+
+fn crc(c: &mut Criterion) {
+    let data = noise(4 << 20, 0);
+    let chunk_type = ChunkType::from_str("IDAT").unwrap();
+    let mut group = c.benchmark_group("crc/4MiB");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(20);
+    group.bench_function("new", |b| {
+        b.iter_batched(
+            || data.clone(),
+            |data| Chunk::new(chunk_type.clone(), data),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("collect", |b| {
+        b.iter(|| {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(
+                chunk_type
+                    .bytes()
+                    .iter()
+                    .chain(data.iter())
+                    .cloned()
+                    .collect::<Vec<u8>>()
+                    .as_slice(),
+            );
+            hasher.finalize()
+        })
+    });
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let bytes = large_png(16).as_bytes();
+    let mut group = c.benchmark_group("parse/16MiB");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.sample_size(20);
+    group.bench_function("try_from", |b| {
+        b.iter(|| Png::try_from(&bytes[..]).unwrap())
+    });
+    group.bench_function("png_ref", |b| {
+        b.iter(|| {
+            let view = PngRef::parse(&bytes).unwrap();
+            view.verify().unwrap();
+            view
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serialize, crc, parse);
+criterion_main!(benches);
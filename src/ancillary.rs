@@ -0,0 +1,546 @@
+use crate::apng::{self, AnimationControl, FrameControl};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::color::{Chromaticities, ColorInfo, Gamma, IccProfile, RenderingIntent};
+use crate::exif::Exif;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::palette::Palette;
+use crate::png::Png;
+use crate::{Error, Result};
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum AncillaryErr {
+    InvalidLength(&'static str, usize),
+    MissingHeader(&'static str),
+    MissingPalette(&'static str),
+    SampleOutOfRange(&'static str, u16, u8),
+    IndexOutOfRange(u8, usize),
+    InvalidSignificantBits(u8, u8),
+    HistogramLength(usize, usize),
+    InvalidSampleDepth(u8),
+    InvalidPaletteName,
+}
+impl std::error::Error for AncillaryErr {}
+impl Display for AncillaryErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            AncillaryErr::InvalidLength(t, len) => write!(f, "Invalid {} chunk length {}", t, len),
+            AncillaryErr::MissingHeader(t) => write!(f, "{} requires an IHDR chunk", t),
+            AncillaryErr::MissingPalette(t) => write!(f, "{} requires a PLTE chunk", t),
+            AncillaryErr::SampleOutOfRange(t, v, depth) => {
+                write!(f, "{} sample {} does not fit bit depth {}", t, v, depth)
+            }
+            AncillaryErr::IndexOutOfRange(i, len) => {
+                write!(f, "bKGD palette index {} out of range 0..{}", i, len)
+            }
+            AncillaryErr::InvalidSignificantBits(bits, depth) => {
+                write!(f, "sBIT value {} must be in 1..={}", bits, depth)
+            }
+            AncillaryErr::HistogramLength(n, entries) => {
+                write!(f, "hIST has {} entries but the palette has {}", n, entries)
+            }
+            AncillaryErr::InvalidSampleDepth(d) => write!(f, "sPLT sample depth {} not 8 or 16", d),
+            AncillaryErr::InvalidPaletteName => {
+                write!(f, "sPLT name must be 1-79 bytes followed by a null")
+            }
+        }
+    }
+}
+
+fn u16_at(data: &[u8], i: usize) -> u16 {
+    u16::from_be_bytes([data[i], data[i + 1]])
+}
+
+fn check_sample(t: &'static str, value: u16, bit_depth: u8) -> Result<()> {
+    if bit_depth < 16 && value >= 1 << bit_depth {
+        return Err(Box::new(AncillaryErr::SampleOutOfRange(
+            t, value, bit_depth,
+        )));
+    }
+    Ok(())
+}
+
+/// Background color from a `bKGD` chunk. Its layout depends on the color type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Gray(u16),
+    Rgb(u16, u16, u16),
+    Index(u8),
+}
+
+impl Background {
+    pub fn from_chunk(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self> {
+        let data = chunk.data();
+        let background = match (ihdr.color_type, data.len()) {
+            (ColorType::Grayscale, 2) | (ColorType::GrayscaleAlpha, 2) => {
+                Background::Gray(u16_at(data, 0))
+            }
+            (ColorType::Rgb, 6) | (ColorType::Rgba, 6) => {
+                Background::Rgb(u16_at(data, 0), u16_at(data, 2), u16_at(data, 4))
+            }
+            (ColorType::Indexed, 1) => Background::Index(data[0]),
+            (_, len) => return Err(Box::new(AncillaryErr::InvalidLength("bKGD", len))),
+        };
+        Ok(background)
+    }
+
+    pub fn validate(&self, ihdr: &Ihdr, palette_len: Option<usize>) -> Result<()> {
+        match *self {
+            Background::Gray(v) => check_sample("bKGD", v, ihdr.bit_depth),
+            Background::Rgb(r, g, b) => [r, g, b]
+                .iter()
+                .try_for_each(|&v| check_sample("bKGD", v, ihdr.bit_depth)),
+            Background::Index(i) => {
+                let len = palette_len.ok_or(AncillaryErr::MissingPalette("bKGD"))?;
+                if i as usize >= len {
+                    return Err(Box::new(AncillaryErr::IndexOutOfRange(i, len)));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let data = match self {
+            Background::Gray(v) => v.to_be_bytes().to_vec(),
+            Background::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+            Background::Index(i) => vec![i],
+        };
+        Chunk::new(ChunkType::from_str("bKGD").unwrap(), data)
+    }
+}
+impl Display for Background {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Background::Gray(v) => write!(f, "gray {}", v),
+            Background::Rgb(r, g, b) => write!(f, "rgb({}, {}, {})", r, g, b),
+            Background::Index(i) => write!(f, "palette index {}", i),
+        }
+    }
+}
+
+/// Significant bits per channel from an `sBIT` chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignificantBits(Vec<u8>);
+
+impl SignificantBits {
+    pub fn new(bits: Vec<u8>) -> Self {
+        Self(bits)
+    }
+
+    pub fn from_chunk(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self> {
+        let bits = Self(chunk.data().to_vec());
+        bits.validate(ihdr)?;
+        Ok(bits)
+    }
+
+    pub fn validate(&self, ihdr: &Ihdr) -> Result<()> {
+        // Palette entries are always 8 bit, and sBIT describes them as rgb.
+        let (channels, depth) = match ihdr.color_type {
+            ColorType::Indexed => (3, 8),
+            c => (c.channels(), ihdr.bit_depth),
+        };
+        if self.0.len() != channels {
+            return Err(Box::new(AncillaryErr::InvalidLength("sBIT", self.0.len())));
+        }
+        match self.0.iter().find(|&&b| b == 0 || b > depth) {
+            Some(&b) => Err(Box::new(AncillaryErr::InvalidSignificantBits(b, depth))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn bits(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str("sBIT").unwrap(), self.0.clone())
+    }
+}
+impl Display for SignificantBits {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let bits: Vec<_> = self.0.iter().map(|b| b.to_string()).collect();
+        write!(f, "{} significant bits", bits.join("/"))
+    }
+}
+
+/// Palette entry usage frequencies from an `hIST` chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram(Vec<u16>);
+
+impl Histogram {
+    pub fn new(frequencies: Vec<u16>) -> Self {
+        Self(frequencies)
+    }
+
+    pub fn from_chunk(chunk: &Chunk, palette_len: usize) -> Result<Self> {
+        let data = chunk.data();
+        if data.len() % 2 != 0 {
+            return Err(Box::new(AncillaryErr::InvalidLength("hIST", data.len())));
+        }
+        let histogram = Self(data.chunks_exact(2).map(|b| u16_at(b, 0)).collect());
+        histogram.validate(palette_len)?;
+        Ok(histogram)
+    }
+
+    pub fn validate(&self, palette_len: usize) -> Result<()> {
+        if self.0.len() != palette_len {
+            return Err(Box::new(AncillaryErr::HistogramLength(
+                self.0.len(),
+                palette_len,
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn frequencies(&self) -> &[u16] {
+        &self.0
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("hIST").unwrap(),
+            self.0.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        )
+    }
+}
+impl Display for Histogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{} palette frequencies", self.0.len())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuggestedEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
+}
+
+/// A suggested palette from an `sPLT` chunk. Samples are stored at the
+/// palette's own sample depth, independent of the image bit depth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    pub sample_depth: u8,
+    pub entries: Vec<SuggestedEntry>,
+}
+
+impl SuggestedPalette {
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        if self.sample_depth != 8 && self.sample_depth != 16 {
+            return Err(Box::new(AncillaryErr::InvalidSampleDepth(
+                self.sample_depth,
+            )));
+        }
+        if self.name.is_empty() || self.name.len() > 79 || self.name.contains('\0') {
+            return Err(Box::new(AncillaryErr::InvalidPaletteName));
+        }
+        let mut data = self.name.as_bytes().to_vec();
+        data.push(0);
+        data.push(self.sample_depth);
+        for e in self.entries.iter() {
+            for &sample in [e.red, e.green, e.blue, e.alpha].iter() {
+                match self.sample_depth {
+                    8 => data.push(sample as u8),
+                    _ => data.extend(sample.to_be_bytes()),
+                }
+            }
+            data.extend(e.frequency.to_be_bytes());
+        }
+        Ok(Chunk::new(ChunkType::from_str("sPLT")?, data))
+    }
+}
+impl TryFrom<&Chunk> for SuggestedPalette {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        let nul = data
+            .iter()
+            .position(|&b| b == 0)
+            .filter(|&n| (1..=79).contains(&n))
+            .ok_or(AncillaryErr::InvalidPaletteName)?;
+        let name = data[..nul].iter().map(|&b| b as char).collect();
+        let sample_depth = *data
+            .get(nul + 1)
+            .ok_or(AncillaryErr::InvalidLength("sPLT", data.len()))?;
+        let entry_size = match sample_depth {
+            8 => 6,
+            16 => 10,
+            d => return Err(Box::new(AncillaryErr::InvalidSampleDepth(d))),
+        };
+        let entries = &data[nul + 2..];
+        if entries.len() % entry_size != 0 {
+            return Err(Box::new(AncillaryErr::InvalidLength("sPLT", data.len())));
+        }
+        let entries = entries
+            .chunks_exact(entry_size)
+            .map(|e| {
+                let sample = |i: usize| match sample_depth {
+                    8 => e[i] as u16,
+                    _ => u16_at(e, i * 2),
+                };
+                SuggestedEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16_at(e, entry_size - 2),
+                }
+            })
+            .collect();
+        Ok(Self {
+            name,
+            sample_depth,
+            entries,
+        })
+    }
+}
+impl Display for SuggestedPalette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}: {} entries at {}-bit",
+            self.name,
+            self.entries.len(),
+            self.sample_depth
+        )
+    }
+}
+
+/// What `IHDR` and `PLTE` say about the image, needed to interpret chunks
+/// whose layout depends on the color type.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageContext {
+    pub ihdr: Option<Ihdr>,
+    pub palette_len: Option<usize>,
+}
+
+impl ImageContext {
+    pub fn from_png(png: &Png) -> Self {
+        Self {
+            ihdr: Ihdr::from_png(png).ok(),
+            palette_len: png.chunk_by_type("PLTE").map(|c| c.data().len() / 3),
+        }
+    }
+
+    fn ihdr(&self, t: &'static str) -> Result<&Ihdr> {
+        self.ihdr
+            .as_ref()
+            .ok_or_else(|| Box::new(AncillaryErr::MissingHeader(t)) as Error)
+    }
+
+    fn palette_len(&self, t: &'static str) -> Result<usize> {
+        self.palette_len
+            .ok_or_else(|| Box::new(AncillaryErr::MissingPalette(t)) as Error)
+    }
+}
+
+/// Ancillary chunk types [`describe`] understands.
+pub const KNOWN_CHUNK_TYPES: [&str; 13] = [
+    "tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "eXIf", "bKGD", "sBIT", "hIST", "sPLT", "acTL", "fcTL",
+    "fdAT",
+];
+
+/// Every chunk type registered for PNG: those of the specification,
+/// including APNG's, and the registered extensions. Unsafe-to-copy ones are
+/// still kept after a critical change, as the spec only has editors drop
+/// chunk types they do not know.
+pub const STANDARD_CHUNK_TYPES: [&str; 34] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "acTL", "bKGD", "cHRM", "cICP", "cLLI", "eXIf", "fcTL", "fdAT",
+    "gAMA", "hIST", "iCCP", "iTXt", "mDCV", "pHYs", "sBIT", "sPLT", "sRGB", "tEXt", "tIME", "tRNS",
+    "zTXt", "oFFs", "pCAL", "sCAL", "gIFg", "gIFx", "gIFt", "sTER", "dSIG", "fRAc",
+];
+
+/// Decodes a known chunk into a one line summary. Returns `None` for chunk
+/// types PNGme has no typed support for.
+pub fn describe(chunk: &Chunk, ctx: &ImageContext) -> Option<Result<String>> {
+    let description = match chunk.chunk_type().to_string().as_str() {
+        "IHDR" => Ihdr::try_from(chunk).map(|c| c.to_string()),
+        "PLTE" => Palette::from_chunks(chunk, None).map(|p| format!("{} entries", p.len())),
+        "tRNS" => describe_transparency(chunk, ctx),
+        "gAMA" => Gamma::try_from(chunk).map(|c| format!("gamma {}", c)),
+        "cHRM" => Chromaticities::try_from(chunk).map(|c| c.to_string()),
+        "sRGB" => RenderingIntent::try_from(chunk).map(|c| format!("{} intent", c)),
+        "iCCP" => IccProfile::try_from(chunk)
+            .map(|c| format!("{} ({} bytes)", c.name(), c.profile().len())),
+        "eXIf" => Exif::try_from(chunk).map(|c| format!("{} tags", c.entries().len())),
+        "bKGD" => ctx.ihdr("bKGD").and_then(|ihdr| {
+            let background = Background::from_chunk(chunk, ihdr)?;
+            background.validate(ihdr, ctx.palette_len)?;
+            Ok(background.to_string())
+        }),
+        "sBIT" => ctx
+            .ihdr("sBIT")
+            .and_then(|ihdr| SignificantBits::from_chunk(chunk, ihdr))
+            .map(|c| c.to_string()),
+        "hIST" => ctx
+            .palette_len("hIST")
+            .and_then(|len| Histogram::from_chunk(chunk, len))
+            .map(|c| c.to_string()),
+        "sPLT" => SuggestedPalette::try_from(chunk).map(|c| c.to_string()),
+        "acTL" => AnimationControl::try_from(chunk)
+            .map(|c| format!("{} frames, {} plays", c.num_frames, c.num_plays)),
+        "fcTL" => FrameControl::try_from(chunk).map(|c| {
+            format!(
+                "frame {} {}x{} at ({}, {}) {:.3}s",
+                c.sequence_number,
+                c.width,
+                c.height,
+                c.x_offset,
+                c.y_offset,
+                c.delay()
+            )
+        }),
+        "fdAT" => apng::frame_data(chunk).map(|(seq, _)| format!("sequence {}", seq)),
+        _ => return None,
+    };
+    Some(description)
+}
+
+fn describe_transparency(chunk: &Chunk, ctx: &ImageContext) -> Result<String> {
+    let ihdr = ctx.ihdr("tRNS")?;
+    let data = chunk.data();
+    match (ihdr.color_type, data.len()) {
+        (ColorType::Indexed, len) if len <= ctx.palette_len("tRNS")? => {
+            Ok(format!("{} palette alphas", len))
+        }
+        (ColorType::Grayscale, 2) => Ok(format!("transparent gray {}", u16_at(data, 0))),
+        (ColorType::Rgb, 6) => Ok(format!(
+            "transparent rgb({}, {}, {})",
+            u16_at(data, 0),
+            u16_at(data, 2),
+            u16_at(data, 4)
+        )),
+        (_, len) => Err(Box::new(AncillaryErr::InvalidLength("tRNS", len))),
+    }
+}
+
+/// Checks every known chunk in `png` and the cross-chunk rules between them,
+/// returning each problem found.
+pub fn validate(png: &Png) -> Vec<Error> {
+    let ctx = ImageContext::from_png(png);
+    let mut problems: Vec<Error> = png
+        .chunks()
+        .iter()
+        .filter_map(|chunk| describe(chunk, &ctx))
+        .filter_map(|description| description.err())
+        .collect();
+    problems.extend(validate_relations(png));
+    problems
+}
+
+/// The cross-chunk part of [`validate`], for callers that already show
+/// what [`describe`] makes of each chunk.
+pub fn validate_relations(png: &Png) -> Vec<Error> {
+    let mut problems = Vec::new();
+    if let Ok(info) = ColorInfo::from_png(png) {
+        if let Err(e) = info.validate() {
+            problems.push(e);
+        }
+    }
+    if let Err(e) = png.animation() {
+        problems.push(e);
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr(color_type: ColorType, bit_depth: u8) -> Ihdr {
+        Ihdr::new(4, 4, bit_depth, color_type).unwrap()
+    }
+
+    #[test]
+    fn test_background_per_color_type() {
+        let gray = ihdr(ColorType::Grayscale, 4);
+        let chunk = Background::Gray(15).to_chunk();
+        let background = Background::from_chunk(&chunk, &gray).unwrap();
+        assert!(background.validate(&gray, None).is_ok());
+        assert!(Background::Gray(16).validate(&gray, None).is_err());
+
+        let rgb = ihdr(ColorType::Rgb, 8);
+        assert!(Background::from_chunk(&chunk, &rgb).is_err());
+        let chunk = Background::Rgb(1, 2, 3).to_chunk();
+        assert_eq!(
+            Background::from_chunk(&chunk, &rgb).unwrap(),
+            Background::Rgb(1, 2, 3)
+        );
+
+        let indexed = ihdr(ColorType::Indexed, 8);
+        assert!(Background::Index(3).validate(&indexed, Some(3)).is_err());
+        assert!(Background::Index(2).validate(&indexed, Some(3)).is_ok());
+        assert!(Background::Index(0).validate(&indexed, None).is_err());
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        let rgba = ihdr(ColorType::Rgba, 8);
+        let bits = SignificantBits::new(vec![5, 6, 5, 8]);
+        assert!(SignificantBits::from_chunk(&bits.to_chunk(), &rgba).is_ok());
+        assert!(SignificantBits::new(vec![5, 6, 5]).validate(&rgba).is_err());
+        assert!(SignificantBits::new(vec![5, 6, 5, 9])
+            .validate(&rgba)
+            .is_err());
+        let indexed = ihdr(ColorType::Indexed, 2);
+        assert!(SignificantBits::new(vec![8, 8, 8])
+            .validate(&indexed)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_histogram_length() {
+        let histogram = Histogram::new(vec![10, 20, 30]);
+        assert!(Histogram::from_chunk(&histogram.to_chunk(), 3).is_ok());
+        assert!(Histogram::from_chunk(&histogram.to_chunk(), 4).is_err());
+    }
+
+    #[test]
+    fn test_suggested_palette_roundtrip() {
+        for &depth in [8, 16].iter() {
+            let palette = SuggestedPalette {
+                name: "web safe".to_string(),
+                sample_depth: depth,
+                entries: vec![SuggestedEntry {
+                    red: 255,
+                    green: 0,
+                    blue: 51,
+                    alpha: 255,
+                    frequency: 7,
+                }],
+            };
+            let chunk = palette.to_chunk().unwrap();
+            assert_eq!(SuggestedPalette::try_from(&chunk).unwrap(), palette);
+        }
+        let chunk = Chunk::new(
+            ChunkType::from_str("sPLT").unwrap(),
+            b"x\0\x08\x01".to_vec(),
+        );
+        assert!(SuggestedPalette::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_validate_png() {
+        let mut png = Png::from_chunks(vec![
+            ihdr(ColorType::Indexed, 8).to_chunk(),
+            Chunk::new(ChunkType::from_str("PLTE").unwrap(), vec![0; 6]),
+            Histogram::new(vec![1, 2]).to_chunk(),
+            Background::Index(1).to_chunk(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]);
+        assert!(validate(&png).is_empty());
+        png.append_chunk(Histogram::new(vec![1, 2, 3]).to_chunk());
+        png.append_chunk(Background::Index(9).to_chunk());
+        assert_eq!(validate(&png).len(), 2);
+        assert!(validate_relations(&png).is_empty());
+    }
+}
@@ -0,0 +1,648 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::palette::Palette;
+use crate::pixels::{PixelErr, RawImage, RgbaImage};
+use crate::png::Png;
+use crate::{Error, Result};
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum ApngErr {
+    InvalidLength(&'static str, usize),
+    InvalidDisposeOp(u8),
+    InvalidBlendOp(u8),
+    SequenceMismatch(u32, u32),
+    FrameCountMismatch(u32, usize),
+    FrameOutOfBounds(u32),
+    DefaultFrameMismatch,
+    DataWithoutControl(u32),
+    EmptyFrame(u32),
+    ZeroFrames,
+}
+impl std::error::Error for ApngErr {}
+impl Display for ApngErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ApngErr::InvalidLength(t, len) => write!(f, "Invalid {} chunk length {}", t, len),
+            ApngErr::InvalidDisposeOp(op) => write!(f, "Invalid fcTL dispose op {}", op),
+            ApngErr::InvalidBlendOp(op) => write!(f, "Invalid fcTL blend op {}", op),
+            ApngErr::SequenceMismatch(expected, got) => {
+                write!(f, "Expected sequence number {}, got {}", expected, got)
+            }
+            ApngErr::FrameCountMismatch(expected, got) => {
+                write!(f, "acTL declares {} frames, found {}", expected, got)
+            }
+            ApngErr::FrameOutOfBounds(seq) => {
+                write!(f, "Frame {} lies outside the image bounds", seq)
+            }
+            ApngErr::DefaultFrameMismatch => {
+                write!(f, "fcTL before IDAT must cover the whole image at offset 0")
+            }
+            ApngErr::DataWithoutControl(seq) => {
+                write!(f, "fdAT {} is not preceded by an fcTL", seq)
+            }
+            ApngErr::EmptyFrame(seq) => write!(f, "Frame {} has no image data", seq),
+            ApngErr::ZeroFrames => write!(f, "acTL must declare at least one frame"),
+        }
+    }
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes(data[i..i + 4].try_into().unwrap())
+}
+
+fn u16_at(data: &[u8], i: usize) -> u16 {
+    u16::from_be_bytes(data[i..i + 2].try_into().unwrap())
+}
+
+/// Animation control from the `acTL` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// Zero means loop forever.
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = self.num_frames.to_be_bytes().to_vec();
+        data.extend(self.num_plays.to_be_bytes());
+        Chunk::new(ChunkType::from_str("acTL").unwrap(), data)
+    }
+}
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        if data.len() != 8 {
+            return Err(Box::new(ApngErr::InvalidLength("acTL", data.len())));
+        }
+        let control = Self {
+            num_frames: u32_at(data, 0),
+            num_plays: u32_at(data, 4),
+        };
+        if control.num_frames == 0 {
+            return Err(Box::new(ApngErr::ZeroFrames));
+        }
+        Ok(control)
+    }
+}
+
+/// How the frame's region is treated before rendering the next frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None,
+    Background,
+    Previous,
+}
+
+/// Whether the frame replaces or is alpha composited over its region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source,
+    Over,
+}
+
+impl Display for DisposeOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            DisposeOp::None => write!(f, "none"),
+            DisposeOp::Background => write!(f, "background"),
+            DisposeOp::Previous => write!(f, "previous"),
+        }
+    }
+}
+impl Display for BlendOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            BlendOp::Source => write!(f, "source"),
+            BlendOp::Over => write!(f, "over"),
+        }
+    }
+}
+
+/// Frame control from an `fcTL` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    /// Frame delay in seconds. A zero denominator means hundredths.
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(26);
+        for v in [
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+        ]
+        .iter()
+        {
+            data.extend(v.to_be_bytes());
+        }
+        data.extend(self.delay_num.to_be_bytes());
+        data.extend(self.delay_den.to_be_bytes());
+        data.push(match self.dispose_op {
+            DisposeOp::None => 0,
+            DisposeOp::Background => 1,
+            DisposeOp::Previous => 2,
+        });
+        data.push(match self.blend_op {
+            BlendOp::Source => 0,
+            BlendOp::Over => 1,
+        });
+        Chunk::new(ChunkType::from_str("fcTL").unwrap(), data)
+    }
+}
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let data = chunk.data();
+        if data.len() != 26 {
+            return Err(Box::new(ApngErr::InvalidLength("fcTL", data.len())));
+        }
+        Ok(Self {
+            sequence_number: u32_at(data, 0),
+            width: u32_at(data, 4),
+            height: u32_at(data, 8),
+            x_offset: u32_at(data, 12),
+            y_offset: u32_at(data, 16),
+            delay_num: u16_at(data, 20),
+            delay_den: u16_at(data, 22),
+            dispose_op: match data[24] {
+                0 => DisposeOp::None,
+                1 => DisposeOp::Background,
+                2 => DisposeOp::Previous,
+                op => return Err(Box::new(ApngErr::InvalidDisposeOp(op))),
+            },
+            blend_op: match data[25] {
+                0 => BlendOp::Source,
+                1 => BlendOp::Over,
+                op => return Err(Box::new(ApngErr::InvalidBlendOp(op))),
+            },
+        })
+    }
+}
+
+/// Splits an `fdAT` chunk into its sequence number and compressed data.
+pub fn frame_data(chunk: &Chunk) -> Result<(u32, &[u8])> {
+    let data = chunk.data();
+    if data.len() < 4 {
+        return Err(Box::new(ApngErr::InvalidLength("fdAT", data.len())));
+    }
+    Ok((u32_at(data, 0), &data[4..]))
+}
+
+pub fn frame_data_chunk(sequence_number: u32, data: &[u8]) -> Chunk {
+    let mut bytes = sequence_number.to_be_bytes().to_vec();
+    bytes.extend(data);
+    Chunk::new(ChunkType::from_str("fdAT").unwrap(), bytes)
+}
+
+/// One animation frame: its control and the zlib stream of its pixels, which
+/// is the `IDAT` data for a default image frame and the `fdAT` data otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    pub data: Vec<u8>,
+    pub is_default_image: bool,
+}
+
+/// Every frame of an animated png, validated against `IHDR` and the
+/// `fcTL`/`fdAT` sequence numbering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub frames: Vec<Frame>,
+    /// Whether the `IDAT` image is part of the animation; if not it is only
+    /// shown by decoders without APNG support.
+    pub default_image_is_frame: bool,
+}
+
+impl Animation {
+    /// Returns `None` when the png has no `acTL` chunk.
+    pub fn from_png(png: &Png) -> Result<Option<Self>> {
+        let control = match png.chunk_by_type("acTL") {
+            Some(chunk) => AnimationControl::try_from(chunk)?,
+            None => return Ok(None),
+        };
+        let ihdr = Ihdr::from_png(png)?;
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut next_sequence = 0;
+        let mut seen_idat = false;
+        let mut default_image_is_frame = false;
+        let mut check_sequence = |seq: u32| {
+            if seq != next_sequence {
+                return Err(Box::new(ApngErr::SequenceMismatch(next_sequence, seq)) as Error);
+            }
+            next_sequence += 1;
+            Ok(())
+        };
+        for chunk in png.chunks() {
+            match chunk.chunk_type().to_string().as_str() {
+                "fcTL" => {
+                    let fctl = FrameControl::try_from(chunk)?;
+                    check_sequence(fctl.sequence_number)?;
+                    if fctl.width == 0
+                        || fctl.height == 0
+                        || fctl.x_offset as u64 + fctl.width as u64 > ihdr.width as u64
+                        || fctl.y_offset as u64 + fctl.height as u64 > ihdr.height as u64
+                    {
+                        return Err(Box::new(ApngErr::FrameOutOfBounds(fctl.sequence_number)));
+                    }
+                    let is_default_image = !seen_idat;
+                    if is_default_image {
+                        if (fctl.width, fctl.height, fctl.x_offset, fctl.y_offset)
+                            != (ihdr.width, ihdr.height, 0, 0)
+                        {
+                            return Err(Box::new(ApngErr::DefaultFrameMismatch));
+                        }
+                        default_image_is_frame = true;
+                    }
+                    frames.push(Frame {
+                        control: fctl,
+                        data: Vec::new(),
+                        is_default_image,
+                    });
+                }
+                "IDAT" => {
+                    seen_idat = true;
+                    if let Some(frame) = frames.last_mut().filter(|f| f.is_default_image) {
+                        frame.data.extend(chunk.data());
+                    }
+                }
+                "fdAT" => {
+                    let (seq, data) = frame_data(chunk)?;
+                    check_sequence(seq)?;
+                    let frame = frames
+                        .last_mut()
+                        .filter(|f| !f.is_default_image)
+                        .ok_or(ApngErr::DataWithoutControl(seq))?;
+                    frame.data.extend(data);
+                }
+                _ => {}
+            }
+        }
+        if let Some(frame) = frames.iter().find(|f| f.data.is_empty()) {
+            return Err(Box::new(ApngErr::EmptyFrame(frame.control.sequence_number)));
+        }
+        if frames.len() != control.num_frames as usize {
+            return Err(Box::new(ApngErr::FrameCountMismatch(
+                control.num_frames,
+                frames.len(),
+            )));
+        }
+        Ok(Some(Self {
+            control,
+            frames,
+            default_image_is_frame,
+        }))
+    }
+
+    /// Renders each frame onto a full size canvas as a viewer would show it,
+    /// applying blend ops while drawing and dispose ops between frames.
+    pub fn render(&self, png: &Png) -> Result<Vec<RgbaImage>> {
+        let ihdr = Ihdr::from_png(png)?;
+        let palette = match ihdr.color_type {
+            ColorType::Indexed => Some(Palette::from_png(png)?),
+            _ => None,
+        };
+        let trns = png.chunk_by_type("tRNS");
+        let mut canvas = RgbaImage::new(ihdr.width, ihdr.height);
+        let mut rendered = Vec::with_capacity(self.frames.len());
+        for (i, frame) in self.frames.iter().enumerate() {
+            let c = &frame.control;
+            let frame_ihdr = Ihdr {
+                width: c.width,
+                height: c.height,
+                ..ihdr
+            };
+            let pixels = RawImage::decode(&frame_ihdr, &frame.data, png.limits())?
+                .to_rgba8(palette.as_ref(), trns)?;
+            let saved = match c.dispose_op {
+                DisposeOp::Previous => Some(canvas.clone()),
+                _ => None,
+            };
+            for y in 0..c.height {
+                for x in 0..c.width {
+                    let src = pixels.pixel(x, y);
+                    let (cx, cy) = (c.x_offset + x, c.y_offset + y);
+                    let out = match c.blend_op {
+                        BlendOp::Source => src,
+                        BlendOp::Over => over(src, canvas.pixel(cx, cy)),
+                    };
+                    canvas.set_pixel(cx, cy, out);
+                }
+            }
+            rendered.push(canvas.clone());
+            // A dispose to previous on the first frame behaves as background.
+            match (c.dispose_op, saved) {
+                (DisposeOp::Previous, Some(saved)) if i > 0 => canvas = saved,
+                (DisposeOp::None, _) => {}
+                _ => {
+                    for y in c.y_offset..c.y_offset + c.height {
+                        for x in c.x_offset..c.x_offset + c.width {
+                            canvas.set_pixel(x, y, [0; 4]);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Builds an animated png from full size frames, all of which must share
+    /// the first frame's dimensions. `delays` are in milliseconds, one per
+    /// frame. The first frame doubles as the default image.
+    pub fn build(frames: &[RgbaImage], delays: &[u16], num_plays: u32) -> Result<Png> {
+        let first = frames.first().ok_or(ApngErr::ZeroFrames)?;
+        let (width, height) = (first.width, first.height);
+        let mut chunks = vec![
+            Ihdr::new(width, height, 8, ColorType::Rgba)?.to_chunk(),
+            AnimationControl {
+                num_frames: frames.len() as u32,
+                num_plays,
+            }
+            .to_chunk(),
+        ];
+        let mut sequence_number = 0;
+        for (i, frame) in frames.iter().enumerate() {
+            if (frame.width, frame.height) != (width, height) {
+                return Err(Box::new(PixelErr::SizeMismatch(
+                    frame.width,
+                    frame.height,
+                    width,
+                    height,
+                )));
+            }
+            chunks.push(
+                FrameControl {
+                    sequence_number,
+                    width,
+                    height,
+                    x_offset: 0,
+                    y_offset: 0,
+                    delay_num: delays.get(i).copied().unwrap_or(100),
+                    delay_den: 1000,
+                    dispose_op: DisposeOp::None,
+                    blend_op: BlendOp::Source,
+                }
+                .to_chunk(),
+            );
+            sequence_number += 1;
+            let data = frame
+                .clone()
+                .into_raw()
+                .encode(crate::pixels::FilterStrategy::Adaptive, 6)?;
+            if i == 0 {
+                chunks.push(Chunk::new(ChunkType::from_str("IDAT")?, data));
+            } else {
+                chunks.push(frame_data_chunk(sequence_number, &data));
+                sequence_number += 1;
+            }
+        }
+        chunks.push(Chunk::new(ChunkType::from_str("IEND")?, vec![]));
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// Total play time of one loop in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|f| f.control.delay()).sum()
+    }
+}
+impl Display for Animation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} frames, {} plays, {:.3}s per loop{}",
+            self.control.num_frames,
+            match self.control.num_plays {
+                0 => "infinite".to_string(),
+                n => n.to_string(),
+            },
+            self.duration(),
+            if self.default_image_is_frame {
+                ""
+            } else {
+                ", default image not part of animation"
+            }
+        )?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let c = &frame.control;
+            write!(
+                f,
+                "\n{:4}: {}x{} at ({}, {}) delay {}/{} ({:.3}s) dispose {} blend {} ({} bytes)",
+                i,
+                c.width,
+                c.height,
+                c.x_offset,
+                c.y_offset,
+                c.delay_num,
+                c.delay_den,
+                c.delay(),
+                c.dispose_op,
+                c.blend_op,
+                frame.data.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Composites `src` over `dst` with straight (non premultiplied) alpha.
+fn over(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    match (src[3], dst[3]) {
+        (255, _) | (_, 0) => src,
+        (0, _) => dst,
+        (sa, da) => {
+            let (sa, da) = (sa as u32, da as u32);
+            let out_a = sa * 255 + da * (255 - sa);
+            let mut out = [0; 4];
+            for c in 0..3 {
+                out[c] =
+                    ((src[c] as u32 * sa * 255 + dst[c] as u32 * da * (255 - sa)) / out_a) as u8;
+            }
+            out[3] = (out_a / 255) as u8;
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn fctl(sequence_number: u32, width: u32, x_offset: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height: 4,
+            x_offset,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::Background,
+            blend_op: BlendOp::Over,
+        }
+    }
+
+    fn testing_apng() -> Png {
+        Png::from_chunks(vec![
+            Ihdr::new(4, 4, 8, ColorType::Rgba).unwrap().to_chunk(),
+            AnimationControl {
+                num_frames: 2,
+                num_plays: 0,
+            }
+            .to_chunk(),
+            fctl(0, 4, 0).to_chunk(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1, 2]),
+            fctl(1, 2, 2).to_chunk(),
+            frame_data_chunk(2, &[3]),
+            frame_data_chunk(3, &[4]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_frame_control_roundtrip() {
+        let control = fctl(5, 3, 1);
+        assert_eq!(
+            FrameControl::try_from(&control.to_chunk()).unwrap(),
+            control
+        );
+        assert!((control.delay() - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_animation_from_png() {
+        let animation = Animation::from_png(&testing_apng()).unwrap().unwrap();
+        assert_eq!(animation.frames.len(), 2);
+        assert!(animation.default_image_is_frame);
+        assert_eq!(animation.frames[0].data, vec![1, 2]);
+        assert_eq!(animation.frames[1].data, vec![3, 4]);
+        assert_eq!(animation.frames[1].control.x_offset, 2);
+        assert!((animation.duration() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_not_animated() {
+        let png = Png::from_chunks(vec![Ihdr::new(1, 1, 8, ColorType::Rgb).unwrap().to_chunk()]);
+        assert!(Animation::from_png(&png).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sequence_gap() {
+        let mut chunks = testing_apng().chunks().to_vec();
+        chunks[6] = frame_data_chunk(4, &[4]);
+        assert!(Animation::from_png(&Png::from_chunks(chunks)).is_err());
+    }
+
+    #[test]
+    fn test_frame_out_of_bounds() {
+        let mut chunks = testing_apng().chunks().to_vec();
+        chunks[4] = fctl(1, 3, 2).to_chunk();
+        assert!(Animation::from_png(&Png::from_chunks(chunks)).is_err());
+    }
+
+    fn solid(width: u32, height: u32, rgba: [u8; 4]) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, rgba);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_build_and_render_roundtrip() {
+        let frames = vec![
+            solid(3, 2, [255, 0, 0, 255]),
+            solid(3, 2, [0, 255, 0, 128]),
+            solid(3, 2, [0, 0, 255, 255]),
+        ];
+        let png = Animation::build(&frames, &[100, 250], 2).unwrap();
+        let animation = png.animation().unwrap().unwrap();
+        assert_eq!(animation.frames.len(), 3);
+        assert_eq!(animation.control.num_plays, 2);
+        assert!((animation.frames[1].control.delay() - 0.25).abs() < 1e-9);
+        assert!((animation.frames[2].control.delay() - 0.1).abs() < 1e-9);
+        assert_eq!(animation.render(&png).unwrap(), frames);
+    }
+
+    #[test]
+    fn test_build_size_mismatch() {
+        let frames = vec![solid(3, 2, [0; 4]), solid(2, 2, [0; 4])];
+        assert!(Animation::build(&frames, &[], 0).is_err());
+        assert!(Animation::build(&[], &[], 0).is_err());
+    }
+
+    #[test]
+    fn test_render_blend_and_dispose() {
+        let background = solid(2, 2, [255, 0, 0, 255]);
+        let png = Animation::build(&[background.clone(), solid(2, 2, [0; 4])], &[], 0).unwrap();
+        // Second frame: a 1x1 half transparent blue pixel blended over the
+        // canvas and then restored by its dispose op.
+        let mut chunks = png.chunks().to_vec();
+        let overlay = solid(1, 1, [0, 0, 255, 128]).into_raw();
+        let data = overlay
+            .encode(crate::pixels::FilterStrategy::Adaptive, 6)
+            .unwrap();
+        let idx = chunks
+            .iter()
+            .rposition(|c| c.chunk_type().to_string() == "fcTL")
+            .unwrap();
+        chunks[idx] = FrameControl {
+            width: 1,
+            height: 1,
+            x_offset: 1,
+            y_offset: 1,
+            dispose_op: DisposeOp::Previous,
+            blend_op: BlendOp::Over,
+            ..FrameControl::try_from(&chunks[idx]).unwrap()
+        }
+        .to_chunk();
+        chunks[idx + 1] = frame_data_chunk(2, &data);
+        let png = Png::from_chunks(chunks);
+        let rendered = png.animation().unwrap().unwrap().render(&png).unwrap();
+        assert_eq!(rendered[0], background);
+        assert_eq!(rendered[1].pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(rendered[1].pixel(1, 1), [127, 0, 128, 255]);
+    }
+
+    #[test]
+    fn test_frame_count_mismatch() {
+        let mut chunks = testing_apng().chunks().to_vec();
+        chunks[1] = AnimationControl {
+            num_frames: 3,
+            num_plays: 0,
+        }
+        .to_chunk();
+        assert!(Animation::from_png(&Png::from_chunks(chunks)).is_err());
+    }
+}
@@ -1,5 +1,14 @@
+use crate::atomic::WriteOptions;
+use crate::batch::{self, BatchErr};
 use crate::chunk_type::ChunkType;
-use clap::{App, AppSettings, Arg, SubCommand};
+use crate::color::{Chromaticities, RenderingIntent};
+use crate::copy::CopyOptions;
+use crate::optimize::OptimizeOptions;
+use crate::palette::PaletteEntry;
+use crate::payload::FrameSpread;
+use crate::png::UnsafeChunkPolicy;
+use crate::strip::StripOptions;
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
 use std::{fmt::Display, path::Path, str::FromStr};
 #[derive(Debug)]
 pub enum ArgsParseErr {
@@ -19,6 +28,36 @@ pub enum ArgsKind {
     Decode(DecodeArgs),
     Remove(RemoveArgs),
     Print(PrintArgs),
+    Color(ColorArgs),
+    Exif(ExifArgs),
+    Palette(PaletteArgs),
+    Apng(ApngArgs),
+    Dump(DumpArgs),
+    Repair(RepairArgs),
+    Strip(StripArgs),
+    Optimize(OptimizeArgs),
+    Diff(DiffArgs),
+    CopyChunks(CopyChunksArgs),
+    #[cfg(feature = "serde")]
+    Export(ExportArgs),
+    #[cfg(feature = "serde")]
+    Import(ImportArgs),
+}
+/// What to run: one command, or one per file when the input paths name
+/// several files.
+pub enum Invocation {
+    Single(ArgsKind),
+    Batch(Vec<(Box<Path>, ArgsKind)>),
+}
+impl ArgsKind {
+    /// Exit status when the command fails. `diff` follows diff(1) and
+    /// keeps 1 to mean the files differ.
+    pub fn error_status(&self) -> i32 {
+        match self {
+            ArgsKind::Diff(_) => 2,
+            _ => 1,
+        }
+    }
 }
 fn is_chunk_type_valid(chunk_type: String) -> Result<(), String> {
     match ChunkType::from_str(&chunk_type) {
@@ -33,6 +72,10 @@ pub struct EncodeArgs {
     pub chunk_type: ChunkType,
     pub message: String,
     pub output_path: Box<Path>,
+    /// Spreads the message across the frames of an animated png.
+    pub spread: Option<FrameSpread>,
+    /// Splits the message over chunks of at most this many data bytes.
+    pub max_chunk_size: Option<usize>,
 }
 
 pub struct DecodeArgs {
@@ -43,10 +86,143 @@ pub struct DecodeArgs {
 pub struct RemoveArgs {
     pub file_path: Box<Path>,
     pub chunk_type: ChunkType,
+    /// Removes in place when absent.
+    pub output_path: Option<Box<Path>>,
 }
 
 pub struct PrintArgs {
     pub file_path: Box<Path>,
+    /// Recovers what it can from a damaged file instead of failing.
+    pub lenient: bool,
+}
+
+pub enum ColorAction {
+    Show,
+    Set {
+        gamma: Option<f64>,
+        chromaticities: Option<Chromaticities>,
+        srgb: Option<RenderingIntent>,
+    },
+    Strip,
+    ExtractIcc {
+        output_path: Box<Path>,
+    },
+    EmbedIcc {
+        profile_path: Box<Path>,
+        name: String,
+    },
+}
+
+pub struct ColorArgs {
+    pub file_path: Box<Path>,
+    pub action: ColorAction,
+}
+
+pub enum ExifAction {
+    Show,
+    Strip { gps_only: bool },
+    SetTag { tag: String, value: String },
+}
+
+pub struct ExifArgs {
+    pub file_path: Box<Path>,
+    pub action: ExifAction,
+}
+
+pub enum PaletteFormat {
+    Gpl,
+    Json,
+}
+
+pub enum PaletteAction {
+    Show,
+    SetEntry {
+        index: usize,
+        entry: PaletteEntry,
+    },
+    Export {
+        output_path: Box<Path>,
+        format: PaletteFormat,
+    },
+}
+
+pub struct PaletteArgs {
+    pub file_path: Box<Path>,
+    pub action: PaletteAction,
+}
+
+pub enum ApngAction {
+    Info,
+    Split {
+        output_dir: Box<Path>,
+    },
+    /// Builds `file_path` from the given frame files.
+    Build {
+        frame_paths: Vec<Box<Path>>,
+        delays: Vec<u16>,
+        num_plays: u32,
+    },
+}
+
+pub struct ApngArgs {
+    pub file_path: Box<Path>,
+    pub action: ApngAction,
+}
+
+pub struct DumpArgs {
+    pub file_path: Box<Path>,
+    pub chunk_type: Option<String>,
+    pub index: Option<usize>,
+    /// Data bytes shown per chunk, `None` for all of them.
+    pub max_bytes: Option<usize>,
+}
+
+pub struct RepairArgs {
+    pub file_path: Box<Path>,
+    /// Repairs in place when absent.
+    pub output_path: Option<Box<Path>>,
+}
+
+pub struct StripArgs {
+    pub file_path: Box<Path>,
+    pub options: StripOptions,
+    /// Strips in place when absent.
+    pub output_path: Option<Box<Path>>,
+}
+
+pub struct OptimizeArgs {
+    pub file_path: Box<Path>,
+    pub options: OptimizeOptions,
+    /// Optimizes in place when absent.
+    pub output_path: Option<Box<Path>>,
+}
+
+pub struct DiffArgs {
+    pub old_path: Box<Path>,
+    pub new_path: Box<Path>,
+    /// Also compares the decoded pixels.
+    pub pixels: bool,
+}
+
+pub struct CopyChunksArgs {
+    pub from_path: Box<Path>,
+    pub to_path: Box<Path>,
+    pub options: CopyOptions,
+    /// Writes into `to_path` when absent.
+    pub output_path: Option<Box<Path>>,
+}
+
+#[cfg(feature = "serde")]
+pub struct ExportArgs {
+    pub file_path: Box<Path>,
+    /// Writes to stdout when absent.
+    pub output_path: Option<Box<Path>>,
+}
+
+#[cfg(feature = "serde")]
+pub struct ImportArgs {
+    pub json_path: Box<Path>,
+    pub output_path: Box<Path>,
 }
 impl EncodeArgs {
     pub fn new(
@@ -54,12 +230,16 @@ impl EncodeArgs {
         chunk_type: &str,
         message: &str,
         output_path: &str,
+        spread: Option<&str>,
+        max_chunk_size: Option<&str>,
     ) -> crate::Result<Self> {
         Ok(Self {
             file_path: Box::from(Path::new(file_path)),
             chunk_type: ChunkType::from_str(chunk_type)?,
             message: message.to_string(),
             output_path: Box::from(Path::new(output_path)),
+            spread: spread.map(FrameSpread::from_str).transpose()?,
+            max_chunk_size: max_chunk_size.map(usize::from_str).transpose()?,
         })
     }
 }
@@ -72,31 +252,268 @@ impl DecodeArgs {
     }
 }
 impl RemoveArgs {
-    pub fn new(file_path: &str, chunk_type: &str) -> crate::Result<Self> {
+    pub fn new(
+        file_path: &str,
+        chunk_type: &str,
+        output_path: Option<&str>,
+    ) -> crate::Result<Self> {
         Ok(Self {
             file_path: Box::from(Path::new(file_path)),
             chunk_type: ChunkType::from_str(chunk_type)?,
+            output_path: output_path.map(|p| Box::from(Path::new(p))),
         })
     }
 }
 impl PrintArgs {
-    pub fn new(file_path: &str) -> crate::Result<Self> {
+    pub fn new(file_path: &str, lenient: bool) -> crate::Result<Self> {
         Ok(Self {
             file_path: Box::from(Path::new(file_path)),
+            lenient,
         })
     }
 }
-pub struct Config {}
+impl ColorArgs {
+    pub fn new(file_path: &str, action: ColorAction) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            action,
+        })
+    }
+}
+impl ExifArgs {
+    pub fn new(file_path: &str, action: ExifAction) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            action,
+        })
+    }
+}
+impl PaletteArgs {
+    pub fn new(file_path: &str, action: PaletteAction) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            action,
+        })
+    }
+}
+impl ApngArgs {
+    pub fn new(file_path: &str, action: ApngAction) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            action,
+        })
+    }
+}
+impl DumpArgs {
+    pub fn new(
+        file_path: &str,
+        chunk_type: Option<&str>,
+        index: Option<&str>,
+        max_bytes: &str,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            chunk_type: chunk_type.map(String::from),
+            index: index.map(usize::from_str).transpose()?,
+            max_bytes: Some(usize::from_str(max_bytes)?).filter(|&n| n > 0),
+        })
+    }
+}
+impl RepairArgs {
+    pub fn new(file_path: &str, output_path: Option<&str>) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            output_path: output_path.map(|p| Box::from(Path::new(p))),
+        })
+    }
+}
+impl StripArgs {
+    pub fn new(
+        file_path: &str,
+        options: StripOptions,
+        output_path: Option<&str>,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            options,
+            output_path: output_path.map(|p| Box::from(Path::new(p))),
+        })
+    }
+}
+impl OptimizeArgs {
+    pub fn new(
+        file_path: &str,
+        filters: &str,
+        levels: &str,
+        idat_size: Option<&str>,
+        reduce: bool,
+        output_path: Option<&str>,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            options: OptimizeOptions {
+                strategies: filters
+                    .split(',')
+                    .map(|f| f.trim().parse())
+                    .collect::<crate::Result<_>>()?,
+                levels: levels
+                    .split(',')
+                    .map(|l| l.trim().parse())
+                    .collect::<std::result::Result<_, _>>()?,
+                idat_size: idat_size.map(usize::from_str).transpose()?,
+                reduce,
+            },
+            output_path: output_path.map(|p| Box::from(Path::new(p))),
+        })
+    }
+}
+impl DiffArgs {
+    pub fn new(old_path: &str, new_path: &str, pixels: bool) -> crate::Result<Self> {
+        Ok(Self {
+            old_path: Box::from(Path::new(old_path)),
+            new_path: Box::from(Path::new(new_path)),
+            pixels,
+        })
+    }
+}
+impl CopyChunksArgs {
+    pub fn new(
+        from_path: &str,
+        to_path: &str,
+        types: Option<&str>,
+        force: bool,
+        output_path: Option<&str>,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            from_path: Box::from(Path::new(from_path)),
+            to_path: Box::from(Path::new(to_path)),
+            options: CopyOptions {
+                types: match types {
+                    Some(types) => types
+                        .split(',')
+                        .map(|t| ChunkType::from_str(t.trim()))
+                        .collect::<crate::Result<_>>()?,
+                    None => Vec::new(),
+                },
+                force,
+            },
+            output_path: output_path.map(|p| Box::from(Path::new(p))),
+        })
+    }
+}
+#[cfg(feature = "serde")]
+impl ExportArgs {
+    pub fn new(file_path: &str, output_path: Option<&str>) -> crate::Result<Self> {
+        Ok(Self {
+            file_path: Box::from(Path::new(file_path)),
+            output_path: output_path.map(|p| Box::from(Path::new(p))),
+        })
+    }
+}
+#[cfg(feature = "serde")]
+impl ImportArgs {
+    pub fn new(json_path: &str, output_path: &str) -> crate::Result<Self> {
+        Ok(Self {
+            json_path: Box::from(Path::new(json_path)),
+            output_path: Box::from(Path::new(output_path)),
+        })
+    }
+}
+fn file_path_arg() -> Arg<'static, 'static> {
+    Arg::with_name("file_path")
+        .required(true)
+        .help("Path of .png file, or a directory or glob pattern to process every match")
+        .short("f")
+        .index(1)
+}
+/// [`file_path_arg`] for subcommands with no other positional arguments,
+/// which take any number of paths.
+fn file_paths_arg() -> Arg<'static, 'static> {
+    file_path_arg()
+        .multiple(true)
+        .help("Paths of .png files, directories or glob patterns")
+}
+/// Options shared by every subcommand.
+pub struct Config {
+    /// Print machine readable JSON instead of text.
+    pub json: bool,
+    /// What to do with unsafe-to-copy chunks once critical chunks change.
+    pub unsafe_chunks: UnsafeChunkPolicy,
+    /// Files processed at once in a batch.
+    pub jobs: usize,
+    /// Reports what would be written without writing anything.
+    pub dry_run: bool,
+    /// How files are replaced.
+    pub write: WriteOptions,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
+}
 impl Config {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            json: false,
+            unsafe_chunks: UnsafeChunkPolicy::default(),
+            jobs: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            dry_run: false,
+            write: WriteOptions::default(),
+        }
     }
-    pub fn parse_args() -> crate::Result<ArgsKind> {
-        let matches = App::new("PNGme")
+    pub fn parse_args() -> crate::Result<(Config, Invocation)> {
+        let app = App::new("PNGme")
             .version("1.0")
             .author("plaxi0s")
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .about("RRCU {Random Rust Coding Urges}")
+            .arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .global(true)
+                    .help("Prints a single JSON object instead of text"),
+            )
+            .arg(
+                Arg::with_name("unsafe_chunks")
+                    .long("unsafe-chunks")
+                    .global(true)
+                    .takes_value(true)
+                    .possible_values(&["drop", "keep"])
+                    .default_value("drop")
+                    .help("Drops or keeps unknown unsafe-to-copy chunks when critical chunks change"),
+            )
+            .arg(
+                Arg::with_name("jobs")
+                    .short("j")
+                    .long("jobs")
+                    .global(true)
+                    .takes_value(true)
+                    .validator(|jobs| match jobs.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("Jobs must be a positive number")),
+                    })
+                    .help("Files processed in parallel when given several, defaults to the number of cpus"),
+            )
+            .arg(
+                Arg::with_name("dry_run")
+                    .long("dry-run")
+                    .global(true)
+                    .help("Shows what would change without writing any file"),
+            )
+            .arg(
+                Arg::with_name("backup")
+                    .long("backup")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("SUFFIX")
+                    .help("Keeps each replaced file under its name plus this suffix"),
+            )
+            .arg(
+                Arg::with_name("preserve_mtime")
+                    .long("preserve-mtime")
+                    .global(true)
+                    .help("Keeps the modification time of replaced files"),
+            )
             .subcommand(
                 SubCommand::with_name("encode")
                     .about("Encodes a message into a .png file")
@@ -128,6 +545,20 @@ impl Config {
                             .help("Output path for png")
                             .short("o")
                             .index(4),
+                    )
+                    .arg(
+                        Arg::with_name("across_frames")
+                            .long("across-frames")
+                            .takes_value(true)
+                            .possible_values(&["chunks", "pixels"])
+                            .help("Spreads the message over the frames of an animated png, in per frame chunks or in pixel data"),
+                    )
+                    .arg(
+                        Arg::with_name("max_chunk_size")
+                            .long("max-chunk-size")
+                            .takes_value(true)
+                            .conflicts_with("across_frames")
+                            .help("Splits the message over several chunks of at most this many bytes"),
                     ),
             )
             .subcommand(
@@ -166,37 +597,702 @@ impl Config {
                             .short("t")
                             .index(2)
                             .validator(is_chunk_type_valid),
+                    )
+                    .arg(
+                        Arg::with_name("output_path")
+                            .short("o")
+                            .long("output")
+                            .takes_value(true)
+                            .help("Writes the result here instead of in place"),
                     ),
             )
             .subcommand(
                 SubCommand::with_name("print")
                     .about("Print a .png file")
+                    .arg(file_paths_arg())
                     .arg(
-                        Arg::with_name("file_path")
+                        Arg::with_name("lenient")
+                            .long("lenient")
+                            .help("Recovers what it can from a damaged file and lists the damage"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("color")
+                    .about("Inspects and edits color management chunks")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("show")
+                            .about("Shows gAMA, cHRM, sRGB and iCCP chunks")
+                            .arg(file_paths_arg()),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("set")
+                            .about("Sets gAMA, cHRM or sRGB chunks")
+                            .arg(file_paths_arg())
+                            .arg(
+                                Arg::with_name("gamma")
+                                    .long("gamma")
+                                    .takes_value(true)
+                                    .help("Image gamma, e.g. 0.45455"),
+                            )
+                            .arg(
+                                Arg::with_name("chrm")
+                                    .long("chrm")
+                                    .takes_value(true)
+                                    .help("Chromaticities as wx,wy,rx,ry,gx,gy,bx,by"),
+                            )
+                            .arg(Arg::with_name("srgb").long("srgb").takes_value(true).help(
+                                "Rendering intent: perceptual, relative, saturation or absolute",
+                            )),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("strip")
+                            .about("Removes all color management chunks")
+                            .arg(file_paths_arg()),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("extract-icc")
+                            .about("Writes the embedded ICC profile to a file")
+                            .arg(file_path_arg())
+                            .arg(
+                                Arg::with_name("output_path")
+                                    .required(true)
+                                    .help("Output path for the ICC profile")
+                                    .short("o")
+                                    .index(2),
+                            ),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("embed-icc")
+                            .about("Embeds an ICC profile file as an iCCP chunk")
+                            .arg(file_path_arg())
+                            .arg(
+                                Arg::with_name("profile_path")
+                                    .required(true)
+                                    .help("Path of the ICC profile")
+                                    .short("p")
+                                    .index(2),
+                            )
+                            .arg(
+                                Arg::with_name("name")
+                                    .long("name")
+                                    .takes_value(true)
+                                    .default_value("ICC profile")
+                                    .help("Profile name stored in the iCCP chunk"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("exif")
+                    .about("Inspects and edits EXIF metadata in the eXIf chunk")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("show")
+                            .about("Shows all EXIF tags")
+                            .arg(file_paths_arg()),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("strip")
+                            .about("Removes the eXIf chunk")
+                            .arg(file_paths_arg())
+                            .arg(
+                                Arg::with_name("gps_only")
+                                    .long("gps-only")
+                                    .help("Only remove GPS tags, keeping the rest"),
+                            ),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("set-tag")
+                            .about("Sets an EXIF tag, creating the eXIf chunk if needed")
+                            .arg(file_path_arg())
+                            .arg(
+                                Arg::with_name("tag")
+                                    .required(true)
+                                    .help("Tag name, e.g. Orientation or Make")
+                                    .index(2),
+                            )
+                            .arg(
+                                Arg::with_name("value")
+                                    .required(true)
+                                    .help("Tag value; rationals as num/den, lists comma separated")
+                                    .index(3),
+                            ),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("palette")
+                    .about("Inspects and edits the PLTE and tRNS chunks of indexed images")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("show")
+                            .about("Shows every palette entry with its alpha")
+                            .arg(file_paths_arg()),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("set-entry")
+                            .about("Sets a palette entry")
+                            .arg(file_path_arg())
+                            .arg(
+                                Arg::with_name("index")
+                                    .required(true)
+                                    .help("Palette index")
+                                    .index(2),
+                            )
+                            .arg(
+                                Arg::with_name("color")
+                                    .required(true)
+                                    .help("Color as #rrggbb or #rrggbbaa")
+                                    .index(3),
+                            ),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("export")
+                            .about("Exports the palette as a GIMP or JSON palette file")
+                            .arg(file_path_arg())
+                            .arg(
+                                Arg::with_name("output_path")
+                                    .required(true)
+                                    .help("Output path for the palette")
+                                    .short("o")
+                                    .index(2),
+                            )
+                            .arg(
+                                Arg::with_name("format")
+                                    .long("format")
+                                    .takes_value(true)
+                                    .possible_values(&["gpl", "json"])
+                                    .help("Palette format, inferred from the extension by default"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("apng")
+                    .about("Inspects animated pngs")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("info")
+                            .about(
+                                "Lists every frame with its region, delay, dispose and blend ops",
+                            )
+                            .arg(file_paths_arg()),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("split")
+                            .about("Writes every composited frame as a separate .png file")
+                            .arg(file_path_arg())
+                            .arg(
+                                Arg::with_name("output_dir")
+                                    .required(true)
+                                    .help("Directory for the frame files")
+                                    .index(2),
+                            ),
+                    )
+                    .subcommand(
+                        SubCommand::with_name("build")
+                            .about("Builds an animated png from a sequence of .png files")
+                            .arg(
+                                Arg::with_name("frames")
+                                    .required(true)
+                                    .multiple(true)
+                                    .help("Frame .png files, all of the same size")
+                                    .index(1),
+                            )
+                            .arg(
+                                Arg::with_name("output_path")
+                                    .required(true)
+                                    .takes_value(true)
+                                    .help("Output path for the animated png")
+                                    .short("o"),
+                            )
+                            .arg(
+                                Arg::with_name("delay")
+                                    .long("delay")
+                                    .takes_value(true)
+                                    .default_value("100")
+                                    .help("Delay of every frame in milliseconds"),
+                            )
+                            .arg(
+                                Arg::with_name("delays")
+                                    .long("delays")
+                                    .takes_value(true)
+                                    .help("Comma separated per frame delays in milliseconds"),
+                            )
+                            .arg(
+                                Arg::with_name("plays")
+                                    .long("plays")
+                                    .takes_value(true)
+                                    .default_value("0")
+                                    .help("Number of loops, 0 for infinite"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("dump")
+                    .about("Dumps every chunk with offsets, crcs, decoded fields and a hex dump")
+                    .arg(file_paths_arg())
+                    .arg(
+                        Arg::with_name("chunk_type")
+                            .long("chunk-type")
+                            .takes_value(true)
+                            .help("Only dumps chunks of this type"),
+                    )
+                    .arg(
+                        Arg::with_name("index")
+                            .long("index")
+                            .takes_value(true)
+                            .help("Only dumps the chunk at this index"),
+                    )
+                    .arg(
+                        Arg::with_name("max_bytes")
+                            .long("max-bytes")
+                            .takes_value(true)
+                            .default_value("256")
+                            .help("Data bytes shown per chunk, 0 for all"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("repair")
+                    .about("Recomputes wrong crcs and fixes wrong length fields")
+                    .arg(file_paths_arg())
+                    .arg(
+                        Arg::with_name("output_path")
+                            .short("o")
+                            .long("output")
+                            .takes_value(true)
+                            .help("Writes the repaired file here instead of in place"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("strip")
+                    .about("Removes ancillary chunks")
+                    .arg(file_paths_arg())
+                    .arg(
+                        Arg::with_name("all_ancillary")
+                            .long("all-ancillary")
+                            .help("Removes every ancillary chunk"),
+                    )
+                    .arg(
+                        Arg::with_name("keep")
+                            .long("keep")
+                            .takes_value(true)
+                            .validator(|types| {
+                                types
+                                    .split(',')
+                                    .try_for_each(|t| is_chunk_type_valid(t.trim().to_string()))
+                            })
+                            .help("Comma separated chunk types to keep, removing every other ancillary chunk"),
+                    )
+                    .arg(
+                        Arg::with_name("remove_private")
+                            .long("remove-private")
+                            .help("Removes private ancillary chunks"),
+                    )
+                    .arg(
+                        Arg::with_name("safe")
+                            .long("safe")
+                            .help("Only removes chunks marked safe to copy"),
+                    )
+                    .group(
+                        ArgGroup::with_name("mode")
+                            .args(&["all_ancillary", "keep", "remove_private", "safe"])
+                            .multiple(true)
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("output_path")
+                            .short("o")
+                            .long("output")
+                            .takes_value(true)
+                            .help("Writes the stripped file here instead of in place"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("optimize")
+                    .about("Recompresses the image data losslessly, keeping the smallest result")
+                    .arg(file_paths_arg())
+                    .arg(
+                        Arg::with_name("filters")
+                            .long("filters")
+                            .takes_value(true)
+                            .default_value("none,sub,up,average,paeth,adaptive")
+                            .help("Comma separated filter strategies to try"),
+                    )
+                    .arg(
+                        Arg::with_name("levels")
+                            .long("levels")
+                            .takes_value(true)
+                            .default_value("6,9")
+                            .help("Comma separated deflate levels to try, 0 to 9"),
+                    )
+                    .arg(
+                        Arg::with_name("idat_size")
+                            .long("idat-size")
+                            .takes_value(true)
+                            .help("Splits the image data over IDAT chunks of at most this many bytes"),
+                    )
+                    .arg(
+                        Arg::with_name("reduce")
+                            .long("reduce")
+                            .help("Also tries smaller lossless color types and bit depths"),
+                    )
+                    .arg(
+                        Arg::with_name("output_path")
+                            .short("o")
+                            .long("output")
+                            .takes_value(true)
+                            .help("Writes the optimized file here instead of in place"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("diff")
+                    .about("Compares the chunks of two .png files, exiting with 1 if they differ")
+                    .arg(
+                        Arg::with_name("old_path")
                             .required(true)
-                            .help("Path of .png file")
-                            .short("f")
+                            .help("Path of the original .png file")
                             .index(1),
+                    )
+                    .arg(
+                        Arg::with_name("new_path")
+                            .required(true)
+                            .help("Path of the changed .png file")
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::with_name("pixels")
+                            .long("pixels")
+                            .help("Also compares the decoded pixels"),
                     ),
             )
-            .get_matches();
+            .subcommand(
+                SubCommand::with_name("copy-chunks")
+                    .about("Copies ancillary chunks from one .png file into another")
+                    .arg(
+                        Arg::with_name("from_path")
+                            .long("from")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path of the .png file to copy chunks from"),
+                    )
+                    .arg(
+                        Arg::with_name("to_path")
+                            .long("to")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path of the .png file to copy chunks into"),
+                    )
+                    .arg(
+                        Arg::with_name("types")
+                            .long("types")
+                            .takes_value(true)
+                            .validator(|types| {
+                                types
+                                    .split(',')
+                                    .try_for_each(|t| is_chunk_type_valid(t.trim().to_string()))
+                            })
+                            .help("Comma separated chunk types to copy, every ancillary chunk if omitted"),
+                    )
+                    .arg(
+                        Arg::with_name("force")
+                            .long("force")
+                            .help("Also copies unsafe-to-copy chunks that may not fit the destination image"),
+                    )
+                    .arg(
+                        Arg::with_name("output_path")
+                            .short("o")
+                            .long("output")
+                            .takes_value(true)
+                            .help("Writes the result here instead of into the destination file"),
+                    ),
+            );
+        #[cfg(feature = "serde")]
+        let app = app
+            .subcommand(
+                SubCommand::with_name("export")
+                    .about("Exports every chunk of a .png file losslessly")
+                    .arg(file_path_arg())
+                    .arg(
+                        Arg::with_name("output_path")
+                            .help("Output path, stdout if omitted")
+                            .index(2),
+                    )
+                    .arg(
+                        Arg::with_name("format")
+                            .long("format")
+                            .takes_value(true)
+                            .possible_values(&["json"])
+                            .default_value("json")
+                            .help("Export format"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("import")
+                    .about("Rebuilds a .png file from an export")
+                    .arg(
+                        Arg::with_name("json_path")
+                            .required(true)
+                            .help("Path of the exported .json file")
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::with_name("output_path")
+                            .required(true)
+                            .help("Output path for png")
+                            .index(2),
+                    ),
+            );
+        let matches = app.get_matches();
+        let mut config = Config::new();
+        config.json = matches.is_present("json");
+        if matches.value_of("unsafe_chunks") == Some("keep") {
+            config.unsafe_chunks = UnsafeChunkPolicy::Keep;
+        }
+        if let Some(jobs) = matches.value_of("jobs") {
+            config.jobs = jobs.parse()?;
+        }
+        config.dry_run = matches.is_present("dry_run");
+        config.write = WriteOptions {
+            backup_suffix: matches.value_of("backup").map(String::from),
+            preserve_mtime: matches.is_present("preserve_mtime"),
+        };
+        Ok((config, Config::invocation(&matches)?))
+    }
+    /// The innermost subcommand's matches, which hold its arguments.
+    fn leaf<'a>(matches: &'a ArgMatches<'a>) -> &'a ArgMatches<'a> {
+        match matches.subcommand() {
+            (_, Some(m)) => Config::leaf(m),
+            _ => matches,
+        }
+    }
+    /// Expands the input paths into one command per file when there are
+    /// several paths or any is a directory or glob pattern.
+    fn invocation(matches: &ArgMatches<'_>) -> crate::Result<Invocation> {
+        let leaf = Config::leaf(matches);
+        let specs: Vec<&str> = leaf
+            .values_of("file_path")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+        if specs.len() < 2 && !specs.iter().any(|s| batch::is_pattern(s)) {
+            return Ok(Invocation::Single(Config::args_kind(matches, None)?));
+        }
+        if ["output_path", "output_dir"]
+            .iter()
+            .any(|a| leaf.is_present(a))
+        {
+            return Err(Box::new(BatchErr::SingleOutput));
+        }
+        let mut commands = Vec::new();
+        for spec in specs {
+            for file in batch::expand(spec)? {
+                let args = Config::args_kind(matches, Some(&file.to_string_lossy()))?;
+                commands.push((file.into_boxed_path(), args));
+            }
+        }
+        Ok(Invocation::Batch(commands))
+    }
+    /// Builds the command, for `file_path` instead of the given input path
+    /// when set.
+    fn args_kind(matches: &ArgMatches<'_>, file_path: Option<&str>) -> crate::Result<ArgsKind> {
+        let path = |m: &ArgMatches<'_>| -> String {
+            file_path
+                .or_else(|| m.value_of("file_path"))
+                .unwrap()
+                .to_string()
+        };
         match matches.subcommand() {
             ("encode", Some(m)) => Ok(ArgsKind::Encode(EncodeArgs::new(
-                m.value_of("file_path").unwrap(),
+                &path(m),
                 m.value_of("chunk_type").unwrap(),
                 m.value_of("message").unwrap(),
                 m.value_of("output_path").unwrap(),
+                m.value_of("across_frames"),
+                m.value_of("max_chunk_size"),
             )?)),
             ("decode", Some(m)) => Ok(ArgsKind::Decode(DecodeArgs::new(
-                m.value_of("file_path").unwrap(),
+                &path(m),
                 m.value_of("chunk_type").unwrap(),
             )?)),
             ("remove", Some(m)) => Ok(ArgsKind::Remove(RemoveArgs::new(
-                m.value_of("file_path").unwrap(),
+                &path(m),
                 m.value_of("chunk_type").unwrap(),
+                m.value_of("output_path"),
             )?)),
             ("print", Some(m)) => Ok(ArgsKind::Print(PrintArgs::new(
-                m.value_of("file_path").unwrap(),
+                &path(m),
+                m.is_present("lenient"),
+            )?)),
+            ("color", Some(m)) => {
+                let (action, m) = match m.subcommand() {
+                    ("show", Some(m)) => (ColorAction::Show, m),
+                    ("set", Some(m)) => (
+                        ColorAction::Set {
+                            gamma: m.value_of("gamma").map(str::parse).transpose()?,
+                            chromaticities: m.value_of("chrm").map(str::parse).transpose()?,
+                            srgb: m.value_of("srgb").map(str::parse).transpose()?,
+                        },
+                        m,
+                    ),
+                    ("strip", Some(m)) => (ColorAction::Strip, m),
+                    ("extract-icc", Some(m)) => (
+                        ColorAction::ExtractIcc {
+                            output_path: Box::from(Path::new(m.value_of("output_path").unwrap())),
+                        },
+                        m,
+                    ),
+                    ("embed-icc", Some(m)) => (
+                        ColorAction::EmbedIcc {
+                            profile_path: Box::from(Path::new(m.value_of("profile_path").unwrap())),
+                            name: m.value_of("name").unwrap().to_string(),
+                        },
+                        m,
+                    ),
+                    _ => return Err(Box::new(ArgsParseErr::UnknownArgument)),
+                };
+                Ok(ArgsKind::Color(ColorArgs::new(&path(m), action)?))
+            }
+            ("exif", Some(m)) => {
+                let (action, m) = match m.subcommand() {
+                    ("show", Some(m)) => (ExifAction::Show, m),
+                    ("strip", Some(m)) => (
+                        ExifAction::Strip {
+                            gps_only: m.is_present("gps_only"),
+                        },
+                        m,
+                    ),
+                    ("set-tag", Some(m)) => (
+                        ExifAction::SetTag {
+                            tag: m.value_of("tag").unwrap().to_string(),
+                            value: m.value_of("value").unwrap().to_string(),
+                        },
+                        m,
+                    ),
+                    _ => return Err(Box::new(ArgsParseErr::UnknownArgument)),
+                };
+                Ok(ArgsKind::Exif(ExifArgs::new(&path(m), action)?))
+            }
+            ("palette", Some(m)) => {
+                let (action, m) = match m.subcommand() {
+                    ("show", Some(m)) => (PaletteAction::Show, m),
+                    ("set-entry", Some(m)) => (
+                        PaletteAction::SetEntry {
+                            index: m.value_of("index").unwrap().parse()?,
+                            entry: m.value_of("color").unwrap().parse()?,
+                        },
+                        m,
+                    ),
+                    ("export", Some(m)) => {
+                        let output_path = m.value_of("output_path").unwrap();
+                        let format = match m.value_of("format") {
+                            Some("json") => PaletteFormat::Json,
+                            Some(_) => PaletteFormat::Gpl,
+                            None if output_path.ends_with(".json") => PaletteFormat::Json,
+                            None => PaletteFormat::Gpl,
+                        };
+                        (
+                            PaletteAction::Export {
+                                output_path: Box::from(Path::new(output_path)),
+                                format,
+                            },
+                            m,
+                        )
+                    }
+                    _ => return Err(Box::new(ArgsParseErr::UnknownArgument)),
+                };
+                Ok(ArgsKind::Palette(PaletteArgs::new(&path(m), action)?))
+            }
+            ("apng", Some(m)) => {
+                let (action, m) = match m.subcommand() {
+                    ("info", Some(m)) => (ApngAction::Info, m),
+                    ("split", Some(m)) => (
+                        ApngAction::Split {
+                            output_dir: Box::from(Path::new(m.value_of("output_dir").unwrap())),
+                        },
+                        m,
+                    ),
+                    ("build", Some(m)) => {
+                        let frame_paths: Vec<Box<Path>> = m
+                            .values_of("frames")
+                            .unwrap()
+                            .map(|p| Box::from(Path::new(p)))
+                            .collect();
+                        let delays = match m.value_of("delays") {
+                            Some(delays) => delays
+                                .split(',')
+                                .map(|d| d.trim().parse())
+                                .collect::<std::result::Result<Vec<u16>, _>>()?,
+                            None => vec![m.value_of("delay").unwrap().parse()?; frame_paths.len()],
+                        };
+                        return Ok(ArgsKind::Apng(ApngArgs::new(
+                            m.value_of("output_path").unwrap(),
+                            ApngAction::Build {
+                                frame_paths,
+                                delays,
+                                num_plays: m.value_of("plays").unwrap().parse()?,
+                            },
+                        )?));
+                    }
+                    _ => return Err(Box::new(ArgsParseErr::UnknownArgument)),
+                };
+                Ok(ArgsKind::Apng(ApngArgs::new(&path(m), action)?))
+            }
+            ("dump", Some(m)) => Ok(ArgsKind::Dump(DumpArgs::new(
+                &path(m),
+                m.value_of("chunk_type"),
+                m.value_of("index"),
+                m.value_of("max_bytes").unwrap(),
+            )?)),
+            ("repair", Some(m)) => Ok(ArgsKind::Repair(RepairArgs::new(
+                &path(m),
+                m.value_of("output_path"),
+            )?)),
+            ("strip", Some(m)) => {
+                let keep = match m.value_of("keep") {
+                    Some(types) => types
+                        .split(',')
+                        .map(|t| ChunkType::from_str(t.trim()))
+                        .collect::<crate::Result<Vec<_>>>()?,
+                    None => Vec::new(),
+                };
+                Ok(ArgsKind::Strip(StripArgs::new(
+                    &path(m),
+                    StripOptions {
+                        all_ancillary: m.is_present("all_ancillary"),
+                        keep,
+                        remove_private: m.is_present("remove_private"),
+                        safe: m.is_present("safe"),
+                    },
+                    m.value_of("output_path"),
+                )?))
+            }
+            ("optimize", Some(m)) => Ok(ArgsKind::Optimize(OptimizeArgs::new(
+                &path(m),
+                m.value_of("filters").unwrap(),
+                m.value_of("levels").unwrap(),
+                m.value_of("idat_size"),
+                m.is_present("reduce"),
+                m.value_of("output_path"),
+            )?)),
+            ("diff", Some(m)) => Ok(ArgsKind::Diff(DiffArgs::new(
+                m.value_of("old_path").unwrap(),
+                m.value_of("new_path").unwrap(),
+                m.is_present("pixels"),
+            )?)),
+            ("copy-chunks", Some(m)) => Ok(ArgsKind::CopyChunks(CopyChunksArgs::new(
+                m.value_of("from_path").unwrap(),
+                m.value_of("to_path").unwrap(),
+                m.value_of("types"),
+                m.is_present("force"),
+                m.value_of("output_path"),
+            )?)),
+            #[cfg(feature = "serde")]
+            ("export", Some(m)) => Ok(ArgsKind::Export(ExportArgs::new(
+                &path(m),
+                m.value_of("output_path"),
+            )?)),
+            #[cfg(feature = "serde")]
+            ("import", Some(m)) => Ok(ArgsKind::Import(ImportArgs::new(
+                m.value_of("json_path").unwrap(),
+                m.value_of("output_path").unwrap(),
             )?)),
             _ => Err(Box::new(ArgsParseErr::UnknownArgument)),
         }
@@ -0,0 +1,126 @@
+use crate::Result;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How [`write`] replaces an existing file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Keeps the replaced file next to it, under its name plus this suffix.
+    pub backup_suffix: Option<String>,
+    /// Gives the new file the modification time of the one it replaces.
+    pub preserve_mtime: bool,
+}
+
+/// Distinguishes temporary files of writes running at the same time.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn with_suffix(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(prefix);
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Writes `bytes` to `path` without ever leaving a partly written file
+/// there: the data goes to a temporary file in the same directory, which
+/// is synced and then renamed over `path`. A replaced file's permissions
+/// are kept, and its modification time and a backup copy as `options` say.
+pub fn write(path: &Path, bytes: &[u8], options: &WriteOptions) -> Result<()> {
+    write_with(path, options, |w| Ok(w.write_all(bytes)?))
+}
+
+/// Like [`write`], with the contents streamed by `contents` through a
+/// buffered writer instead of given as one slice.
+pub fn write_with<F>(path: &Path, options: &WriteOptions, contents: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let existing = fs::metadata(path).ok().filter(|m| m.is_file());
+    let temp = with_suffix(
+        path,
+        ".",
+        &format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+    let written = (|| -> Result<()> {
+        let f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        let mut w = BufWriter::new(f);
+        contents(&mut w)?;
+        let f = w.into_inner().map_err(|e| e.into_error())?;
+        if let Some(metadata) = &existing {
+            f.set_permissions(metadata.permissions())?;
+            if options.preserve_mtime {
+                f.set_modified(metadata.modified()?)?;
+            }
+        }
+        f.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    if let (Some(_), Some(suffix)) = (&existing, &options.backup_suffix) {
+        let backup = with_suffix(path, "", suffix);
+        let _ = fs::remove_file(&backup);
+        if fs::hard_link(path, &backup).is_err() {
+            fs::copy(path, &backup)?;
+        }
+    }
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(Box::new(e));
+    }
+    // Makes the rename itself durable; not every platform can open a
+    // directory, so failing here is not an error.
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_write_replaces_with_backup_and_mtime() {
+        let dir = std::env::temp_dir().join(format!("pngme-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.png");
+        fs::write(&path, b"old").unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let options = WriteOptions {
+            backup_suffix: Some(".bak".to_string()),
+            preserve_mtime: true,
+        };
+        write(&path, b"new", &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(dir.join("image.png.bak")).unwrap(), b"old");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+        // Only the file and its backup are left, no temporary files.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        write(&dir.join("new.png"), b"fresh", &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read(dir.join("new.png")).unwrap(), b"fresh");
+        assert!(write(&dir.join("missing/x.png"), b"", &options).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
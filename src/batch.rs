@@ -0,0 +1,143 @@
+use crate::Result;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+#[derive(Debug)]
+pub enum BatchErr {
+    NoMatch(String),
+    SingleOutput,
+}
+impl std::error::Error for BatchErr {}
+impl Display for BatchErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            BatchErr::NoMatch(spec) => write!(f, "No .png files found for {}", spec),
+            BatchErr::SingleOutput => write!(
+                f,
+                "A command writing to a given output path takes a single input file"
+            ),
+        }
+    }
+}
+
+/// Whether `spec` may name several files: a directory or a glob pattern.
+/// An existing file whose name looks like a pattern is just that file.
+pub fn is_pattern(spec: &str) -> bool {
+    let path = Path::new(spec);
+    path.is_dir() || !path.exists() && spec.contains(['*', '?', '['])
+}
+
+/// The files `spec` names: itself unless it [`is_pattern`], every `.png`
+/// file under a directory, recursively, or every file a glob pattern
+/// matches, in sorted order.
+pub fn expand(spec: &str) -> Result<Vec<PathBuf>> {
+    if !is_pattern(spec) {
+        return Ok(vec![PathBuf::from(spec)]);
+    }
+    let mut files = Vec::new();
+    if Path::new(spec).is_dir() {
+        png_files(Path::new(spec), &mut files)?;
+    } else {
+        for entry in glob::glob(spec)? {
+            let path = entry?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(Box::new(BatchErr::NoMatch(spec.to_string())));
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn png_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            png_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Applies `f` to every item on up to `jobs` threads, returning the results
+/// in the order of `items`.
+pub fn parallel_map<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let count = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..count).map(|_| None).collect::<Vec<Option<R>>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, count.max(1)) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let (index, item) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                let result = f(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every item is processed"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_parallel_map_keeps_order() {
+        let items: Vec<u32> = (0..100).collect();
+        let squares = parallel_map(items, 4, |i| i * i);
+        assert_eq!(squares, (0..100).map(|i| i * i).collect::<Vec<_>>());
+        assert!(parallel_map(Vec::<u32>::new(), 4, |i| i).is_empty());
+    }
+
+    #[test]
+    fn test_expand_directory_and_glob() {
+        let dir = std::env::temp_dir().join(format!("pngme-batch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.png", "a.PNG", "notes.txt", "nested/c.png"] {
+            std::fs::write(dir.join(name), &PNG_FILE[..]).unwrap();
+        }
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let spec = dir.to_string_lossy();
+        assert_eq!(
+            names(expand(&spec).unwrap()),
+            ["a.PNG", "b.png", "nested/c.png"]
+        );
+        let pattern = dir.join("*.png").to_string_lossy().into_owned();
+        assert_eq!(names(expand(&pattern).unwrap()), ["b.png"]);
+        let nothing = dir.join("*.gif").to_string_lossy().into_owned();
+        assert!(expand(&nothing).is_err());
+        let file = dir.join("notes.txt").to_string_lossy().into_owned();
+        assert_eq!(names(expand(&file).unwrap()), ["notes.txt"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
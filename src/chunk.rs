@@ -7,6 +7,7 @@ use crc32fast::Hasher;
 use std::{
     convert::{TryFrom, TryInto},
     fmt::{Display, Formatter},
+    io::{self, Write},
 };
 #[derive(Debug)]
 pub enum ChunkErr {
@@ -35,48 +36,37 @@ pub struct Chunk {
 impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
     fn try_from(chunks: &[u8]) -> Result<Self> {
-        if chunks.len() < 12 {
-            return Err(Box::new(ChunkErr::ChunksLengthLess));
-        }
-        let (expected_length, tail) = chunks.split_at(4);
-        let expected_length = u32::from_be_bytes(expected_length.try_into()?);
-        let length = (tail.len() - 8) as u32;
-        if expected_length != length {
-            return Err(Box::new(ChunkErr::ChunkLengthInvalid));
-        }
-        let (type_and_data, expected_crc) = tail.split_at(tail.len() - 4);
-        let mut hasher = Hasher::new();
-        hasher.update(type_and_data);
-        let crc = hasher.finalize();
-        let expected_crc = u32::from_be_bytes(expected_crc.try_into()?);
-        if expected_crc != crc {
+        Chunk::parse(chunks, true)
+    }
+}
+impl Chunk {
+    /// Parses a whole chunk, length and crc fields included. Without
+    /// `verify_crc` a mismatching crc is kept as stored instead of rejected,
+    /// and can be found again with [`Chunk::crc_ok`].
+    pub fn parse(chunks: &[u8], verify_crc: bool) -> Result<Self> {
+        let chunk = ChunkRef::parse(chunks)?;
+        if verify_crc && !chunk.crc_ok() {
             return Err(Box::new(ChunkErr::ChunkCRCInvalid));
         }
-        let (chunk_type_bytes, data) = type_and_data.split_at(4);
-        let chunk_type = ChunkType::try_from(TryInto::<[u8; 4]>::try_into(chunk_type_bytes)?)?;
-        Ok(Self {
-            chunk_type,
-            data: Box::from(data),
-            crc,
-        })
+        Ok(chunk.to_chunk())
+    }
+    /// A borrowed view of the chunk.
+    pub fn view(&self) -> ChunkRef<'_> {
+        ChunkRef {
+            chunk_type: self.chunk_type.clone(),
+            data: &self.data,
+            crc: self.crc,
+        }
     }
 }
 impl Chunk {
+    /// Largest chunk data length the spec allows.
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let mut hasher = Hasher::new();
-        hasher.update(
-            chunk_type
-                .bytes()
-                .iter()
-                .chain(data.iter())
-                .cloned()
-                .collect::<Vec<u8>>()
-                .as_slice(),
-        );
         Self {
+            crc: crc(&chunk_type, &data),
             chunk_type,
             data: data.into_boxed_slice(),
-            crc: hasher.finalize(),
         }
     }
     pub fn length(&self) -> u32 {
@@ -88,32 +78,120 @@ impl Chunk {
     pub fn data(&self) -> &[u8] {
         &self.data
     }
+    /// The crc as stored, which only differs from [`Chunk::computed_crc`]
+    /// for chunks parsed without crc verification.
     pub fn crc(&self) -> u32 {
         self.crc
     }
+    pub fn computed_crc(&self) -> u32 {
+        crc(&self.chunk_type, &self.data)
+    }
+    pub fn crc_ok(&self) -> bool {
+        self.crc == self.computed_crc()
+    }
+    /// The same chunk with its crc recomputed.
+    pub fn with_fixed_crc(self) -> Self {
+        Chunk::new(self.chunk_type, self.data.into_vec())
+    }
     pub fn data_as_string(&self) -> Result<String> {
-        match std::str::from_utf8(&self.data()) {
+        match std::str::from_utf8(self.data()) {
             Ok(s) => Ok(String::from(s)),
             Err(e) => Err(Box::new(e)),
         }
     }
+    /// Length of the whole chunk, length and crc fields included.
+    pub fn encoded_len(&self) -> usize {
+        self.data.len() + 12
+    }
+    /// Writes the whole chunk, field by field, without copying its data.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.length().to_be_bytes())?;
+        w.write_all(&self.chunk_type.bytes())?;
+        w.write_all(&self.data)?;
+        w.write_all(&self.crc.to_be_bytes())
+    }
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.length()
-            .to_be_bytes()
-            .iter()
-            .chain(self.chunk_type.bytes().iter())
-            .chain(self.data().iter())
-            .chain(self.crc.to_be_bytes().iter())
-            .cloned()
-            .collect::<Vec<_>>()
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec does not fail");
+        bytes
     }
 }
+
+/// The crc of a chunk's type and data, hashed in place.
+fn crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(&chunk_type.bytes());
+    hasher.update(data);
+    hasher.finalize()
+}
+/// A chunk borrowed from the buffer it was parsed from, such as a whole
+/// file read once or memory mapped. Its crc is kept as stored and only
+/// checked when asked, with [`ChunkRef::crc_ok`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
+impl<'a> ChunkRef<'a> {
+    /// Parses a whole chunk, length and crc fields included, without
+    /// checking the crc.
+    pub fn parse(chunks: &'a [u8]) -> Result<Self> {
+        if chunks.len() < 12 {
+            return Err(Box::new(ChunkErr::ChunksLengthLess));
+        }
+        let (expected_length, tail) = chunks.split_at(4);
+        let expected_length = u32::from_be_bytes(expected_length.try_into()?);
+        let length = (tail.len() - 8) as u32;
+        if expected_length != length {
+            return Err(Box::new(ChunkErr::ChunkLengthInvalid));
+        }
+        let (type_and_data, expected_crc) = tail.split_at(tail.len() - 4);
+        let (chunk_type_bytes, data) = type_and_data.split_at(4);
+        let chunk_type = ChunkType::try_from(TryInto::<[u8; 4]>::try_into(chunk_type_bytes)?)?;
+        Ok(Self {
+            chunk_type,
+            data,
+            crc: u32::from_be_bytes(expected_crc.try_into()?),
+        })
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    /// The data, borrowed for as long as the buffer rather than the view.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    /// The crc as stored.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    pub fn computed_crc(&self) -> u32 {
+        crc(&self.chunk_type, self.data)
+    }
+    pub fn crc_ok(&self) -> bool {
+        self.crc == self.computed_crc()
+    }
+    /// An owned copy, keeping the crc as stored.
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk {
+            chunk_type: self.chunk_type.clone(),
+            data: Box::from(self.data),
+            crc: self.crc,
+        }
+    }
+}
+
 impl Display for Chunk {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         write!(
             f,
             "{}",
-            std::str::from_utf8(&self.data()).map_err(|_| std::fmt::Error)?
+            std::str::from_utf8(self.data()).map_err(|_| std::fmt::Error)?
         )
     }
 }
@@ -165,6 +243,29 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_chunk_write_to() {
+        let chunk = testing_chunk();
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), chunk.encoded_len());
+        assert_eq!(Chunk::try_from(bytes.as_ref()).unwrap().crc(), chunk.crc());
+        assert_eq!(bytes, chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_parse_without_crc_check() {
+        let mut bytes = testing_chunk().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(Chunk::try_from(bytes.as_ref()).is_err());
+        let chunk = Chunk::parse(&bytes, false).unwrap();
+        assert!(!chunk.crc_ok());
+        assert_eq!(chunk.computed_crc(), 2882656334);
+        assert_eq!(chunk.as_bytes(), bytes);
+        assert!(chunk.with_fixed_crc().crc_ok());
+    }
+
     #[test]
     fn test_valid_chunk_from_bytes() {
         let data_length: u32 = 42;
@@ -0,0 +1,545 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::limits::Limits;
+use crate::png::Png;
+use crate::zlib;
+use crate::{Error, Result};
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum ColorErr {
+    WrongChunkType(String),
+    InvalidLength(&'static str, usize),
+    InvalidRenderingIntent(u8),
+    InvalidProfileName,
+    UnknownCompressionMethod(u8),
+    InvalidChromaticities(String),
+    SrgbAndIccpPresent,
+    InvalidGamma(f64),
+}
+impl std::error::Error for ColorErr {}
+impl Display for ColorErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ColorErr::WrongChunkType(t) => write!(f, "Unexpected chunk type {}", t),
+            ColorErr::InvalidLength(t, len) => write!(f, "Invalid {} chunk length {}", t, len),
+            ColorErr::InvalidRenderingIntent(i) => write!(f, "Invalid sRGB rendering intent {}", i),
+            ColorErr::InvalidProfileName => {
+                write!(f, "ICC profile name must be 1-79 printable Latin-1 chars")
+            }
+            ColorErr::UnknownCompressionMethod(m) => {
+                write!(f, "Unknown iCCP compression method {}", m)
+            }
+            ColorErr::InvalidChromaticities(s) => {
+                write!(
+                    f,
+                    "Expected 8 comma separated non-negative chromaticities, got {}",
+                    s
+                )
+            }
+            ColorErr::SrgbAndIccpPresent => {
+                write!(f, "sRGB and iCCP chunks must not both be present")
+            }
+            ColorErr::InvalidGamma(g) => write!(f, "Gamma must be a positive number, got {}", g),
+        }
+    }
+}
+
+/// PNG stores gamma and chromaticity values as integers scaled by 100000.
+const SCALE: f64 = 100000.0;
+
+fn check_type(chunk: &Chunk, expected: &str) -> Result<()> {
+    if chunk.chunk_type().to_string() != expected {
+        return Err(Box::new(ColorErr::WrongChunkType(
+            chunk.chunk_type().to_string(),
+        )));
+    }
+    Ok(())
+}
+
+/// `value` scaled, or `None` unless it is finite, not negative and fits
+/// the 2^31-1 that PNG allows in a four byte integer.
+fn scaled(value: f64) -> Option<u32> {
+    let raw = (value * SCALE).round();
+    (0.0..=i32::MAX as f64).contains(&raw).then_some(raw as u32)
+}
+
+/// Image gamma from a `gAMA` chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gamma(u32);
+
+impl Gamma {
+    /// Fails unless `gamma` is finite, positive and representable once scaled.
+    pub fn from_f64(gamma: f64) -> Result<Self> {
+        let raw = scaled(gamma)
+            .filter(|&raw| raw > 0)
+            .ok_or(ColorErr::InvalidGamma(gamma))?;
+        Ok(Self(raw))
+    }
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+    pub fn value(&self) -> f64 {
+        self.0 as f64 / SCALE
+    }
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("gAMA").unwrap(),
+            self.0.to_be_bytes().to_vec(),
+        )
+    }
+}
+impl TryFrom<&Chunk> for Gamma {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        check_type(chunk, "gAMA")?;
+        let bytes: [u8; 4] = chunk
+            .data()
+            .try_into()
+            .map_err(|_| ColorErr::InvalidLength("gAMA", chunk.data().len()))?;
+        Ok(Self(u32::from_be_bytes(bytes)))
+    }
+}
+impl Display for Gamma {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:.5}", self.value())
+    }
+}
+
+/// White point and primaries from a `cHRM` chunk, stored as raw scaled values
+/// in the chunk's order: white, red, green, blue (x then y).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chromaticities {
+    values: [u32; 8],
+}
+
+impl Chromaticities {
+    pub fn raw(&self) -> [u32; 8] {
+        self.values
+    }
+    pub fn white_point(&self) -> (f64, f64) {
+        self.pair(0)
+    }
+    pub fn red(&self) -> (f64, f64) {
+        self.pair(2)
+    }
+    pub fn green(&self) -> (f64, f64) {
+        self.pair(4)
+    }
+    pub fn blue(&self) -> (f64, f64) {
+        self.pair(6)
+    }
+    fn pair(&self, i: usize) -> (f64, f64) {
+        (
+            self.values[i] as f64 / SCALE,
+            self.values[i + 1] as f64 / SCALE,
+        )
+    }
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("cHRM").unwrap(),
+            self.values.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        )
+    }
+}
+impl TryFrom<&Chunk> for Chromaticities {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        check_type(chunk, "cHRM")?;
+        if chunk.data().len() != 32 {
+            return Err(Box::new(ColorErr::InvalidLength(
+                "cHRM",
+                chunk.data().len(),
+            )));
+        }
+        let mut values = [0; 8];
+        for (value, bytes) in values.iter_mut().zip(chunk.data().chunks_exact(4)) {
+            *value = u32::from_be_bytes(bytes.try_into()?);
+        }
+        Ok(Self { values })
+    }
+}
+impl FromStr for Chromaticities {
+    type Err = Error;
+    /// Parses `wx,wy,rx,ry,gx,gy,bx,by` as floating point values.
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| ColorErr::InvalidChromaticities(s.to_string()))?;
+        let parsed: [f64; 8] = parsed
+            .try_into()
+            .map_err(|_| ColorErr::InvalidChromaticities(s.to_string()))?;
+        let mut values = [0; 8];
+        for (value, p) in values.iter_mut().zip(parsed.iter()) {
+            *value = scaled(*p).ok_or_else(|| ColorErr::InvalidChromaticities(s.to_string()))?;
+        }
+        Ok(Self { values })
+    }
+}
+impl Display for Chromaticities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let fmt_pair = |(x, y): (f64, f64)| format!("({:.5}, {:.5})", x, y);
+        write!(
+            f,
+            "white {} red {} green {} blue {}",
+            fmt_pair(self.white_point()),
+            fmt_pair(self.red()),
+            fmt_pair(self.green()),
+            fmt_pair(self.blue())
+        )
+    }
+}
+
+/// Rendering intent from an `sRGB` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+impl RenderingIntent {
+    pub fn to_chunk(self) -> Chunk {
+        Chunk::new(ChunkType::from_str("sRGB").unwrap(), vec![u8::from(self)])
+    }
+}
+impl TryFrom<u8> for RenderingIntent {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(RenderingIntent::Perceptual),
+            1 => Ok(RenderingIntent::RelativeColorimetric),
+            2 => Ok(RenderingIntent::Saturation),
+            3 => Ok(RenderingIntent::AbsoluteColorimetric),
+            _ => Err(Box::new(ColorErr::InvalidRenderingIntent(value))),
+        }
+    }
+}
+impl From<RenderingIntent> for u8 {
+    fn from(intent: RenderingIntent) -> Self {
+        match intent {
+            RenderingIntent::Perceptual => 0,
+            RenderingIntent::RelativeColorimetric => 1,
+            RenderingIntent::Saturation => 2,
+            RenderingIntent::AbsoluteColorimetric => 3,
+        }
+    }
+}
+impl TryFrom<&Chunk> for RenderingIntent {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        check_type(chunk, "sRGB")?;
+        match chunk.data() {
+            [intent] => RenderingIntent::try_from(*intent),
+            data => Err(Box::new(ColorErr::InvalidLength("sRGB", data.len()))),
+        }
+    }
+}
+impl FromStr for RenderingIntent {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "perceptual" => Ok(RenderingIntent::Perceptual),
+            "relative" => Ok(RenderingIntent::RelativeColorimetric),
+            "saturation" => Ok(RenderingIntent::Saturation),
+            "absolute" => Ok(RenderingIntent::AbsoluteColorimetric),
+            _ => RenderingIntent::try_from(s.parse::<u8>()?),
+        }
+    }
+}
+impl Display for RenderingIntent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            RenderingIntent::Perceptual => write!(f, "perceptual"),
+            RenderingIntent::RelativeColorimetric => write!(f, "relative colorimetric"),
+            RenderingIntent::Saturation => write!(f, "saturation"),
+            RenderingIntent::AbsoluteColorimetric => write!(f, "absolute colorimetric"),
+        }
+    }
+}
+
+/// Embedded ICC profile from an `iCCP` chunk. The profile is kept
+/// uncompressed; it is deflated again when written back out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IccProfile {
+    name: String,
+    profile: Vec<u8>,
+}
+
+impl IccProfile {
+    pub fn new(name: &str, profile: Vec<u8>) -> Result<Self> {
+        if name.is_empty()
+            || name.chars().count() > 79
+            || !name
+                .chars()
+                .all(|c| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c))
+            || name.starts_with(' ')
+            || name.ends_with(' ')
+            || name.contains("  ")
+        {
+            return Err(Box::new(ColorErr::InvalidProfileName));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            profile,
+        })
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn profile(&self) -> &[u8] {
+        &self.profile
+    }
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.push(0);
+        data.push(0);
+        data.extend(zlib::deflate(&self.profile)?);
+        Ok(Chunk::new(ChunkType::from_str("iCCP")?, data))
+    }
+}
+impl TryFrom<&Chunk> for IccProfile {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        IccProfile::from_chunk_with(chunk, &Limits::default())
+    }
+}
+impl IccProfile {
+    /// Parses an `iCCP` chunk, inflating at most
+    /// `limits.max_decompressed_size` bytes of profile.
+    pub fn from_chunk_with(chunk: &Chunk, limits: &Limits) -> Result<Self> {
+        check_type(chunk, "iCCP")?;
+        let data = chunk.data();
+        let nul = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(ColorErr::InvalidProfileName)?;
+        if nul == 0 || nul > 79 {
+            return Err(Box::new(ColorErr::InvalidProfileName));
+        }
+        // Profile names are Latin-1, which maps one to one onto the first
+        // 256 code points.
+        let name = data[..nul].iter().map(|&b| b as char).collect();
+        let method = *data
+            .get(nul + 1)
+            .ok_or(ColorErr::InvalidLength("iCCP", data.len()))?;
+        if method != 0 {
+            return Err(Box::new(ColorErr::UnknownCompressionMethod(method)));
+        }
+        Ok(Self {
+            name,
+            profile: zlib::inflate(&data[nul + 2..], limits.max_decompressed_size)?,
+        })
+    }
+}
+
+/// The color management chunks found in a png.
+#[derive(Debug, Default)]
+pub struct ColorInfo {
+    pub gamma: Option<Gamma>,
+    pub chromaticities: Option<Chromaticities>,
+    pub srgb: Option<RenderingIntent>,
+    pub icc: Option<IccProfile>,
+}
+
+impl ColorInfo {
+    pub const CHUNK_TYPES: [&'static str; 4] = ["gAMA", "cHRM", "sRGB", "iCCP"];
+
+    pub fn from_png(png: &Png) -> Result<Self> {
+        Ok(Self {
+            gamma: png.chunk_by_type("gAMA").map(Gamma::try_from).transpose()?,
+            chromaticities: png
+                .chunk_by_type("cHRM")
+                .map(Chromaticities::try_from)
+                .transpose()?,
+            srgb: png
+                .chunk_by_type("sRGB")
+                .map(RenderingIntent::try_from)
+                .transpose()?,
+            icc: png
+                .chunk_by_type("iCCP")
+                .map(|c| IccProfile::from_chunk_with(c, png.limits()))
+                .transpose()?,
+        })
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.srgb.is_some() && self.icc.is_some() {
+            return Err(Box::new(ColorErr::SrgbAndIccpPresent));
+        }
+        Ok(())
+    }
+}
+impl Display for ColorInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match &self.gamma {
+            Some(gamma) => writeln!(f, "gAMA: {}", gamma)?,
+            None => writeln!(f, "gAMA: none")?,
+        }
+        match &self.chromaticities {
+            Some(chrm) => writeln!(f, "cHRM: {}", chrm)?,
+            None => writeln!(f, "cHRM: none")?,
+        }
+        match &self.srgb {
+            Some(intent) => writeln!(f, "sRGB: {}", intent)?,
+            None => writeln!(f, "sRGB: none")?,
+        }
+        match &self.icc {
+            Some(icc) => write!(f, "iCCP: {} ({} bytes)", icc.name(), icc.profile().len()),
+            None => write!(f, "iCCP: none"),
+        }
+    }
+}
+
+/// The chunk type that must not be present alongside `chunk_type`.
+pub fn conflicting_type(chunk_type: &str) -> Option<&'static str> {
+    match chunk_type {
+        "sRGB" => Some("iCCP"),
+        "iCCP" => Some("sRGB"),
+        _ => None,
+    }
+}
+
+/// Replaces any existing chunk of the same type with `chunk`. As `sRGB` and
+/// `iCCP` are mutually exclusive, setting one drops the other; the removed
+/// chunks are returned.
+pub fn set_color_chunk(png: &mut Png, chunk: Chunk) -> Vec<Chunk> {
+    let chunk_type = chunk.chunk_type().to_string();
+    let conflicting = conflicting_type(&chunk_type);
+    let mut removed = Vec::new();
+    for t in std::iter::once(chunk_type.as_str()).chain(conflicting) {
+        while let Ok(c) = png.remove_chunk(t) {
+            removed.push(c);
+        }
+    }
+    png.insert_before_image_data(chunk);
+    removed
+}
+
+/// Removes every color management chunk, returning the removed chunks.
+pub fn strip_color_chunks(png: &mut Png) -> Vec<Chunk> {
+    let mut removed = Vec::new();
+    for t in ColorInfo::CHUNK_TYPES.iter() {
+        while let Ok(c) = png.remove_chunk(t) {
+            removed.push(c);
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_gamma_roundtrip() {
+        let gamma = Gamma::from_f64(0.45455).unwrap();
+        assert_eq!(gamma.raw(), 45455);
+        assert_eq!(Gamma::try_from(&gamma.to_chunk()).unwrap(), gamma);
+    }
+
+    #[test]
+    fn test_invalid_gamma_value() {
+        for &gamma in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e12, 1e-9].iter() {
+            assert!(Gamma::from_f64(gamma).is_err(), "{}", gamma);
+        }
+    }
+
+    #[test]
+    fn test_invalid_gamma_length() {
+        let chunk = Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 1]);
+        assert!(Gamma::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_chromaticities_roundtrip() {
+        let chrm = Chromaticities::from_str("0.3127,0.329,0.64,0.33,0.3,0.6,0.15,0.06").unwrap();
+        assert_eq!(chrm.raw()[0], 31270);
+        assert_eq!(chrm.blue(), (0.15, 0.06));
+        assert_eq!(Chromaticities::try_from(&chrm.to_chunk()).unwrap(), chrm);
+        for bad in ["-0.1", "NaN", "inf", "1e9"].iter() {
+            let s = format!("0.3127,0.329,0.64,0.33,0.3,0.6,0.15,{}", bad);
+            assert!(Chromaticities::from_str(&s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_rendering_intent() {
+        let chunk = RenderingIntent::Saturation.to_chunk();
+        assert_eq!(chunk.data(), &[2]);
+        assert_eq!(
+            RenderingIntent::try_from(&chunk).unwrap(),
+            RenderingIntent::Saturation
+        );
+        assert!(RenderingIntent::try_from(4u8).is_err());
+        assert_eq!(
+            RenderingIntent::from_str("relative").unwrap(),
+            RenderingIntent::RelativeColorimetric
+        );
+    }
+
+    #[test]
+    fn test_icc_profile_roundtrip() {
+        let icc = IccProfile::new("Display P3", vec![7; 300]).unwrap();
+        let chunk = icc.to_chunk().unwrap();
+        assert_eq!(IccProfile::try_from(&chunk).unwrap(), icc);
+    }
+
+    #[test]
+    fn test_icc_profile_invalid_name() {
+        assert!(IccProfile::new("", vec![]).is_err());
+        assert!(IccProfile::new(" leading", vec![]).is_err());
+        assert!(IccProfile::new(&"a".repeat(80), vec![]).is_err());
+    }
+
+    #[test]
+    fn test_set_color_chunk_placement() {
+        let mut png = testing_png();
+        set_color_chunk(&mut png, Gamma::from_f64(0.45455).unwrap().to_chunk());
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "gAMA");
+        set_color_chunk(&mut png, Gamma::from_f64(1.0).unwrap().to_chunk());
+        let info = ColorInfo::from_png(&png).unwrap();
+        assert_eq!(info.gamma, Some(Gamma::from_f64(1.0).unwrap()));
+        assert_eq!(png.chunks().len(), 4);
+    }
+
+    #[test]
+    fn test_srgb_and_iccp_exclusive() {
+        let mut png = testing_png();
+        set_color_chunk(&mut png, RenderingIntent::Perceptual.to_chunk());
+        let icc = IccProfile::new("Custom", vec![1, 2, 3]).unwrap();
+        let removed = set_color_chunk(&mut png, icc.to_chunk().unwrap());
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].chunk_type().to_string(), "sRGB");
+        let info = ColorInfo::from_png(&png).unwrap();
+        assert!(info.validate().is_ok());
+        assert!(info.srgb.is_none());
+
+        png.insert_before_image_data(RenderingIntent::Perceptual.to_chunk());
+        assert!(ColorInfo::from_png(&png).unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn test_strip_color_chunks() {
+        let mut png = testing_png();
+        set_color_chunk(&mut png, Gamma::from_f64(0.45455).unwrap().to_chunk());
+        set_color_chunk(&mut png, RenderingIntent::Perceptual.to_chunk());
+        assert_eq!(strip_color_chunks(&mut png).len(), 2);
+        assert_eq!(png.chunks().len(), 3);
+    }
+}
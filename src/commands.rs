@@ -1,52 +1,1246 @@
-use crate::args::{self, ArgsKind, DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs};
-use crate::chunk::Chunk;
-use crate::png::Png;
+use crate::ancillary;
+use crate::apng::Animation;
+use crate::args::{
+    ApngAction, ApngArgs, ArgsKind, ColorAction, ColorArgs, Config, CopyChunksArgs, DecodeArgs,
+    DiffArgs, DumpArgs, EncodeArgs, ExifAction, ExifArgs, OptimizeArgs, PaletteAction, PaletteArgs,
+    PaletteFormat, PrintArgs, RemoveArgs, RepairArgs, StripArgs,
+};
+#[cfg(feature = "serde")]
+use crate::args::{ExportArgs, ImportArgs};
+use crate::atomic;
+use crate::batch;
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_type::ChunkType;
+use crate::color::{self, ColorInfo, Gamma, IccProfile};
+use crate::copy;
+use crate::diff::{self, Change, Detail, PixelDiff};
+use crate::dump;
+use crate::exif::{self, ByteOrder, Exif};
+use crate::json::Json;
+use crate::optimize;
+use crate::palette::Palette;
+use crate::payload::{self, FrameSpread, Manifest};
+use crate::pixels::RgbaImage;
+use crate::png::{chunk_table, ParseOptions, Png, PngErr, PngRef, UnsafeChunkPolicy};
+use crate::repair::{self, Fix};
+use crate::strip;
 use crate::Result;
 use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Read;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// What a command reports: lines of text for people, and the same facts as
+/// a JSON object for `--json`.
+struct Output {
+    text: Vec<String>,
+    json: Json,
+    /// Printed as is in both modes, for commands whose result is itself a
+    /// document.
+    document: Option<String>,
+    /// Exit status of the process.
+    status: i32,
+    /// Set once a file went to stdout, which the report then stays off.
+    report_to_stderr: bool,
+}
+
+impl Output {
+    fn new(command: &str) -> Self {
+        Self {
+            text: Vec::new(),
+            json: Json::object(vec![("command", command.into())]),
+            document: None,
+            status: 0,
+            report_to_stderr: false,
+        }
+    }
+    fn action(command: &str, action: &str) -> Self {
+        Output::new(command).field("action", action)
+    }
+    fn line(mut self, line: impl Into<String>) -> Self {
+        self.text.push(line.into());
+        self
+    }
+    fn field(mut self, key: &str, value: impl Into<Json>) -> Self {
+        self.json = self.json.with(key, value);
+        self
+    }
+}
+
+fn path_json(p: &Path) -> Json {
+    p.to_string_lossy().into_owned().into()
+}
+
+fn chunk_types(chunks: &[Chunk]) -> Json {
+    Json::array(chunks.iter().map(|c| c.chunk_type().to_string()))
+}
+
+/// Whether `path` is `-`, which stands for stdin or stdout.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if is_stdio(path) {
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+    } else {
+        File::open(path)?.read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// An input file's bytes for a read-only command, memory mapped rather
+/// than read with the `mmap` feature.
+enum Bytes {
+    Read(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for Bytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Bytes::Read(bytes) => bytes,
+            #[cfg(feature = "mmap")]
+            Bytes::Mapped(map) => map,
+        }
+    }
+}
+
+fn read_only_bytes(path: &Path) -> Result<Bytes> {
+    #[cfg(feature = "mmap")]
+    if !is_stdio(path) {
+        let file = File::open(path)?;
+        // SAFETY: the map is only read, while the command runs. It relies
+        // on no other process truncating or rewriting the file meanwhile;
+        // PNGme itself never does, since its writes replace files by rename.
+        return Ok(Bytes::Mapped(unsafe { memmap2::Mmap::map(&file)? }));
+    }
+    Ok(Bytes::Read(read_bytes(path)?))
+}
+
+fn read_png_with(path: &Path, options: &ParseOptions) -> Result<Png> {
+    if is_stdio(path) {
+        Png::read_from_with(std::io::stdin().lock(), options)
+    } else {
+        Png::from_file_with(path, options)
+    }
+}
+
+fn read_png(path: &Path) -> Result<Png> {
+    read_png_with(path, &ParseOptions::default())
+}
+
+/// Replaces `path` with what `contents` writes, as configured, or streams
+/// it to stdout for `-`, moving the report to stderr. Does nothing on a
+/// dry run.
+fn write_with<F>(config: &Config, path: &Path, mut output: Output, contents: F) -> Result<Output>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    if config.dry_run {
+        return Ok(output);
+    }
+    if is_stdio(path) {
+        let mut stdout = BufWriter::new(std::io::stdout().lock());
+        contents(&mut stdout)?;
+        stdout.flush()?;
+        output.report_to_stderr = true;
+    } else {
+        atomic::write_with(path, &config.write, contents)?;
+    }
+    Ok(output)
+}
+
+fn write_file(config: &Config, path: &Path, bytes: &[u8], output: Output) -> Result<Output> {
+    write_with(config, path, output, |w| Ok(w.write_all(bytes)?))
+}
+
+fn write_png(config: &Config, path: &Path, png: &Png, output: Output) -> Result<Output> {
+    write_with(config, path, output, |w| png.write_to(w))
+}
+
+/// How lines about a written file start, which on a dry run it is not.
+fn wrote(config: &Config) -> &'static str {
+    if config.dry_run {
+        "Would write"
+    } else {
+        "Wrote"
+    }
+}
+
+/// Writes an edited `png` to `path` under the configured unsafe chunk
+/// policy, reporting the unsafe-to-copy chunks its critical changes left
+/// stale.
+fn save(config: &Config, png: &mut Png, path: &Path, output: Output) -> Result<Output> {
+    png.set_unsafe_chunk_policy(config.unsafe_chunks);
+    let stale: Vec<Chunk> = png.stale_chunks().into_iter().cloned().collect();
+    let mut output = write_png(config, path, png, output)?;
+    for chunk in &stale {
+        output = output.line(match config.unsafe_chunks {
+            UnsafeChunkPolicy::Drop => format!(
+                "Dropped unsafe-to-copy chunk {} since critical chunks changed",
+                chunk.chunk_type()
+            ),
+            UnsafeChunkPolicy::Keep => format!(
+                "Warning: kept unsafe-to-copy chunk {} although critical chunks changed",
+                chunk.chunk_type()
+            ),
+        });
+    }
+    Ok(output.field("unsafe_chunks", chunk_types(&stale)))
+}
+
 pub struct Command {}
 impl Command {
-    pub fn run(args: ArgsKind) -> Result<()> {
-        match args {
-            ArgsKind::Encode(EncodeArgs {
+    /// Runs the command and prints what it reports, returning the exit
+    /// status.
+    pub fn run(config: &Config, args: ArgsKind) -> Result<i32> {
+        let output = Command::execute(config, args)?;
+        let mut report: Box<dyn Write> = if output.report_to_stderr {
+            Box::new(std::io::stderr())
+        } else {
+            Box::new(std::io::stdout())
+        };
+        if let Some(document) = output.document {
+            writeln!(report, "{}", document)?;
+        } else if config.json {
+            writeln!(report, "{}", output.json)?;
+        } else {
+            for line in output.text {
+                writeln!(report, "{}", line)?;
+            }
+        }
+        Ok(output.status)
+    }
+
+    /// Runs one command per file on up to `config.jobs` threads, then
+    /// prints each file's report followed by a summary. A failing file does
+    /// not stop the others; the exit status is 1 if any failed.
+    pub fn run_batch(config: &Config, commands: Vec<(Box<Path>, ArgsKind)>) -> i32 {
+        let results = batch::parallel_map(commands, config.jobs, |(path, args)| {
+            let result = Command::execute(config, args).map_err(|e| e.to_string());
+            (path, result)
+        });
+        let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+        let succeeded = results.len() - failed;
+        if config.json {
+            let files = results.into_iter().map(|(path, result)| match result {
+                Ok(Output { json, document, .. }) => Json::object(vec![
+                    ("file", path_json(&path)),
+                    ("ok", true.into()),
+                    (
+                        "result",
+                        match document {
+                            Some(document) => json.with("document", document),
+                            None => json,
+                        },
+                    ),
+                ]),
+                Err(e) => Json::object(vec![
+                    ("file", path_json(&path)),
+                    ("ok", false.into()),
+                    ("error", e.into()),
+                ]),
+            });
+            let summary = Output::new("batch")
+                .field("files", Json::array(files))
+                .field("succeeded", succeeded)
+                .field("failed", failed);
+            println!("{}", summary.json);
+        } else {
+            for (path, result) in results {
+                match result {
+                    Ok(output) => {
+                        println!("{}:", path.to_string_lossy());
+                        for line in output.document.into_iter().chain(output.text) {
+                            println!("    {}", line.replace('\n', "\n    "));
+                        }
+                    }
+                    Err(e) => eprintln!("Error: {}: {}", path.to_string_lossy(), e),
+                }
+            }
+            println!(
+                "{} files, {} succeeded, {} failed",
+                succeeded + failed,
+                succeeded,
+                failed
+            );
+        }
+        if failed > 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Runs the command, returning what it reports.
+    fn execute(config: &Config, args: ArgsKind) -> Result<Output> {
+        let output = Command::dispatch(config, args)?;
+        Ok(if config.dry_run {
+            output
+                .line("Dry run: no files were written")
+                .field("dry_run", true)
+        } else {
+            output
+        })
+    }
+
+    fn dispatch(config: &Config, args: ArgsKind) -> Result<Output> {
+        let output = match args {
+            ArgsKind::Encode(args) => Command::encode(config, args)?,
+            ArgsKind::Decode(args) => Command::decode(args)?,
+            ArgsKind::Remove(RemoveArgs {
                 file_path,
                 chunk_type,
-                message,
                 output_path,
             }) => {
-                let mut png = Png::from_file(&file_path)?;
-                png.append_chunk(Chunk::new(chunk_type, message.into_bytes()));
-                png.to_file(&output_path)
+                let mut png = read_png(&file_path)?;
+                let chunk_removed = png.remove_chunk(&chunk_type.to_string())?;
+                let output_path = output_path.unwrap_or_else(|| file_path.clone());
+                let output = Output::new("remove")
+                    .line(format!(
+                        "Removed chunk: {} from file {}",
+                        chunk_removed,
+                        file_path.to_str().unwrap() // TODO maybe error here
+                    ))
+                    .field("file", path_json(&file_path))
+                    .field("output", path_json(&output_path))
+                    .field("chunk_type", chunk_type.to_string())
+                    .field("length", chunk_removed.length());
+                save(config, &mut png, &output_path, output)?
             }
-            ArgsKind::Decode(DecodeArgs {
+            ArgsKind::Print(args) => Command::print(args)?,
+            ArgsKind::Color(args) => Command::color(config, args)?,
+            ArgsKind::Exif(args) => Command::exif(config, args)?,
+            ArgsKind::Palette(args) => Command::palette(config, args)?,
+            ArgsKind::Apng(args) => Command::apng(config, args)?,
+            ArgsKind::Dump(args) => Command::dump(args)?,
+            ArgsKind::Repair(args) => Command::repair(config, args)?,
+            ArgsKind::Strip(args) => Command::strip(config, args)?,
+            ArgsKind::Optimize(args) => Command::optimize(config, args)?,
+            ArgsKind::Diff(args) => Command::diff(args)?,
+            ArgsKind::CopyChunks(args) => Command::copy_chunks(config, args)?,
+            #[cfg(feature = "serde")]
+            ArgsKind::Export(ExportArgs {
                 file_path,
-                chunk_type,
+                output_path,
             }) => {
-                let png = Png::from_file(&file_path)?;
-                let msg = png.chunk_by_type(&chunk_type.to_string()).unwrap(); //TODO maybe error here
-                println!("{}", msg);
-                Ok(())
+                let png = read_png(&file_path)?;
+                let document = serde_json::to_string_pretty(&png)?;
+                let output = Output::new("export").field("chunks", png.chunks().len());
+                match output_path {
+                    Some(output_path) => {
+                        write_file(config, &output_path, document.as_bytes(), output)?
+                            .line(format!(
+                                "Exported {} chunks to {}",
+                                png.chunks().len(),
+                                output_path.to_string_lossy()
+                            ))
+                            .field("output", path_json(&output_path))
+                    }
+                    None => Output {
+                        document: Some(document),
+                        ..output
+                    },
+                }
             }
-            ArgsKind::Remove(RemoveArgs {
-                file_path,
-                chunk_type,
+            #[cfg(feature = "serde")]
+            ArgsKind::Import(ImportArgs {
+                json_path,
+                output_path,
             }) => {
-                let mut png = Png::from_file(&file_path)?;
-                let chunk_removed = png.remove_chunk(&chunk_type.to_string())?;
-                println!(
-                    "Removed chunk: {} from file {}",
-                    chunk_removed,
-                    file_path.to_str().unwrap() // TODO maybe error here
-                );
-                png.to_file(&file_path)?;
-                Ok(())
-            }
-            ArgsKind::Print(PrintArgs { file_path }) => {
-                let png = Png::from_file(&file_path)?;
-                println!("{}", png);
-                Ok(())
+                let png: Png = serde_json::from_slice(&read_bytes(&json_path)?)?;
+                let output = Output::new("import")
+                    .line(format!(
+                        "Imported {} chunks into {}",
+                        png.chunks().len(),
+                        output_path.to_string_lossy()
+                    ))
+                    .field("output", path_json(&output_path))
+                    .field("chunks", png.chunks().len());
+                write_png(config, &output_path, &png, output)?
+            }
+        };
+        Ok(output)
+    }
+
+    fn encode(
+        config: &Config,
+        EncodeArgs {
+            file_path,
+            chunk_type,
+            message,
+            output_path,
+            spread,
+            max_chunk_size,
+        }: EncodeArgs,
+    ) -> Result<Output> {
+        let mut png = read_png(&file_path)?;
+        // Messages too long for one chunk are split even when no maximum
+        // size is asked for.
+        let max_chunk_size = max_chunk_size
+            .or_else(|| Some(Chunk::MAX_LENGTH as usize).filter(|&max| message.len() > max));
+        let output = Output::new("encode")
+            .field("output", path_json(&output_path))
+            .field("chunk_type", chunk_type.to_string())
+            .field("bytes", message.len());
+        let output = match (spread, max_chunk_size) {
+            (Some(spread), _) => {
+                payload::hide_in_frames(&mut png, &chunk_type, message.as_bytes(), spread)?;
+                output.field(
+                    "mode",
+                    match spread {
+                        FrameSpread::Chunks => "frame-chunks",
+                        FrameSpread::Pixels => "frame-pixels",
+                    },
+                )
+            }
+            (None, Some(max)) => {
+                let id = payload::hide_in_chunks(&mut png, &chunk_type, message.as_bytes(), max)?;
+                output.field("mode", "split").field("payload_id", id)
+            }
+            (None, None) => {
+                png.append_chunk(Chunk::new(chunk_type, message.into_bytes()));
+                output.field("mode", "single")
+            }
+        };
+        save(config, &mut png, &output_path, output)
+    }
+
+    fn print(PrintArgs { file_path, lenient }: PrintArgs) -> Result<Output> {
+        let bytes = read_only_bytes(&file_path)?;
+        let (infos, png, diagnostics);
+        let view;
+        if lenient {
+            png = Png::from_bytes_with(&bytes, &ParseOptions::lenient())?;
+            infos = png.chunk_infos();
+            diagnostics = png.diagnostics().iter().map(|d| d.to_string()).collect();
+        } else {
+            // A strict read borrows every chunk from the file, and copies
+            // only what validation looks at.
+            view = PngRef::parse(&bytes)?;
+            view.verify()?;
+            infos = view.chunk_infos();
+            png = view.metadata();
+            diagnostics = Vec::new();
+        }
+        // Invalid chunks are already flagged in their preview.
+        let warnings: Vec<String> = ancillary::validate_relations(&png)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        let chunks = infos.iter().map(|info| {
+            let t = info.chunk.chunk_type();
+            Json::object(vec![
+                ("index", info.index.into()),
+                ("offset", info.offset.into()),
+                ("type", t.to_string().into()),
+                ("length", info.chunk.length().into()),
+                ("crc", info.chunk.crc().into()),
+                ("critical", t.is_critical().into()),
+                ("public", t.is_public().into()),
+                ("safe_to_copy", t.is_safe_to_copy().into()),
+                ("preview", info.preview.clone().into()),
+            ])
+        });
+        let mut output = Output::new("print")
+            .line(chunk_table(&infos))
+            .field("file", path_json(&file_path))
+            .field("chunks", Json::array(chunks))
+            .field("warnings", warnings.clone())
+            .field("diagnostics", diagnostics.clone());
+        for diagnostic in diagnostics {
+            output = output.line(format!("Damaged: {}", diagnostic));
+        }
+        for warning in warnings {
+            output = output.line(format!("Warning: {}", warning));
+        }
+        Ok(output)
+    }
+
+    fn decode(
+        DecodeArgs {
+            file_path,
+            chunk_type,
+        }: DecodeArgs,
+    ) -> Result<Output> {
+        let bytes = read_only_bytes(&file_path)?;
+        let view = PngRef::parse(&bytes)?;
+        view.verify()?;
+        let carries = |c: &ChunkRef| c.chunk_type() == &chunk_type;
+        // Messages live in chunks of their own type, the only ones copied,
+        // unless a payload is spread over frame pixels.
+        let in_pixels = view.chunks().iter().filter(|c| carries(c)).any(
+            |c| matches!(Manifest::parse(c.data()), Ok(Some(m)) if m.spread == FrameSpread::Pixels),
+        );
+        let png = if in_pixels {
+            view.to_png()
+        } else {
+            view.select(carries)
+        };
+        let messages: Vec<Vec<u8>> = match payload::reveal_from_frames(&png, &chunk_type)? {
+            Some(message) => vec![message],
+            None => {
+                let split = payload::reveal_from_chunks(&png, &chunk_type)?;
+                if split.is_empty() {
+                    let msg = png
+                        .chunk_by_type(&chunk_type.to_string())
+                        .ok_or(PngErr::PngChunkTypeAbsent)?;
+                    vec![msg.data().to_vec()]
+                } else {
+                    split.into_iter().map(|(_, message)| message).collect()
+                }
+            }
+        };
+        let messages: Vec<String> = messages
+            .iter()
+            .map(|m| String::from_utf8_lossy(m).into_owned())
+            .collect();
+        let mut output = Output::new("decode")
+            .field("chunk_type", chunk_type.to_string())
+            .field("messages", messages.clone());
+        for message in messages {
+            output = output.line(message);
+        }
+        Ok(output)
+    }
+
+    fn dump(
+        DumpArgs {
+            file_path,
+            chunk_type,
+            index,
+            max_bytes,
+        }: DumpArgs,
+    ) -> Result<Output> {
+        let bytes = read_bytes(&file_path)?;
+        let raw = dump::raw_chunks(&bytes)?;
+        let ctx = ancillary::ImageContext::from_png(&Png::from_chunks(
+            raw.iter().filter_map(|c| c.to_chunk()).collect(),
+        ));
+        let mut output = Output::new("dump").field("file", path_json(&file_path));
+        let mut chunks = Vec::new();
+        for (i, c) in raw.iter().enumerate() {
+            if index.is_some_and(|index| index != i)
+                || chunk_type.as_ref().is_some_and(|t| *t != c.type_name())
+            {
+                continue;
+            }
+            let flags = ChunkType::try_from(c.type_bytes).ok().map(|t| {
+                format!(
+                    "{}, {}, {}",
+                    if t.is_critical() {
+                        "critical"
+                    } else {
+                        "ancillary"
+                    },
+                    if t.is_public() { "public" } else { "private" },
+                    if t.is_safe_to_copy() {
+                        "safe to copy"
+                    } else {
+                        "unsafe to copy"
+                    }
+                )
+            });
+            let decoded = c
+                .to_chunk()
+                .and_then(|chunk| ancillary::describe(&chunk, &ctx))
+                .map(|d| d.unwrap_or_else(|e| format!("invalid: {}", e)));
+            let shown = &c.data[..max_bytes.map_or(c.data.len(), |n| n.min(c.data.len()))];
+
+            output = output
+                .line(format!(
+                    "#{} {} at offset {} (0x{:x})",
+                    i,
+                    c.type_name(),
+                    c.offset,
+                    c.offset
+                ))
+                .line(format!(
+                    "  length {}, {}",
+                    c.length,
+                    flags.as_deref().unwrap_or("invalid chunk type")
+                ))
+                .line(match c.stored_crc {
+                    Some(stored) if c.crc_ok() => format!("  crc {:08x} (ok)", stored),
+                    Some(stored) => format!(
+                        "  crc {:08x}, computed {:08x} (mismatch)",
+                        stored,
+                        c.computed_crc()
+                    ),
+                    None => format!(
+                        "  truncated, {} of {} data bytes present",
+                        c.data.len(),
+                        c.length
+                    ),
+                });
+            if let Some(decoded) = &decoded {
+                output = output.line(format!("  decoded: {}", decoded));
+            }
+            for line in dump::hex_dump(shown, c.data_offset()) {
+                output = output.line(format!("  {}", line));
+            }
+            if shown.len() < c.data.len() {
+                output = output.line(format!("  ... {} more bytes", c.data.len() - shown.len()));
+            }
+            chunks.push(Json::object(vec![
+                ("index", i.into()),
+                ("offset", c.offset.into()),
+                ("length", c.length.into()),
+                ("type", c.type_name().into()),
+                ("stored_crc", c.stored_crc.into()),
+                ("computed_crc", c.computed_crc().into()),
+                ("crc_ok", c.crc_ok().into()),
+                ("decoded", decoded.into()),
+                (
+                    "data",
+                    shown
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<String>()
+                        .into(),
+                ),
+                ("data_truncated", (shown.len() < c.length as usize).into()),
+            ]));
+        }
+        Ok(output.field("chunks", chunks))
+    }
+
+    fn repair(
+        config: &Config,
+        RepairArgs {
+            file_path,
+            output_path,
+        }: RepairArgs,
+    ) -> Result<Output> {
+        let bytes = read_bytes(&file_path)?;
+        let (mut png, fixes) = repair::repair(&bytes)?;
+        let output = Output::new("repair").field("file", path_json(&file_path));
+        if fixes.is_empty() {
+            return Ok(output
+                .line("No problems found")
+                .field("output", Json::Null)
+                .field("fixes", Json::array(Vec::<Json>::new())));
+        }
+        let output_path = output_path.unwrap_or_else(|| file_path.clone());
+        let mut output = output.field("output", path_json(&output_path));
+        let mut fixed = Vec::new();
+        for fix in &fixes {
+            output = output.line(fix.to_string());
+            fixed.push(match fix {
+                Fix::Crc {
+                    index,
+                    chunk_type,
+                    stored,
+                    computed,
+                } => Json::object(vec![
+                    ("kind", "crc".into()),
+                    ("index", (*index).into()),
+                    ("type", chunk_type.clone().into()),
+                    ("stored", (*stored).into()),
+                    ("fixed", (*computed).into()),
+                ]),
+                Fix::Length {
+                    index,
+                    chunk_type,
+                    stored,
+                    actual,
+                } => Json::object(vec![
+                    ("kind", "length".into()),
+                    ("index", (*index).into()),
+                    ("type", chunk_type.clone().into()),
+                    ("stored", (*stored).into()),
+                    ("fixed", (*actual).into()),
+                ]),
+                Fix::TrailingBytes { offset, count } => Json::object(vec![
+                    ("kind", "trailing_bytes".into()),
+                    ("offset", (*offset).into()),
+                    ("count", (*count).into()),
+                ]),
+            });
+        }
+        let output = output
+            .line(format!(
+                "{} {} with {} fixes",
+                wrote(config),
+                output_path.to_string_lossy(),
+                fixes.len()
+            ))
+            .field("fixes", fixed);
+        save(config, &mut png, &output_path, output)
+    }
+
+    fn strip(
+        config: &Config,
+        StripArgs {
+            file_path,
+            options,
+            output_path,
+        }: StripArgs,
+    ) -> Result<Output> {
+        let mut png = read_png(&file_path)?;
+        let before = png.encoded_len();
+        let removed = strip::strip(&mut png, &options);
+        let after = png.encoded_len();
+        let output_path = output_path.unwrap_or_else(|| file_path.clone());
+        let mut output = Output::new("strip");
+        for chunk in &removed {
+            output = output.line(format!(
+                "Removed {} ({} bytes)",
+                chunk.chunk_type(),
+                chunk.length() + 12
+            ));
+        }
+        let output = output
+            .line(format!(
+                "{} {}: {} -> {} bytes, saved {}",
+                wrote(config),
+                output_path.to_string_lossy(),
+                before,
+                after,
+                before - after
+            ))
+            .field("file", path_json(&file_path))
+            .field("output", path_json(&output_path))
+            .field("removed", chunk_types(&removed))
+            .field("bytes_before", before)
+            .field("bytes_after", after)
+            .field("bytes_saved", before - after);
+        save(config, &mut png, &output_path, output)
+    }
+
+    fn optimize(
+        config: &Config,
+        OptimizeArgs {
+            file_path,
+            options,
+            output_path,
+        }: OptimizeArgs,
+    ) -> Result<Output> {
+        let mut png = read_png(&file_path)?;
+        png.set_unsafe_chunk_policy(config.unsafe_chunks);
+        let mut optimized = optimize::optimize(&png, &options)?;
+        let output = Output::new("optimize")
+            .field("file", path_json(&file_path))
+            .field("bytes_before", optimized.bytes_before)
+            .field("bytes_after", optimized.bytes_after)
+            .field(
+                "bytes_saved",
+                optimized.bytes_before - optimized.bytes_after,
+            );
+        let (strategy, level) = match optimized.encoding {
+            Some(encoding) => encoding,
+            None => {
+                return Ok(output
+                    .line(format!(
+                        "{} bytes, no smaller encoding found",
+                        optimized.bytes_before
+                    ))
+                    .field("output", Json::Null)
+                    .field("filter", Json::Null)
+                    .field("level", Json::Null)
+                    .field("reduced_to", Json::Null))
+            }
+        };
+        let output_path = output_path.unwrap_or_else(|| file_path.clone());
+        let reduced_to = optimized
+            .reduced
+            .map(|(color_type, depth)| format!("{}-bit {}", depth, color_type));
+        let mut output = output;
+        if let Some(reduced_to) = &reduced_to {
+            output = output.line(format!("Reduced to {}", reduced_to));
+        }
+        let output = output
+            .line(format!(
+                "{} {}: {} -> {} bytes, saved {} ({} filter, level {})",
+                wrote(config),
+                output_path.to_string_lossy(),
+                optimized.bytes_before,
+                optimized.bytes_after,
+                optimized.bytes_before - optimized.bytes_after,
+                strategy,
+                level
+            ))
+            .field("output", path_json(&output_path))
+            .field("filter", strategy.to_string())
+            .field("level", level)
+            .field("reduced_to", reduced_to);
+        save(config, &mut optimized.png, &output_path, output)
+    }
+
+    fn diff(
+        DiffArgs {
+            old_path,
+            new_path,
+            pixels,
+        }: DiffArgs,
+    ) -> Result<Output> {
+        let old = read_png(&old_path)?;
+        let new = read_png(&new_path)?;
+        let diff = diff::diff(&old, &new, pixels)?;
+        let mut output = Output::new("diff")
+            .field("old", path_json(&old_path))
+            .field("new", path_json(&new_path))
+            .field("identical", diff.is_identical());
+        let mut changes = Vec::new();
+        for change in &diff.changes {
+            output = output.line(change.to_string());
+            changes.push(match change {
+                Change::Added { index, chunk_type } => Json::object(vec![
+                    ("kind", "added".into()),
+                    ("type", chunk_type.clone().into()),
+                    ("to", (*index).into()),
+                ]),
+                Change::Removed { index, chunk_type } => Json::object(vec![
+                    ("kind", "removed".into()),
+                    ("type", chunk_type.clone().into()),
+                    ("from", (*index).into()),
+                ]),
+                Change::Moved {
+                    from,
+                    to,
+                    chunk_type,
+                } => Json::object(vec![
+                    ("kind", "moved".into()),
+                    ("type", chunk_type.clone().into()),
+                    ("from", (*from).into()),
+                    ("to", (*to).into()),
+                ]),
+                Change::Modified {
+                    from,
+                    to,
+                    chunk_type,
+                    detail,
+                } => {
+                    let json = Json::object(vec![
+                        ("kind", "modified".into()),
+                        ("type", chunk_type.clone().into()),
+                        ("from", (*from).into()),
+                        ("to", (*to).into()),
+                    ]);
+                    match detail {
+                        Detail::Fields(fields) => json.with(
+                            "fields",
+                            Json::array(fields.iter().map(|f| {
+                                Json::object(vec![
+                                    ("field", f.field.clone().into()),
+                                    ("old", f.old.clone().into()),
+                                    ("new", f.new.clone().into()),
+                                ])
+                            })),
+                        ),
+                        Detail::Bytes {
+                            old_length,
+                            new_length,
+                            ranges,
+                        } => json
+                            .with("old_length", *old_length)
+                            .with("new_length", *new_length)
+                            .with(
+                                "ranges",
+                                Json::array(
+                                    ranges
+                                        .iter()
+                                        .map(|&(start, end)| Json::array(vec![start, end])),
+                                ),
+                            ),
+                    }
+                }
+            });
+        }
+        output = output.field("changes", changes);
+        output = match &diff.pixels {
+            None => output.field("pixels", Json::Null),
+            Some(pixels) => {
+                let json = match pixels {
+                    PixelDiff::Dimensions { old, new } => Json::object(vec![
+                        ("identical", false.into()),
+                        ("old_size", Json::array(vec![old.0, old.1])),
+                        ("new_size", Json::array(vec![new.0, new.1])),
+                    ]),
+                    PixelDiff::Pixels {
+                        differing,
+                        bounds,
+                        max_delta,
+                    } => Json::object(vec![
+                        ("identical", (*differing == 0).into()),
+                        ("differing", (*differing).into()),
+                        (
+                            "bounds",
+                            bounds
+                                .map(|(left, top, right, bottom)| {
+                                    Json::array(vec![left, top, right, bottom])
+                                })
+                                .into(),
+                        ),
+                        ("max_delta", (*max_delta as u32).into()),
+                    ]),
+                };
+                output.line(pixels.to_string()).field("pixels", json)
+            }
+        };
+        if diff.is_identical() {
+            output = output.line("Files are identical");
+        }
+        Ok(Output {
+            status: if diff.is_identical() { 0 } else { 1 },
+            ..output
+        })
+    }
+
+    fn copy_chunks(
+        config: &Config,
+        CopyChunksArgs {
+            from_path,
+            to_path,
+            options,
+            output_path,
+        }: CopyChunksArgs,
+    ) -> Result<Output> {
+        let from = read_png(&from_path)?;
+        let mut to = read_png(&to_path)?;
+        let copied = copy::copy_chunks(&from, &mut to, &options)?;
+        let output_path = output_path.unwrap_or_else(|| to_path.clone());
+        let mut output = Output::new("copy-chunks");
+        for chunk in &copied.copied {
+            output = output.line(format!("Copied {}", chunk.chunk_type()));
+        }
+        for chunk in &copied.refused {
+            output = output.line(format!(
+                "Refused {}: unsafe to copy into a different image, use --force to copy anyway",
+                chunk.chunk_type()
+            ));
+        }
+        let output = output
+            .line(format!(
+                "{} {} with {} chunks copied, {} replaced",
+                wrote(config),
+                output_path.to_string_lossy(),
+                copied.copied.len(),
+                copied.replaced.len()
+            ))
+            .field("from", path_json(&from_path))
+            .field("to", path_json(&to_path))
+            .field("output", path_json(&output_path))
+            .field("copied", chunk_types(&copied.copied))
+            .field("replaced", chunk_types(&copied.replaced))
+            .field("refused", chunk_types(&copied.refused));
+        save(config, &mut to, &output_path, output)
+    }
+
+    fn color(config: &Config, ColorArgs { file_path, action }: ColorArgs) -> Result<Output> {
+        let mut png = read_png(&file_path)?;
+        match action {
+            ColorAction::Show => {
+                let info = ColorInfo::from_png(&png)?;
+                let pair = |(x, y): (f64, f64)| Json::array(vec![x, y]);
+                let chromaticities = info.chromaticities.as_ref().map(|c| {
+                    Json::object(vec![
+                        ("white", pair(c.white_point())),
+                        ("red", pair(c.red())),
+                        ("green", pair(c.green())),
+                        ("blue", pair(c.blue())),
+                    ])
+                });
+                let icc = info.icc.as_ref().map(|icc| {
+                    Json::object(vec![
+                        ("name", icc.name().into()),
+                        ("size", icc.profile().len().into()),
+                    ])
+                });
+                let warning = info.validate().err().map(|e| e.to_string());
+                let mut output = Output::action("color", "show")
+                    .line(info.to_string())
+                    .field("gamma", info.gamma.as_ref().map(Gamma::value))
+                    .field("chromaticities", chromaticities)
+                    .field("srgb", info.srgb.map(|i| i.to_string()))
+                    .field("icc", icc)
+                    .field("warnings", warning.iter().cloned().collect::<Vec<_>>());
+                if let Some(warning) = warning {
+                    output = output.line(format!("Warning: {}", warning));
+                }
+                Ok(output)
+            }
+            ColorAction::Set {
+                gamma,
+                chromaticities,
+                srgb,
+            } => {
+                let mut chunks = Vec::new();
+                if let Some(gamma) = gamma {
+                    chunks.push(Gamma::from_f64(gamma)?.to_chunk());
+                }
+                if let Some(chrm) = chromaticities {
+                    chunks.push(chrm.to_chunk());
+                }
+                if let Some(intent) = srgb {
+                    chunks.push(intent.to_chunk());
+                }
+                let mut output = Output::action("color", "set").field("set", chunk_types(&chunks));
+                let mut removed_all = Vec::new();
+                for chunk in chunks {
+                    output = output.line(format!("Set chunk: {}", chunk.chunk_type()));
+                    for removed in color::set_color_chunk(&mut png, chunk) {
+                        output = output.line(format!("Removed chunk: {}", removed.chunk_type()));
+                        removed_all.push(removed);
+                    }
+                }
+                let output = output.field("removed", chunk_types(&removed_all));
+                save(config, &mut png, &file_path, output)
+            }
+            ColorAction::Strip => {
+                let removed = color::strip_color_chunks(&mut png);
+                let mut output =
+                    Output::action("color", "strip").field("removed", chunk_types(&removed));
+                for chunk in &removed {
+                    output = output.line(format!("Removed chunk: {}", chunk.chunk_type()));
+                }
+                save(config, &mut png, &file_path, output)
+            }
+            ColorAction::ExtractIcc { output_path } => {
+                let chunk = png
+                    .chunk_by_type("iCCP")
+                    .ok_or(PngErr::PngChunkTypeAbsent)?;
+                let icc = IccProfile::from_chunk_with(chunk, png.limits())?;
+                let output = Output::action("color", "extract-icc")
+                    .line(format!(
+                        "Extracted ICC profile {} ({} bytes)",
+                        icc.name(),
+                        icc.profile().len()
+                    ))
+                    .field("name", icc.name())
+                    .field("size", icc.profile().len())
+                    .field("output", path_json(&output_path));
+                write_file(config, &output_path, icc.profile(), output)
+            }
+            ColorAction::EmbedIcc { profile_path, name } => {
+                let mut profile = Vec::new();
+                File::open(&profile_path)?.read_to_end(&mut profile)?;
+                let icc = IccProfile::new(&name, profile)?;
+                let removed = color::set_color_chunk(&mut png, icc.to_chunk()?);
+                let mut output = Output::action("color", "embed-icc")
+                    .line("Set chunk: iCCP")
+                    .field("set", Json::array(vec!["iCCP"]))
+                    .field("removed", chunk_types(&removed));
+                for chunk in &removed {
+                    output = output.line(format!("Removed chunk: {}", chunk.chunk_type()));
+                }
+                save(config, &mut png, &file_path, output)
+            }
+        }
+    }
+    fn exif(config: &Config, ExifArgs { file_path, action }: ExifArgs) -> Result<Output> {
+        let mut png = read_png(&file_path)?;
+        // Left unparsed for a full strip so a damaged chunk can still be removed.
+        let parse = |png: &Png| png.chunk_by_type("eXIf").map(Exif::try_from).transpose();
+        match action {
+            ExifAction::Show => {
+                let output = Output::action("exif", "show");
+                let exif = match parse(&png)? {
+                    Some(exif) => exif,
+                    None => {
+                        return Ok(output
+                            .line("No eXIf chunk")
+                            .field("byte_order", Json::Null)
+                            .field("entries", Json::Array(vec![]))
+                            .field("gps", Json::Null))
+                    }
+                };
+                let order = exif.byte_order();
+                let entries = exif.entries().into_iter().map(|(section, entry)| {
+                    Json::object(vec![
+                        ("section", format!("{:?}", section).to_lowercase().into()),
+                        ("tag", entry.tag.into()),
+                        ("name", exif::tag_name(section, entry.tag).into()),
+                        ("value", entry.value(order).to_string().into()),
+                    ])
+                });
+                Ok(output
+                    .line(exif.to_string())
+                    .field(
+                        "byte_order",
+                        match order {
+                            ByteOrder::LittleEndian => "little",
+                            ByteOrder::BigEndian => "big",
+                        },
+                    )
+                    .field("entries", Json::array(entries))
+                    .field(
+                        "gps",
+                        exif.gps_coordinates()
+                            .map(|(lat, lon)| Json::array(vec![lat, lon])),
+                    ))
+            }
+            ExifAction::Strip { gps_only: false } => {
+                let removed = png.remove_chunk("eXIf")?;
+                let output = Output::action("exif", "strip")
+                    .line(format!(
+                        "Removed chunk: {} ({} bytes)",
+                        removed.chunk_type(),
+                        removed.length()
+                    ))
+                    .field("gps_only", false)
+                    .field("removed", true);
+                save(config, &mut png, &file_path, output)
+            }
+            ExifAction::Strip { gps_only: true } => {
+                let mut exif = parse(&png)?.ok_or(PngErr::PngChunkTypeAbsent)?;
+                exif.check_rewritable()?;
+                let output = Output::action("exif", "strip").field("gps_only", true);
+                if !exif.remove_gps() {
+                    return Ok(output.line("No GPS tags found").field("removed", false));
+                }
+                png.remove_chunk("eXIf")?;
+                png.insert_before_image_data(exif.to_chunk());
+                let output = output.line("Removed GPS tags").field("removed", true);
+                save(config, &mut png, &file_path, output)
+            }
+            ExifAction::SetTag { tag, value } => {
+                let mut exif = parse(&png)?.unwrap_or_else(|| Exif::new(ByteOrder::LittleEndian));
+                exif.check_rewritable()?;
+                exif.set_tag(&tag, &value)?;
+                let _ = png.remove_chunk("eXIf");
+                png.insert_before_image_data(exif.to_chunk());
+                let output = Output::action("exif", "set-tag")
+                    .line(format!("Set tag: {} = {}", tag, value))
+                    .field("tag", tag)
+                    .field("value", value);
+                save(config, &mut png, &file_path, output)
+            }
+        }
+    }
+    fn palette(config: &Config, PaletteArgs { file_path, action }: PaletteArgs) -> Result<Output> {
+        let mut png = read_png(&file_path)?;
+        let mut palette = Palette::from_png(&png)?;
+        match action {
+            PaletteAction::Show => {
+                let entries = palette.entries().iter().enumerate().map(|(i, e)| {
+                    Json::object(vec![
+                        ("index", i.into()),
+                        ("red", e.red.into()),
+                        ("green", e.green.into()),
+                        ("blue", e.blue.into()),
+                        ("alpha", e.alpha.into()),
+                    ])
+                });
+                Ok(Output::action("palette", "show")
+                    .line(palette.to_string())
+                    .field("entries", Json::array(entries)))
+            }
+            PaletteAction::SetEntry { index, entry } => {
+                palette.set_entry(index, entry)?;
+                palette.apply(&mut png)?;
+                let output = Output::action("palette", "set-entry")
+                    .line(format!("Set palette entry {} to {}", index, entry))
+                    .field("index", index)
+                    .field("entry", entry.to_string());
+                save(config, &mut png, &file_path, output)
+            }
+            PaletteAction::Export {
+                output_path,
+                format,
+            } => {
+                let (contents, format_name) = match format {
+                    PaletteFormat::Gpl => (
+                        palette.to_gpl(
+                            &file_path
+                                .file_stem()
+                                .map(|s| s.to_string_lossy())
+                                .unwrap_or_default(),
+                        ),
+                        "gpl",
+                    ),
+                    PaletteFormat::Json => (palette.to_json(), "json"),
+                };
+                let output = Output::action("palette", "export")
+                    .line(format!("Exported {} palette entries", palette.len()))
+                    .field("output", path_json(&output_path))
+                    .field("format", format_name)
+                    .field("entries", palette.len());
+                write_file(config, &output_path, contents.as_bytes(), output)
+            }
+        }
+    }
+    fn apng(config: &Config, ApngArgs { file_path, action }: ApngArgs) -> Result<Output> {
+        match action {
+            ApngAction::Info => {
+                let png = read_png(&file_path)?;
+                let output = Output::action("apng", "info");
+                let animation = match png.animation()? {
+                    Some(animation) => animation,
+                    None => return Ok(output.line("Not an animated png").field("animated", false)),
+                };
+                let frames = animation.frames.iter().map(|frame| {
+                    let c = &frame.control;
+                    Json::object(vec![
+                        ("sequence_number", c.sequence_number.into()),
+                        ("width", c.width.into()),
+                        ("height", c.height.into()),
+                        ("x_offset", c.x_offset.into()),
+                        ("y_offset", c.y_offset.into()),
+                        ("delay", c.delay().into()),
+                        ("dispose_op", c.dispose_op.to_string().into()),
+                        ("blend_op", c.blend_op.to_string().into()),
+                        ("bytes", frame.data.len().into()),
+                    ])
+                });
+                Ok(output
+                    .line(animation.to_string())
+                    .field("animated", true)
+                    .field("num_frames", animation.control.num_frames)
+                    .field("num_plays", animation.control.num_plays)
+                    .field("duration", animation.duration())
+                    .field("default_image_is_frame", animation.default_image_is_frame)
+                    .field("frames", Json::array(frames)))
+            }
+            ApngAction::Split { output_dir } => {
+                let png = read_png(&file_path)?;
+                let animation = png.animation()?.ok_or(PngErr::PngChunkTypeAbsent)?;
+                if !config.dry_run {
+                    std::fs::create_dir_all(&output_dir)?;
+                }
+                let frames = animation.render(&png)?;
+                let mut output = Output::action("apng", "split");
+                let mut written = Vec::new();
+                for (i, (image, frame)) in frames.iter().zip(animation.frames.iter()).enumerate() {
+                    let path = output_dir.join(format!("frame_{:04}.png", i));
+                    output = write_png(config, &path, &image.to_png()?, output)?;
+                    output = output.line(format!(
+                        "{} {} ({:.3}s)",
+                        wrote(config),
+                        path.to_string_lossy(),
+                        frame.control.delay()
+                    ));
+                    written.push(Json::object(vec![
+                        ("path", path_json(&path)),
+                        ("delay", frame.control.delay().into()),
+                    ]));
+                }
+                Ok(output.field("frames", written))
+            }
+            ApngAction::Build {
+                frame_paths,
+                delays,
+                num_plays,
+            } => {
+                let frames = frame_paths
+                    .iter()
+                    .map(|p| RgbaImage::from_png(&read_png(p)?))
+                    .collect::<Result<Vec<_>>>()?;
+                let png = Animation::build(&frames, &delays, num_plays)?;
+                let output = Output::action("apng", "build")
+                    .line(format!(
+                        "Built {} with {} frames",
+                        file_path.to_string_lossy(),
+                        frames.len()
+                    ))
+                    .field("output", path_json(&file_path))
+                    .field("frames", frames.len());
+                write_png(config, &file_path, &png, output)
             }
         }
     }
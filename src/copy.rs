@@ -0,0 +1,267 @@
+use crate::ancillary::{self, ImageContext};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::color;
+use crate::png::Png;
+use crate::Result;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum CopyErr {
+    CriticalType(String),
+}
+impl std::error::Error for CopyErr {}
+impl Display for CopyErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            CopyErr::CriticalType(chunk_type) => {
+                write!(f, "{} is a critical chunk and cannot be copied", chunk_type)
+            }
+        }
+    }
+}
+
+/// Which chunks [`copy_chunks`] copies.
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    /// Ancillary chunk types to copy, every ancillary chunk when empty.
+    pub types: Vec<ChunkType>,
+    /// Copies unsafe-to-copy chunks even when they cannot be checked
+    /// against the destination's critical chunks.
+    pub force: bool,
+}
+
+/// What [`copy_chunks`] did, each list in source file order.
+#[derive(Debug, Default)]
+pub struct Copied {
+    pub copied: Vec<Chunk>,
+    /// Destination chunks removed for a copied chunk, as described at
+    /// [`copy_chunks`].
+    pub replaced: Vec<Chunk>,
+    /// Unsafe-to-copy chunks left out.
+    pub refused: Vec<Chunk>,
+}
+
+/// The animation chunks, which describe frames of the image data they
+/// were written with and so are never checked against another image.
+const FRAME_CHUNK_TYPES: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// Chunk types that may appear more than once, told apart by the keyword
+/// or palette name ending at their first null byte.
+const KEYED_CHUNK_TYPES: [&str; 4] = ["tEXt", "zTXt", "iTXt", "sPLT"];
+
+/// Where the spec allows a chunk relative to the critical chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placement {
+    BeforePalette,
+    BeforeImageData,
+    AfterImageData,
+}
+
+/// Where `chunk`, found at `index` in `png`, goes. Types with an ordering
+/// rule follow it. Others keep their side of `PLTE` and `IDAT`, which is
+/// what the spec asks of editors copying chunks they do not know.
+fn placement(png: &Png, index: usize, chunk: &Chunk) -> Placement {
+    match chunk.chunk_type().to_string().as_str() {
+        "cHRM" | "gAMA" | "iCCP" | "sBIT" | "sRGB" | "cICP" | "mDCV" | "cLLI" => {
+            Placement::BeforePalette
+        }
+        "bKGD" | "hIST" | "tRNS" | "pHYs" | "sPLT" | "eXIf" | "oFFs" | "pCAL" | "sCAL" | "sTER"
+        | "acTL" => Placement::BeforeImageData,
+        _ => {
+            let before = |t: &str| png.position(t).is_some_and(|p| index < p);
+            if before("PLTE") {
+                Placement::BeforePalette
+            } else if before("IDAT") {
+                Placement::BeforeImageData
+            } else {
+                Placement::AfterImageData
+            }
+        }
+    }
+}
+
+/// Inserts `chunk` as late as `placement` allows, so that chunks inserted
+/// one after another keep their order.
+fn insert(png: &mut Png, placement: Placement, chunk: Chunk) {
+    let first = |types: &[&str]| {
+        png.chunks()
+            .iter()
+            .position(|c| types.contains(&c.chunk_type().to_string().as_str()))
+            .unwrap_or(png.chunks().len())
+    };
+    let index = match placement {
+        Placement::BeforePalette => first(&["PLTE", "IDAT", "IEND"]),
+        Placement::BeforeImageData => first(&["IDAT", "IEND"]),
+        Placement::AfterImageData => first(&["IEND"]),
+    };
+    png.insert_chunk(index, chunk);
+}
+
+fn critical_chunks(png: &Png) -> Vec<(ChunkType, &[u8])> {
+    png.chunks()
+        .iter()
+        .filter(|c| c.chunk_type().is_critical())
+        .map(|c| (c.chunk_type().clone(), c.data()))
+        .collect()
+}
+
+/// Whether the spec allows copying `chunk` into `to`. Safe-to-copy chunks
+/// always are. Unsafe-to-copy chunks depend on the critical chunks, so they
+/// are only copied unchanged when those match, or for known types other
+/// than the animation chunks when they are valid for the destination.
+fn may_copy(chunk: &Chunk, same_critical: bool, ctx: &ImageContext) -> bool {
+    let t = chunk.chunk_type();
+    t.is_safe_to_copy()
+        || same_critical
+        || !FRAME_CHUNK_TYPES.contains(&t.to_string().as_str())
+            && matches!(ancillary::describe(chunk, ctx), Some(Ok(_)))
+}
+
+/// Whether copying `chunk` removes `existing` from the destination: it
+/// has the same type and, for [`KEYED_CHUNK_TYPES`], the same keyword, or
+/// it is the `sRGB` or `iCCP` chunk that `chunk` excludes.
+fn supersedes(chunk: &Chunk, existing: &Chunk) -> bool {
+    let t = chunk.chunk_type().to_string();
+    if existing.chunk_type() != chunk.chunk_type() {
+        return color::conflicting_type(&t) == Some(&existing.chunk_type().to_string());
+    }
+    let key = |c: &Chunk| c.data().split(|&b| b == 0).next().map(<[u8]>::to_vec);
+    !KEYED_CHUNK_TYPES.contains(&t.as_str()) || key(chunk) == key(existing)
+}
+
+/// Copies the ancillary chunks `options` select from `from` into `to`.
+/// Chunks in `to` that a copied chunk supersedes are replaced, and each
+/// copied chunk is placed where the spec's ordering rules allow.
+pub fn copy_chunks(from: &Png, to: &mut Png, options: &CopyOptions) -> Result<Copied> {
+    if let Some(t) = options.types.iter().find(|t| t.is_critical()) {
+        return Err(Box::new(CopyErr::CriticalType(t.to_string())));
+    }
+    let same_critical = critical_chunks(from) == critical_chunks(to);
+    let ctx = ImageContext::from_png(to);
+    let mut copied = Copied::default();
+    let mut selected = Vec::new();
+    for (index, chunk) in from.chunks().iter().enumerate() {
+        let t = chunk.chunk_type();
+        if t.is_critical() || !options.types.is_empty() && !options.types.contains(t) {
+            continue;
+        }
+        if options.force || may_copy(chunk, same_critical, &ctx) {
+            selected.push((placement(from, index, chunk), chunk.clone()));
+        } else {
+            copied.refused.push(chunk.clone());
+        }
+    }
+    copied.replaced = to.remove_chunks(|c| selected.iter().any(|(_, s)| supersedes(s, c)));
+    for (placement, chunk) in selected {
+        insert(to, placement, chunk.clone());
+        copied.copied.push(chunk);
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::RgbaImage;
+    use crate::png::tests::PNG_FILE;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_copy_chunks_places_by_ordering_rules() {
+        let mut from = Png::try_from(&PNG_FILE[..]).unwrap();
+        from.insert_chunk(6, chunk("tEXt", b"Title\0Dice"));
+        let mut to = RgbaImage::new(2, 2).to_png().unwrap();
+        to.insert_chunk(1, chunk("gAMA", &[0, 0, 0, 1]));
+        let copied = copy_chunks(&from, &mut to, &CopyOptions::default()).unwrap();
+        // The private critical RuSt is not copied, and gAMA, being valid
+        // for any image, is copied although it is unsafe to copy.
+        assert_eq!(
+            types(&to),
+            ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "tEXt", "IEND"]
+        );
+        assert_eq!(copied.replaced.len(), 1);
+        assert!(copied.refused.is_empty());
+        assert_eq!(to.chunk_by_type("gAMA").unwrap().data(), [0, 0, 177, 143]);
+    }
+
+    #[test]
+    fn test_copy_chunks_refuses_unknown_unsafe() {
+        let mut from = RgbaImage::new(2, 2).to_png().unwrap();
+        from.insert_chunk(1, chunk("prIV", &[1]));
+        from.insert_chunk(1, chunk("prIv", &[2]));
+        let options = CopyOptions::default();
+
+        let mut same = from.clone();
+        same.remove_chunks(|c| !c.chunk_type().is_critical());
+        let copied = copy_chunks(&from, &mut same, &options).unwrap();
+        assert!(copied.refused.is_empty());
+
+        let mut other = RgbaImage::new(3, 2).to_png().unwrap();
+        let copied = copy_chunks(&from, &mut other, &options).unwrap();
+        assert_eq!(types(&other), ["IHDR", "prIv", "IDAT", "IEND"]);
+        assert_eq!(copied.refused[0].chunk_type().to_string(), "prIV");
+
+        let forced = CopyOptions {
+            force: true,
+            ..CopyOptions::default()
+        };
+        copy_chunks(&from, &mut other, &forced).unwrap();
+        assert_eq!(types(&other), ["IHDR", "prIv", "prIV", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_copy_chunks_keeps_other_keywords() {
+        let mut from = RgbaImage::new(2, 2).to_png().unwrap();
+        from.insert_chunk(1, chunk("tEXt", b"Title\0New"));
+        let mut to = RgbaImage::new(2, 2).to_png().unwrap();
+        to.insert_chunk(1, chunk("tEXt", b"Title\0Old"));
+        to.insert_chunk(1, chunk("tEXt", b"Author\0Someone"));
+        let copied = copy_chunks(&from, &mut to, &CopyOptions::default()).unwrap();
+        assert_eq!(copied.replaced.len(), 1);
+        let texts: Vec<&[u8]> = to.chunks()[1..3].iter().map(|c| c.data()).collect();
+        assert_eq!(texts, [&b"Author\0Someone"[..], &b"Title\0New"[..]]);
+    }
+
+    #[test]
+    fn test_copy_chunks_srgb_replaces_iccp() {
+        let mut from = RgbaImage::new(2, 2).to_png().unwrap();
+        from.insert_chunk(1, chunk("sRGB", &[0]));
+        let mut to = RgbaImage::new(2, 2).to_png().unwrap();
+        let icc = crate::color::IccProfile::new("profile", b"data".to_vec()).unwrap();
+        to.insert_chunk(1, icc.to_chunk().unwrap());
+        let copied = copy_chunks(&from, &mut to, &CopyOptions::default()).unwrap();
+        assert_eq!(copied.replaced[0].chunk_type().to_string(), "iCCP");
+        assert_eq!(types(&to), ["IHDR", "sRGB", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_copy_chunks_types() {
+        let from = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut to = RgbaImage::new(2, 2).to_png().unwrap();
+        let options = CopyOptions {
+            types: vec![ChunkType::from_str("pHYs").unwrap()],
+            ..CopyOptions::default()
+        };
+        copy_chunks(&from, &mut to, &options).unwrap();
+        assert_eq!(types(&to), ["IHDR", "pHYs", "IDAT", "IEND"]);
+
+        let options = CopyOptions {
+            types: vec![ChunkType::from_str("IHDR").unwrap()],
+            ..CopyOptions::default()
+        };
+        assert!(copy_chunks(&from, &mut to, &options).is_err());
+    }
+}
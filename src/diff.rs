@@ -0,0 +1,533 @@
+use crate::ancillary::{self, ImageContext};
+use crate::apng::{AnimationControl, FrameControl};
+use crate::chunk::Chunk;
+use crate::color::{Chromaticities, Gamma, RenderingIntent};
+use crate::ihdr::Ihdr;
+use crate::palette::Palette;
+use crate::png::Png;
+use crate::reduce;
+use crate::Result;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+/// One field of a known chunk type whose value differs. A field present on
+/// one side only, such as a palette entry, has `None` on the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// How the data of a chunk present in both files differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detail {
+    Fields(Vec<FieldChange>),
+    /// Half open byte ranges of the data that differ, for chunk types
+    /// without a field-level comparison. A length change shows up as a
+    /// range running to the end of the longer side, and ranges with only a
+    /// few equal bytes between them are merged.
+    Bytes {
+        old_length: usize,
+        new_length: usize,
+        ranges: Vec<(usize, usize)>,
+    },
+}
+
+/// One difference found by [`diff`]. Indices are positions in
+/// `Png::chunks` of the old or new file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added {
+        index: usize,
+        chunk_type: String,
+    },
+    Removed {
+        index: usize,
+        chunk_type: String,
+    },
+    /// The chunk is in both files but its position relative to the other
+    /// common chunks changed.
+    Moved {
+        from: usize,
+        to: usize,
+        chunk_type: String,
+    },
+    Modified {
+        from: usize,
+        to: usize,
+        chunk_type: String,
+        detail: Detail,
+    },
+}
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Change::Added { index, chunk_type } => write!(f, "+ {} at {}", chunk_type, index),
+            Change::Removed { index, chunk_type } => write!(f, "- {} at {}", chunk_type, index),
+            Change::Moved {
+                from,
+                to,
+                chunk_type,
+            } => write!(f, "> {} moved from {} to {}", chunk_type, from, to),
+            Change::Modified {
+                from,
+                to,
+                chunk_type,
+                detail,
+            } => {
+                write!(f, "~ {} at {} -> {}:", chunk_type, from, to)?;
+                match detail {
+                    Detail::Fields(fields) => {
+                        for change in fields {
+                            let old = change.old.as_deref().unwrap_or("(none)");
+                            let new = change.new.as_deref().unwrap_or("(none)");
+                            write!(f, "\n    {}: {} -> {}", change.field, old, new)?;
+                        }
+                        Ok(())
+                    }
+                    Detail::Bytes {
+                        old_length,
+                        new_length,
+                        ranges,
+                    } => {
+                        if old_length != new_length {
+                            write!(f, " length {} -> {},", old_length, new_length)?;
+                        }
+                        let ranges: Vec<String> = ranges
+                            .iter()
+                            .map(|(start, end)| format!("{}..{}", start, end))
+                            .collect();
+                        write!(f, " bytes {} differ", ranges.join(", "))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How the decoded pixels of the two files compare.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PixelDiff {
+    Dimensions {
+        old: (u32, u32),
+        new: (u32, u32),
+    },
+    Pixels {
+        differing: usize,
+        /// Smallest rectangle holding every differing pixel, as left, top,
+        /// right and bottom, inclusive.
+        bounds: Option<(u32, u32, u32, u32)>,
+        /// Largest difference of any channel, on the 16-bit scale of
+        /// [`reduce::rgba16`].
+        max_delta: u16,
+    },
+}
+impl PixelDiff {
+    pub fn is_identical(&self) -> bool {
+        matches!(self, PixelDiff::Pixels { differing: 0, .. })
+    }
+}
+impl Display for PixelDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            PixelDiff::Dimensions { old, new } => write!(
+                f,
+                "Dimensions differ: {}x{} -> {}x{}",
+                old.0, old.1, new.0, new.1
+            ),
+            PixelDiff::Pixels {
+                bounds: Some((left, top, right, bottom)),
+                differing,
+                max_delta,
+            } => write!(
+                f,
+                "{} pixels differ within ({}, {})..=({}, {}), max channel delta {}",
+                differing, left, top, right, bottom, max_delta
+            ),
+            PixelDiff::Pixels { .. } => write!(f, "Pixels are identical"),
+        }
+    }
+}
+
+/// Everything [`diff`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+    /// Only computed when asked for.
+    pub pixels: Option<PixelDiff>,
+}
+impl Diff {
+    pub fn is_identical(&self) -> bool {
+        self.changes.is_empty() && self.pixels.as_ref().map_or(true, PixelDiff::is_identical)
+    }
+}
+
+/// Compares the chunks of `old` and `new`, and their decoded pixels when
+/// `pixels` is set. Chunks are paired by type and by occurrence among
+/// chunks of that type, so the second `IDAT` of one file is compared with
+/// the second `IDAT` of the other. Changes come removals first, then
+/// additions, moves and modifications, each in file order.
+pub fn diff(old: &Png, new: &Png, pixels: bool) -> Result<Diff> {
+    let (a, b) = (old.chunks(), new.chunks());
+    let mut occurrences: HashMap<(String, usize), usize> = HashMap::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (j, chunk) in b.iter().enumerate() {
+        let t = chunk.chunk_type().to_string();
+        let n = seen.entry(t.clone()).or_insert(0);
+        occurrences.insert((t, *n), j);
+        *n += 1;
+    }
+    seen.clear();
+    let mut pairs = Vec::new();
+    let mut changes = Vec::new();
+    for (i, chunk) in a.iter().enumerate() {
+        let t = chunk.chunk_type().to_string();
+        let n = seen.entry(t.clone()).or_insert(0);
+        match occurrences.remove(&(t.clone(), *n)) {
+            Some(j) => pairs.push((i, j)),
+            None => changes.push(Change::Removed {
+                index: i,
+                chunk_type: t,
+            }),
+        }
+        *n += 1;
+    }
+    let mut added: Vec<usize> = occurrences.into_values().collect();
+    added.sort_unstable();
+    changes.extend(added.into_iter().map(|j| Change::Added {
+        index: j,
+        chunk_type: b[j].chunk_type().to_string(),
+    }));
+
+    let in_order = longest_increasing(&pairs.iter().map(|&(_, j)| j).collect::<Vec<_>>());
+    for (k, &(from, to)) in pairs.iter().enumerate() {
+        if !in_order[k] {
+            changes.push(Change::Moved {
+                from,
+                to,
+                chunk_type: a[from].chunk_type().to_string(),
+            });
+        }
+    }
+    let (old_ctx, new_ctx) = (ImageContext::from_png(old), ImageContext::from_png(new));
+    for &(from, to) in &pairs {
+        if a[from].data() != b[to].data() {
+            changes.push(Change::Modified {
+                from,
+                to,
+                chunk_type: a[from].chunk_type().to_string(),
+                detail: detail(&a[from], &b[to], &old_ctx, &new_ctx),
+            });
+        }
+    }
+
+    let pixels = if pixels {
+        Some(pixel_diff(old, new)?)
+    } else {
+        None
+    };
+    Ok(Diff { changes, pixels })
+}
+
+/// Marks the elements of `values` that belong to one longest strictly
+/// increasing subsequence; the others are the ones that moved.
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // tails[k] is the index of the smallest value ending an increasing run
+    // of length k + 1.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+    for (i, &v) in values.iter().enumerate() {
+        let k = tails.partition_point(|&t| values[t] < v);
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut keep = vec![false; values.len()];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        keep[i] = true;
+        next = previous[i];
+    }
+    keep
+}
+
+/// Named field values of the chunk types with a field-level comparison,
+/// falling back to the single description from [`ancillary::describe`] for
+/// the other known types.
+fn fields(chunk: &Chunk, ctx: &ImageContext) -> Option<Vec<(String, String)>> {
+    let point = |(x, y): (f64, f64)| format!("{}, {}", x, y);
+    let named = |fields: Vec<(&str, String)>| {
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    };
+    let fields = match chunk.chunk_type().to_string().as_str() {
+        "IHDR" => Ihdr::try_from(chunk).map(|c| {
+            named(vec![
+                ("width", c.width.to_string()),
+                ("height", c.height.to_string()),
+                ("bit_depth", c.bit_depth.to_string()),
+                ("color_type", c.color_type.to_string()),
+                ("interlaced", c.interlaced.to_string()),
+            ])
+        }),
+        "PLTE" => Palette::from_chunks(chunk, None).map(|p| {
+            p.entries()
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    (
+                        format!("entry {}", i),
+                        format!("#{:02x}{:02x}{:02x}", e.red, e.green, e.blue),
+                    )
+                })
+                .collect()
+        }),
+        "gAMA" => Gamma::try_from(chunk).map(|c| named(vec![("gamma", c.to_string())])),
+        "cHRM" => Chromaticities::try_from(chunk).map(|c| {
+            named(vec![
+                ("white_point", point(c.white_point())),
+                ("red", point(c.red())),
+                ("green", point(c.green())),
+                ("blue", point(c.blue())),
+            ])
+        }),
+        "sRGB" => RenderingIntent::try_from(chunk).map(|c| named(vec![("intent", c.to_string())])),
+        "acTL" => AnimationControl::try_from(chunk).map(|c| {
+            named(vec![
+                ("num_frames", c.num_frames.to_string()),
+                ("num_plays", c.num_plays.to_string()),
+            ])
+        }),
+        "fcTL" => FrameControl::try_from(chunk).map(|c| {
+            named(vec![
+                ("sequence_number", c.sequence_number.to_string()),
+                ("width", c.width.to_string()),
+                ("height", c.height.to_string()),
+                ("x_offset", c.x_offset.to_string()),
+                ("y_offset", c.y_offset.to_string()),
+                ("delay", format!("{}/{}", c.delay_num, c.delay_den)),
+                ("dispose_op", c.dispose_op.to_string()),
+                ("blend_op", c.blend_op.to_string()),
+            ])
+        }),
+        _ => ancillary::describe(chunk, ctx)?.map(|s| named(vec![("summary", s)])),
+    };
+    fields.ok()
+}
+
+/// Differing byte ranges closer than this are reported as one, so that
+/// recompressed data does not list every byte that happens to match.
+const MERGE_GAP: usize = 8;
+
+fn detail(old: &Chunk, new: &Chunk, old_ctx: &ImageContext, new_ctx: &ImageContext) -> Detail {
+    if let (Some(a), Some(b)) = (fields(old, old_ctx), fields(new, new_ctx)) {
+        let mut changes = Vec::new();
+        for (i, (name, value)) in a.iter().enumerate() {
+            let other = b.get(i).filter(|(n, _)| n == name).map(|(_, v)| v);
+            if other != Some(value) {
+                changes.push(FieldChange {
+                    field: name.clone(),
+                    old: Some(value.clone()),
+                    new: other.cloned(),
+                });
+            }
+        }
+        for (name, value) in b.iter().skip(a.len()) {
+            changes.push(FieldChange {
+                field: name.clone(),
+                old: None,
+                new: Some(value.clone()),
+            });
+        }
+        // Data that differs only in ways the fields do not show, such as
+        // the image data behind an fdAT sequence number, is compared bytewise.
+        if !changes.is_empty() {
+            return Detail::Fields(changes);
+        }
+    }
+    let (a, b) = (old.data(), new.data());
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for i in 0..a.len().max(b.len()) {
+        if a.get(i) != b.get(i) {
+            match ranges.last_mut() {
+                Some((_, end)) if i - *end < MERGE_GAP => *end = i + 1,
+                _ => ranges.push((i, i + 1)),
+            }
+        }
+    }
+    Detail::Bytes {
+        old_length: a.len(),
+        new_length: b.len(),
+        ranges,
+    }
+}
+
+/// Compares the default images of `old` and `new` pixel by pixel, after
+/// decoding both to 16-bit RGBA so that different encodings of the same
+/// image compare equal.
+pub fn pixel_diff(old: &Png, new: &Png) -> Result<PixelDiff> {
+    let (a, b) = (Ihdr::from_png(old)?, Ihdr::from_png(new)?);
+    if (a.width, a.height) != (b.width, b.height) {
+        return Ok(PixelDiff::Dimensions {
+            old: (a.width, a.height),
+            new: (b.width, b.height),
+        });
+    }
+    let (old_pixels, new_pixels) = (reduce::rgba16(old)?, reduce::rgba16(new)?);
+    let mut differing = 0;
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    let mut max_delta = 0;
+    for (i, (p, q)) in old_pixels.iter().zip(&new_pixels).enumerate() {
+        if p == q {
+            continue;
+        }
+        differing += 1;
+        let (x, y) = ((i % a.width as usize) as u32, (i / a.width as usize) as u32);
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+            None => (x, y, x, y),
+        });
+        for c in 0..4 {
+            max_delta = max_delta.max(p[c].abs_diff(q[c]));
+        }
+    }
+    Ok(PixelDiff::Pixels {
+        differing,
+        bounds,
+        max_delta,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::pixels::RgbaImage;
+    use crate::png::tests::PNG_FILE;
+    use std::str::FromStr;
+
+    fn dice() -> Png {
+        Png::try_from(&PNG_FILE[..]).unwrap()
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let diff = diff(&dice(), &dice(), true).unwrap();
+        assert!(diff.changes.is_empty());
+        assert!(diff.is_identical());
+    }
+
+    #[test]
+    fn test_diff_added_removed_moved() {
+        let old = dice();
+        let mut new = dice();
+        new.remove_chunk("gAMA").unwrap();
+        let phys = new.remove_chunk("pHYs").unwrap();
+        new.remove_chunk("sRGB").unwrap();
+        new.append_chunk(Chunk::new(ChunkType::from_str("teXt").unwrap(), vec![1]));
+        new.insert_chunk(1, phys);
+        let srgb = old.chunk_by_type("sRGB").unwrap().clone();
+        new.insert_chunk(2, srgb);
+        // New order: IHDR pHYs sRGB IDAT RuSt IEND teXt
+        let diff = diff(&old, &new, false).unwrap();
+        assert_eq!(
+            diff.changes,
+            vec![
+                Change::Removed {
+                    index: 2,
+                    chunk_type: "gAMA".to_string()
+                },
+                Change::Added {
+                    index: 6,
+                    chunk_type: "teXt".to_string()
+                },
+                Change::Moved {
+                    from: 1,
+                    to: 2,
+                    chunk_type: "sRGB".to_string()
+                },
+            ]
+        );
+        assert!(!diff.is_identical());
+    }
+
+    #[test]
+    fn test_diff_fields_and_bytes() {
+        let mut old = dice();
+        let mut new = dice();
+        let gamma = Chunk::new(
+            ChunkType::from_str("gAMA").unwrap(),
+            50000u32.to_be_bytes().to_vec(),
+        );
+        new.replace_chunk(gamma);
+        let private = ChunkType::from_str("prIv").unwrap();
+        old.insert_chunk(6, Chunk::new(private.clone(), vec![0; 20]));
+        let mut data = vec![0; 21];
+        data[0] = 1;
+        data[3] = 1;
+        data[15] = 1;
+        data[20] = 1;
+        new.insert_chunk(6, Chunk::new(private, data));
+
+        let changes = diff(&old, &new, false).unwrap().changes;
+        assert_eq!(changes.len(), 2);
+        match &changes[0] {
+            Change::Modified {
+                detail: Detail::Fields(fields),
+                ..
+            } => {
+                assert_eq!(fields.len(), 1);
+                assert_eq!(fields[0].field, "gamma");
+                assert_eq!(fields[0].new.as_deref(), Some("0.50000"));
+            }
+            other => panic!("unexpected change {:?}", other),
+        }
+        assert_eq!(
+            changes[1],
+            Change::Modified {
+                from: 6,
+                to: 6,
+                chunk_type: "prIv".to_string(),
+                detail: Detail::Bytes {
+                    old_length: 20,
+                    new_length: 21,
+                    ranges: vec![(0, 4), (15, 21)],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_pixel_diff() {
+        let mut image = RgbaImage::new(4, 3);
+        let old = image.to_png().unwrap();
+        image.set_pixel(1, 0, [0, 0, 10, 0]);
+        image.set_pixel(2, 2, [0, 0, 0, 1]);
+        let new = image.to_png().unwrap();
+        assert_eq!(
+            pixel_diff(&old, &new).unwrap(),
+            PixelDiff::Pixels {
+                differing: 2,
+                bounds: Some((1, 0, 2, 2)),
+                max_delta: 2570,
+            }
+        );
+        let other = RgbaImage::new(3, 4).to_png().unwrap();
+        assert_eq!(
+            pixel_diff(&old, &other).unwrap(),
+            PixelDiff::Dimensions {
+                old: (4, 3),
+                new: (3, 4)
+            }
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+use crc32fast::Hasher;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum DumpErr {
+    NotPng,
+}
+impl std::error::Error for DumpErr {}
+impl Display for DumpErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            DumpErr::NotPng => write!(f, "File does not start with the png signature"),
+        }
+    }
+}
+
+/// A chunk as laid out in the file, read without any validation so that
+/// malformed files can still be inspected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawChunk<'a> {
+    /// Offset of the length field from the start of the file.
+    pub offset: usize,
+    pub length: u32,
+    pub type_bytes: [u8; 4],
+    /// The data actually present, shorter than `length` if the file is
+    /// truncated.
+    pub data: &'a [u8],
+    /// `None` if the file ends before the crc.
+    pub stored_crc: Option<u32>,
+}
+
+impl RawChunk<'_> {
+    pub fn type_name(&self) -> String {
+        String::from_utf8_lossy(&self.type_bytes).into_owned()
+    }
+
+    /// Offset of the first data byte from the start of the file.
+    pub fn data_offset(&self) -> usize {
+        self.offset + 8
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.stored_crc.is_none()
+    }
+
+    /// Crc over the type and the data present.
+    pub fn computed_crc(&self) -> u32 {
+        let mut hasher = Hasher::new();
+        hasher.update(&self.type_bytes);
+        hasher.update(self.data);
+        hasher.finalize()
+    }
+
+    pub fn crc_ok(&self) -> bool {
+        self.stored_crc == Some(self.computed_crc())
+    }
+
+    /// The chunk as PNGme models it, if its type is valid and it is whole.
+    pub fn to_chunk(&self) -> Option<Chunk> {
+        if self.is_truncated() {
+            return None;
+        }
+        let chunk_type = ChunkType::try_from(self.type_bytes).ok()?;
+        Some(Chunk::new(chunk_type, self.data.to_vec()))
+    }
+}
+
+/// Walks the chunks of a png file by their length fields alone. A chunk
+/// whose length runs past the end of the file is returned truncated and
+/// ends the walk; trailing bytes too short for a chunk header are ignored.
+pub fn raw_chunks(bytes: &[u8]) -> Result<Vec<RawChunk<'_>>> {
+    if bytes.len() < 8 || bytes[..8] != Png::STANDARD_HEADER {
+        return Err(Box::new(DumpErr::NotPng));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let type_bytes: [u8; 4] = bytes[offset + 4..offset + 8].try_into().unwrap();
+        let data_start = offset + 8;
+        let data_end = data_start.saturating_add(length as usize);
+        if data_end.saturating_add(4) > bytes.len() {
+            chunks.push(RawChunk {
+                offset,
+                length,
+                type_bytes,
+                data: &bytes[data_start..data_end.min(bytes.len())],
+                stored_crc: None,
+            });
+            break;
+        }
+        chunks.push(RawChunk {
+            offset,
+            length,
+            type_bytes,
+            data: &bytes[data_start..data_end],
+            stored_crc: Some(u32::from_be_bytes(
+                bytes[data_end..data_end + 4].try_into().unwrap(),
+            )),
+        });
+        offset = data_end + 4;
+    }
+    Ok(chunks)
+}
+
+/// Formats `data` as `xxd` does: the absolute offset, 16 bytes in hex and
+/// the same bytes as ASCII, with `.` for anything unprintable.
+pub fn hex_dump(data: &[u8], base_offset: usize) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let mut hex = String::with_capacity(49);
+            for (j, b) in row.iter().enumerate() {
+                if j == 8 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{:02x} ", b));
+            }
+            let ascii: String = row
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:08x}  {:<49} |{}|", base_offset + i * 16, hex, ascii)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_raw_chunks_match_parsed() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let raw = raw_chunks(&PNG_FILE).unwrap();
+        assert_eq!(raw.len(), png.chunks().len());
+        for (raw, chunk) in raw.iter().zip(png.chunks()) {
+            assert!(raw.crc_ok());
+            assert_eq!(raw.to_chunk().unwrap().as_bytes(), chunk.as_bytes());
+        }
+        assert_eq!(raw[0].offset, 8);
+        assert_eq!(raw[0].data_offset(), 16);
+    }
+
+    #[test]
+    fn test_raw_chunks_bad_crc() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[20] ^= 0xff;
+        let raw = raw_chunks(&bytes).unwrap();
+        assert!(!raw[0].crc_ok());
+        assert!(raw[1..].iter().all(|c| c.crc_ok()));
+    }
+
+    #[test]
+    fn test_raw_chunks_truncated() {
+        let bytes = &PNG_FILE[..1000];
+        let raw = raw_chunks(bytes).unwrap();
+        let last = raw.last().unwrap();
+        assert!(last.is_truncated());
+        assert!(last.data.len() < last.length as usize);
+        assert!(last.to_chunk().is_none());
+    }
+
+    #[test]
+    fn test_raw_chunks_not_png() {
+        assert!(raw_chunks(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let lines = hex_dump(b"\x89PNG\r\n\x1a\n0123456789abcdef", 0x10);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "00000010  89 50 4e 47 0d 0a 1a 0a  30 31 32 33 34 35 36 37  |.PNG....01234567|"
+        );
+        assert!(lines[1].starts_with("00000020  38 39 61 62"));
+        assert!(lines[1].ends_with("|89abcdef|"));
+    }
+}
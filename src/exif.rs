@@ -0,0 +1,902 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::{Error, Result};
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum ExifErr {
+    WrongChunkType(String),
+    InvalidHeader,
+    OutOfBounds(usize),
+    UnknownFieldType(u16),
+    IfdLoop(usize),
+    IfdTooDeep(usize),
+    UnknownEntries(usize),
+    UnknownTag(String),
+    InvalidValue(String),
+}
+impl std::error::Error for ExifErr {}
+impl Display for ExifErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ExifErr::WrongChunkType(t) => write!(f, "Unexpected chunk type {}", t),
+            ExifErr::InvalidHeader => write!(f, "eXIf data does not start with a TIFF header"),
+            ExifErr::OutOfBounds(offset) => {
+                write!(f, "eXIf offset {} points outside of the chunk", offset)
+            }
+            ExifErr::UnknownFieldType(t) => write!(f, "Unknown TIFF field type {}", t),
+            ExifErr::IfdLoop(offset) => write!(f, "IFD at offset {} is referenced twice", offset),
+            ExifErr::IfdTooDeep(offset) => {
+                write!(f, "IFD at offset {} is nested too deeply", offset)
+            }
+            ExifErr::UnknownEntries(n) => write!(
+                f,
+                "eXIf has {} entries of unknown type that rewriting it would lose",
+                n
+            ),
+            ExifErr::UnknownTag(t) => write!(f, "Unknown EXIF tag {}", t),
+            ExifErr::InvalidValue(v) => write!(f, "Invalid value {} for tag", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+    fn u16_bytes(self, v: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => v.to_le_bytes(),
+            ByteOrder::BigEndian => v.to_be_bytes(),
+        }
+    }
+    fn u32_bytes(self, v: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => v.to_le_bytes(),
+            ByteOrder::BigEndian => v.to_be_bytes(),
+        }
+    }
+}
+
+/// Which IFD a tag lives in. GPS tag ids overlap the others, so a tag is only
+/// identified by its id together with its section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Image,
+    Exif,
+    Gps,
+}
+
+pub const BYTE: u16 = 1;
+pub const ASCII: u16 = 2;
+pub const SHORT: u16 = 3;
+pub const LONG: u16 = 4;
+pub const RATIONAL: u16 = 5;
+pub const UNDEFINED: u16 = 7;
+pub const SRATIONAL: u16 = 10;
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const INTEROP_IFD_POINTER: u16 = 0xA005;
+const STRIP_OFFSETS: u16 = 0x0111;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+/// Real EXIF nests at most two deep, IFD0 holding the Exif IFD holding the
+/// interoperability IFD. Crafted chains beyond this would exhaust the stack.
+const MAX_IFD_DEPTH: usize = 4;
+
+pub const ORIENTATION: u16 = 0x0112;
+pub const MAKE: u16 = 0x010F;
+pub const MODEL: u16 = 0x0110;
+pub const DATE_TIME: u16 = 0x0132;
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+pub const GPS_LATITUDE_REF: u16 = 0x0001;
+pub const GPS_LATITUDE: u16 = 0x0002;
+pub const GPS_LONGITUDE_REF: u16 = 0x0003;
+pub const GPS_LONGITUDE: u16 = 0x0004;
+
+/// Well known tags: id, name, section and the field type used when setting it.
+const TAGS: [(u16, &str, Section, u16); 29] = [
+    (0x010E, "ImageDescription", Section::Image, ASCII),
+    (MAKE, "Make", Section::Image, ASCII),
+    (MODEL, "Model", Section::Image, ASCII),
+    (ORIENTATION, "Orientation", Section::Image, SHORT),
+    (0x011A, "XResolution", Section::Image, RATIONAL),
+    (0x011B, "YResolution", Section::Image, RATIONAL),
+    (0x0128, "ResolutionUnit", Section::Image, SHORT),
+    (0x0131, "Software", Section::Image, ASCII),
+    (DATE_TIME, "DateTime", Section::Image, ASCII),
+    (0x013B, "Artist", Section::Image, ASCII),
+    (0x8298, "Copyright", Section::Image, ASCII),
+    (0x829A, "ExposureTime", Section::Exif, RATIONAL),
+    (0x829D, "FNumber", Section::Exif, RATIONAL),
+    (0x8827, "ISOSpeedRatings", Section::Exif, SHORT),
+    (0x9000, "ExifVersion", Section::Exif, UNDEFINED),
+    (DATE_TIME_ORIGINAL, "DateTimeOriginal", Section::Exif, ASCII),
+    (0x9004, "DateTimeDigitized", Section::Exif, ASCII),
+    (0x920A, "FocalLength", Section::Exif, RATIONAL),
+    (0xA002, "PixelXDimension", Section::Exif, LONG),
+    (0xA003, "PixelYDimension", Section::Exif, LONG),
+    (0xA434, "LensModel", Section::Exif, ASCII),
+    (0x0000, "GPSVersionID", Section::Gps, BYTE),
+    (GPS_LATITUDE_REF, "GPSLatitudeRef", Section::Gps, ASCII),
+    (GPS_LATITUDE, "GPSLatitude", Section::Gps, RATIONAL),
+    (GPS_LONGITUDE_REF, "GPSLongitudeRef", Section::Gps, ASCII),
+    (GPS_LONGITUDE, "GPSLongitude", Section::Gps, RATIONAL),
+    (0x0005, "GPSAltitudeRef", Section::Gps, BYTE),
+    (0x0006, "GPSAltitude", Section::Gps, RATIONAL),
+    (0x001D, "GPSDateStamp", Section::Gps, ASCII),
+];
+
+pub fn tag_name(section: Section, tag: u16) -> Option<&'static str> {
+    TAGS.iter()
+        .find(|(id, _, s, _)| *id == tag && *s == section)
+        .map(|(_, name, _, _)| *name)
+}
+
+/// Looks a tag up by name, returning its id, section and field type.
+pub fn tag_by_name(name: &str) -> Result<(u16, Section, u16)> {
+    TAGS.iter()
+        .find(|(_, n, _, _)| n.eq_ignore_ascii_case(name))
+        .map(|(id, _, section, field_type)| (*id, *section, *field_type))
+        .ok_or_else(|| Box::new(ExifErr::UnknownTag(name.to_string())) as Error)
+}
+
+fn field_type_size(field_type: u16) -> Result<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Ok(1),
+        3 | 8 => Ok(2),
+        4 | 9 | 11 => Ok(4),
+        5 | 10 | 12 => Ok(8),
+        _ => Err(Box::new(ExifErr::UnknownFieldType(field_type))),
+    }
+}
+
+/// A decoded field value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Ascii(String),
+    Unsigned(Vec<u32>),
+    Signed(Vec<i32>),
+    Rational(Vec<(u32, u32)>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f64>),
+    Undefined(Vec<u8>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Ascii(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Unsigned(v) => v.first().copied(),
+            _ => None,
+        }
+    }
+    pub fn as_f64s(&self) -> Option<Vec<f64>> {
+        match self {
+            Value::Rational(v) => Some(
+                v.iter()
+                    .map(|&(n, d)| if d == 0 { 0.0 } else { n as f64 / d as f64 })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        fn join<T: Display>(values: &[T]) -> String {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        match self {
+            Value::Ascii(s) => write!(f, "{}", s),
+            Value::Unsigned(v) => write!(f, "{}", join(v)),
+            Value::Signed(v) => write!(f, "{}", join(v)),
+            Value::Rational(v) => write!(
+                f,
+                "{}",
+                join(
+                    &v.iter()
+                        .map(|(n, d)| format!("{}/{}", n, d))
+                        .collect::<Vec<_>>()
+                )
+            ),
+            Value::SRational(v) => write!(
+                f,
+                "{}",
+                join(
+                    &v.iter()
+                        .map(|(n, d)| format!("{}/{}", n, d))
+                        .collect::<Vec<_>>()
+                )
+            ),
+            Value::Float(v) => write!(f, "{}", join(v)),
+            Value::Undefined(v) if v.len() <= 16 => write!(f, "{:?}", v),
+            Value::Undefined(v) => write!(f, "<{} bytes>", v.len()),
+        }
+    }
+}
+
+/// A single IFD entry. `data` holds the raw value bytes in the file's byte
+/// order so entries round-trip untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+impl Entry {
+    pub fn value(&self, order: ByteOrder) -> Value {
+        let d = &self.data;
+        match self.field_type {
+            ASCII => Value::Ascii(
+                String::from_utf8_lossy(d)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            ),
+            BYTE => Value::Unsigned(d.iter().map(|&b| b as u32).collect()),
+            SHORT => Value::Unsigned(d.chunks_exact(2).map(|b| order.u16(b) as u32).collect()),
+            LONG => Value::Unsigned(d.chunks_exact(4).map(|b| order.u32(b)).collect()),
+            RATIONAL => Value::Rational(
+                d.chunks_exact(8)
+                    .map(|b| (order.u32(&b[..4]), order.u32(&b[4..])))
+                    .collect(),
+            ),
+            6 => Value::Signed(d.iter().map(|&b| b as i8 as i32).collect()),
+            8 => Value::Signed(
+                d.chunks_exact(2)
+                    .map(|b| order.u16(b) as i16 as i32)
+                    .collect(),
+            ),
+            9 => Value::Signed(d.chunks_exact(4).map(|b| order.u32(b) as i32).collect()),
+            SRATIONAL => Value::SRational(
+                d.chunks_exact(8)
+                    .map(|b| (order.u32(&b[..4]) as i32, order.u32(&b[4..]) as i32))
+                    .collect(),
+            ),
+            11 => Value::Float(
+                d.chunks_exact(4)
+                    .map(|b| f32::from_bits(order.u32(b)) as f64)
+                    .collect(),
+            ),
+            12 => Value::Float(
+                d.chunks_exact(8)
+                    .map(|b| {
+                        let (hi, lo) = match order {
+                            ByteOrder::LittleEndian => (order.u32(&b[4..]), order.u32(&b[..4])),
+                            ByteOrder::BigEndian => (order.u32(&b[..4]), order.u32(&b[4..])),
+                        };
+                        f64::from_bits(((hi as u64) << 32) | lo as u64)
+                    })
+                    .collect(),
+            ),
+            _ => Value::Undefined(d.clone()),
+        }
+    }
+
+    /// Builds an entry of `field_type` from its textual form. Rationals are
+    /// written as `num/den` and multiple values are comma separated.
+    pub fn parse(tag: u16, field_type: u16, s: &str, order: ByteOrder) -> Result<Self> {
+        let invalid = || Box::new(ExifErr::InvalidValue(s.to_string())) as Error;
+        let parts = || s.split(',').map(str::trim);
+        let (count, data) = match field_type {
+            ASCII => {
+                let mut data = s.as_bytes().to_vec();
+                data.push(0);
+                (data.len(), data)
+            }
+            BYTE | UNDEFINED => {
+                let data = parts()
+                    .map(|p| p.parse::<u8>().map_err(|_| invalid()))
+                    .collect::<Result<Vec<_>>>()?;
+                (data.len(), data)
+            }
+            SHORT => {
+                let values = parts()
+                    .map(|p| p.parse::<u16>().map_err(|_| invalid()))
+                    .collect::<Result<Vec<_>>>()?;
+                (
+                    values.len(),
+                    values.iter().flat_map(|&v| order.u16_bytes(v)).collect(),
+                )
+            }
+            LONG => {
+                let values = parts()
+                    .map(|p| p.parse::<u32>().map_err(|_| invalid()))
+                    .collect::<Result<Vec<_>>>()?;
+                (
+                    values.len(),
+                    values.iter().flat_map(|&v| order.u32_bytes(v)).collect(),
+                )
+            }
+            RATIONAL => {
+                let values = parts()
+                    .map(|p| {
+                        let (n, d) = p.split_once('/').unwrap_or((p, "1"));
+                        Ok((
+                            n.parse::<u32>().map_err(|_| invalid())?,
+                            d.parse::<u32>().map_err(|_| invalid())?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                (
+                    values.len(),
+                    values
+                        .iter()
+                        .flat_map(|&(n, d)| {
+                            order
+                                .u32_bytes(n)
+                                .iter()
+                                .chain(order.u32_bytes(d).iter())
+                                .copied()
+                                .collect::<Vec<_>>()
+                        })
+                        .collect(),
+                )
+            }
+            t => return Err(Box::new(ExifErr::UnknownFieldType(t))),
+        };
+        Ok(Self {
+            tag,
+            field_type,
+            count: count as u32,
+            data,
+        })
+    }
+}
+
+/// An image file directory. Pointers to the Exif, GPS and interoperability
+/// IFDs are resolved into `sub_ifds`, and a JPEG thumbnail referenced by IFD1
+/// is carried as bytes, so the offsets can be recomputed on write.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ifd {
+    pub entries: Vec<Entry>,
+    pub sub_ifds: Vec<(u16, Ifd)>,
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+impl Ifd {
+    pub fn entry(&self, tag: u16) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+    pub fn sub_ifd(&self, pointer: u16) -> Option<&Ifd> {
+        self.sub_ifds
+            .iter()
+            .find(|(p, _)| *p == pointer)
+            .map(|(_, ifd)| ifd)
+    }
+    fn sub_ifd_mut(&mut self, pointer: u16) -> &mut Ifd {
+        let idx = match self.sub_ifds.iter().position(|(p, _)| *p == pointer) {
+            Some(idx) => idx,
+            None => {
+                self.sub_ifds.push((pointer, Ifd::default()));
+                self.sub_ifds.len() - 1
+            }
+        };
+        &mut self.sub_ifds[idx].1
+    }
+    fn set(&mut self, entry: Entry) {
+        match self.entries.iter_mut().find(|e| e.tag == entry.tag) {
+            Some(e) => *e = entry,
+            None => self.entries.push(entry),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    order: ByteOrder,
+    visited: HashSet<usize>,
+    /// Entries skipped for having a field type this reader doesn't know.
+    unknown_entries: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| Box::new(ExifErr::OutOfBounds(offset)) as Error)
+    }
+
+    /// Reads the IFD at `offset`, nested `depth` pointers below IFD0 or IFD1,
+    /// returning it with the offset of the next IFD.
+    fn ifd(&mut self, offset: usize, depth: usize) -> Result<(Ifd, usize)> {
+        if depth > MAX_IFD_DEPTH {
+            return Err(Box::new(ExifErr::IfdTooDeep(offset)));
+        }
+        if !self.visited.insert(offset) {
+            return Err(Box::new(ExifErr::IfdLoop(offset)));
+        }
+        let order = self.order;
+        let count = order.u16(self.slice(offset, 2)?) as usize;
+        let mut ifd = Ifd::default();
+        let mut thumbnail_offset = None;
+        for i in 0..count {
+            let raw = self.slice(offset + 2 + 12 * i, 12)?;
+            let tag = order.u16(&raw[..2]);
+            let field_type = order.u16(&raw[2..4]);
+            let count = order.u32(&raw[4..8]);
+            // Entries of types added after TIFF 6 are skipped rather than
+            // failing the whole IFD. Their size is unknown, so they cannot
+            // be carried over to a rewrite.
+            let size = match field_type_size(field_type) {
+                Ok(size) => size,
+                Err(_) => {
+                    self.unknown_entries += 1;
+                    continue;
+                }
+            };
+            let len = size
+                .checked_mul(count as usize)
+                .ok_or(ExifErr::OutOfBounds(offset))?;
+            let data = if len <= 4 {
+                raw[8..8 + len].to_vec()
+            } else {
+                self.slice(order.u32(&raw[8..]) as usize, len)?.to_vec()
+            };
+            match tag {
+                EXIF_IFD_POINTER | GPS_IFD_POINTER | INTEROP_IFD_POINTER => {
+                    let (sub, _) = self.ifd(order.u32(&raw[8..]) as usize, depth + 1)?;
+                    ifd.sub_ifds.push((tag, sub));
+                }
+                THUMBNAIL_OFFSET => thumbnail_offset = Some(order.u32(&raw[8..]) as usize),
+                _ => ifd.entries.push(Entry {
+                    tag,
+                    field_type,
+                    count,
+                    data,
+                }),
+            }
+        }
+        if let Some(thumbnail_offset) = thumbnail_offset {
+            let len = ifd
+                .entry(THUMBNAIL_LENGTH)
+                .and_then(|e| e.value(order).as_u32())
+                .unwrap_or(0) as usize;
+            ifd.thumbnail = Some(self.slice(thumbnail_offset, len)?.to_vec());
+        }
+        let next = order.u32(self.slice(offset + 2 + 12 * count, 4)?) as usize;
+        Ok((ifd, next))
+    }
+}
+
+/// Parsed contents of an `eXIf` chunk: IFD0 with its Exif and GPS sub-IFDs,
+/// and the optional thumbnail IFD1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exif {
+    order: ByteOrder,
+    ifd0: Ifd,
+    ifd1: Option<Ifd>,
+    unknown_entries: usize,
+}
+
+impl Exif {
+    pub fn new(order: ByteOrder) -> Self {
+        Self {
+            order,
+            ifd0: Ifd::default(),
+            ifd1: None,
+            unknown_entries: 0,
+        }
+    }
+    pub fn byte_order(&self) -> ByteOrder {
+        self.order
+    }
+    pub fn ifd0(&self) -> &Ifd {
+        &self.ifd0
+    }
+    pub fn ifd1(&self) -> Option<&Ifd> {
+        self.ifd1.as_ref()
+    }
+
+    fn section(&self, section: Section) -> Option<&Ifd> {
+        match section {
+            Section::Image => Some(&self.ifd0),
+            Section::Exif => self.ifd0.sub_ifd(EXIF_IFD_POINTER),
+            Section::Gps => self.ifd0.sub_ifd(GPS_IFD_POINTER),
+        }
+    }
+
+    pub fn get(&self, section: Section, tag: u16) -> Option<Value> {
+        self.section(section)
+            .and_then(|ifd| ifd.entry(tag))
+            .map(|e| e.value(self.order))
+    }
+
+    pub fn set(&mut self, section: Section, entry: Entry) {
+        match section {
+            Section::Image => self.ifd0.set(entry),
+            Section::Exif => self.ifd0.sub_ifd_mut(EXIF_IFD_POINTER).set(entry),
+            Section::Gps => self.ifd0.sub_ifd_mut(GPS_IFD_POINTER).set(entry),
+        }
+    }
+
+    /// Sets a well known tag by name from its textual value.
+    pub fn set_tag(&mut self, name: &str, value: &str) -> Result<()> {
+        let (tag, section, field_type) = tag_by_name(name)?;
+        let entry = Entry::parse(tag, field_type, value, self.order)?;
+        self.set(section, entry);
+        Ok(())
+    }
+
+    pub fn orientation(&self) -> Option<u32> {
+        self.get(Section::Image, ORIENTATION)
+            .and_then(|v| v.as_u32())
+    }
+    pub fn make(&self) -> Option<String> {
+        self.ascii(Section::Image, MAKE)
+    }
+    pub fn model(&self) -> Option<String> {
+        self.ascii(Section::Image, MODEL)
+    }
+    pub fn date_time(&self) -> Option<String> {
+        self.ascii(Section::Image, DATE_TIME)
+    }
+    pub fn date_time_original(&self) -> Option<String> {
+        self.ascii(Section::Exif, DATE_TIME_ORIGINAL)
+    }
+    fn ascii(&self, section: Section, tag: u16) -> Option<String> {
+        self.get(section, tag)
+            .and_then(|v| v.as_str().map(String::from))
+    }
+
+    /// Returns the GPS position as signed decimal (latitude, longitude).
+    pub fn gps_coordinates(&self) -> Option<(f64, f64)> {
+        let coordinate = |tag, ref_tag, negative| {
+            let dms = self.get(Section::Gps, tag)?.as_f64s()?;
+            let value = dms
+                .iter()
+                .zip([1.0, 60.0, 3600.0])
+                .map(|(v, d)| v / d)
+                .sum::<f64>();
+            match self.ascii(Section::Gps, ref_tag) {
+                Some(r) if r == negative => Some(-value),
+                _ => Some(value),
+            }
+        };
+        Some((
+            coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, "S")?,
+            coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W")?,
+        ))
+    }
+
+    /// Number of entries left out of [`Exif::entries`] because their field
+    /// type is unknown.
+    pub fn unknown_entries(&self) -> usize {
+        self.unknown_entries
+    }
+    /// Fails if [`Exif::as_bytes`] would lose entries of unknown type, so
+    /// that edits refuse rather than drop them.
+    pub fn check_rewritable(&self) -> Result<()> {
+        match self.unknown_entries {
+            0 => Ok(()),
+            n => Err(Box::new(ExifErr::UnknownEntries(n))),
+        }
+    }
+
+    pub fn has_gps(&self) -> bool {
+        self.ifd0.sub_ifd(GPS_IFD_POINTER).is_some()
+    }
+
+    /// Drops the GPS IFD, returning whether there was one.
+    pub fn remove_gps(&mut self) -> bool {
+        let before = self.ifd0.sub_ifds.len();
+        self.ifd0.sub_ifds.retain(|(p, _)| *p != GPS_IFD_POINTER);
+        before != self.ifd0.sub_ifds.len()
+    }
+
+    /// Every entry with its section, in file order.
+    pub fn entries(&self) -> Vec<(Section, &Entry)> {
+        [Section::Image, Section::Exif, Section::Gps]
+            .iter()
+            .filter_map(|&s| self.section(s).map(|ifd| (s, ifd)))
+            .flat_map(|(s, ifd)| ifd.entries.iter().map(move |e| (s, e)))
+            .collect()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut out = match self.order {
+            ByteOrder::LittleEndian => b"II".to_vec(),
+            ByteOrder::BigEndian => b"MM".to_vec(),
+        };
+        out.extend(self.order.u16_bytes(42));
+        out.extend(self.order.u32_bytes(8));
+        let ifd0 = self.write_ifd(&self.ifd0, &mut out);
+        if let Some(ifd1) = &self.ifd1 {
+            let next = self.write_ifd(ifd1, &mut out) as u32;
+            let at = ifd0 + 2 + 12 * self.field_count(&self.ifd0);
+            out[at..at + 4].copy_from_slice(&self.order.u32_bytes(next));
+        }
+        out
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str("eXIf").unwrap(), self.as_bytes())
+    }
+
+    fn field_count(&self, ifd: &Ifd) -> usize {
+        ifd.entries.len() + ifd.sub_ifds.len() + ifd.thumbnail.is_some() as usize
+    }
+
+    /// Writes `ifd` at the end of `out` followed by its out of line values and
+    /// sub-IFDs, returning the offset it was written at.
+    fn write_ifd(&self, ifd: &Ifd, out: &mut Vec<u8>) -> usize {
+        enum Field<'a> {
+            Entry(&'a Entry),
+            SubIfd(u16, &'a Ifd),
+            Thumbnail(&'a [u8]),
+        }
+        let tag = |f: &Field| match f {
+            Field::Entry(e) => e.tag,
+            Field::SubIfd(p, _) => *p,
+            Field::Thumbnail(_) => THUMBNAIL_OFFSET,
+        };
+        let mut fields: Vec<Field> = ifd
+            .entries
+            .iter()
+            .map(Field::Entry)
+            .chain(ifd.sub_ifds.iter().map(|(p, s)| Field::SubIfd(*p, s)))
+            .chain(ifd.thumbnail.as_deref().map(Field::Thumbnail))
+            .collect();
+        fields.sort_by_key(tag);
+
+        let order = self.order;
+        if out.len() % 2 == 1 {
+            out.push(0);
+        }
+        let start = out.len();
+        out.extend(order.u16_bytes(fields.len() as u16));
+        out.resize(start + 2 + 12 * fields.len() + 4, 0);
+        for (i, field) in fields.iter().enumerate() {
+            let (field_type, count, value) = match field {
+                Field::Entry(e) if e.data.len() <= 4 => {
+                    let mut value = [0; 4];
+                    value[..e.data.len()].copy_from_slice(&e.data);
+                    (e.field_type, e.count, value)
+                }
+                Field::Entry(e) => {
+                    if out.len() % 2 == 1 {
+                        out.push(0);
+                    }
+                    let offset = out.len() as u32;
+                    out.extend(&e.data);
+                    (e.field_type, e.count, order.u32_bytes(offset))
+                }
+                Field::SubIfd(_, sub) => {
+                    let offset = self.write_ifd(sub, out) as u32;
+                    (LONG, 1, order.u32_bytes(offset))
+                }
+                Field::Thumbnail(data) => {
+                    let offset = out.len() as u32;
+                    out.extend(*data);
+                    (LONG, 1, order.u32_bytes(offset))
+                }
+            };
+            let at = start + 2 + 12 * i;
+            out[at..at + 2].copy_from_slice(&order.u16_bytes(tag(field)));
+            out[at + 2..at + 4].copy_from_slice(&order.u16_bytes(field_type));
+            out[at + 4..at + 8].copy_from_slice(&order.u32_bytes(count));
+            out[at + 8..at + 12].copy_from_slice(&value);
+        }
+        start
+    }
+}
+impl TryFrom<&[u8]> for Exif {
+    type Error = Error;
+    fn try_from(data: &[u8]) -> Result<Self> {
+        let order = match data.get(..4) {
+            Some(b"II\x2a\x00") => ByteOrder::LittleEndian,
+            Some(b"MM\x00\x2a") => ByteOrder::BigEndian,
+            _ => return Err(Box::new(ExifErr::InvalidHeader)),
+        };
+        let mut reader = Reader {
+            data,
+            order,
+            visited: HashSet::new(),
+            unknown_entries: 0,
+        };
+        let offset = order.u32(reader.slice(4, 4)?) as usize;
+        let (ifd0, next) = reader.ifd(offset, 0)?;
+        let ifd1 = match next {
+            0 => None,
+            next => Some(reader.ifd(next, 0)?.0),
+        };
+        // Uncompressed thumbnails reference their strips by offset, which
+        // are not tracked, so such an IFD1 is dropped rather than corrupted.
+        let ifd1 = ifd1.filter(|ifd| ifd.entry(STRIP_OFFSETS).is_none());
+        Ok(Self {
+            order,
+            ifd0,
+            ifd1,
+            unknown_entries: reader.unknown_entries,
+        })
+    }
+}
+impl TryFrom<&Chunk> for Exif {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if chunk.chunk_type().to_string() != "eXIf" {
+            return Err(Box::new(ExifErr::WrongChunkType(
+                chunk.chunk_type().to_string(),
+            )));
+        }
+        Exif::try_from(chunk.data())
+    }
+}
+impl Display for Exif {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let entries = self.entries();
+        for (i, (section, entry)) in entries.iter().enumerate() {
+            match tag_name(*section, entry.tag) {
+                Some(name) => write!(f, "{}: {}", name, entry.value(self.order))?,
+                None => write!(
+                    f,
+                    "{:?} tag 0x{:04x}: {}",
+                    section,
+                    entry.tag,
+                    entry.value(self.order)
+                )?,
+            }
+            if i + 1 < entries.len() {
+                writeln!(f)?;
+            }
+        }
+        if let Some((lat, lon)) = self.gps_coordinates() {
+            write!(f, "\nGPS position: {:.6}, {:.6}", lat, lon)?;
+        }
+        if let Some(thumbnail) = self.ifd1.as_ref().and_then(|i| i.thumbnail.as_ref()) {
+            write!(f, "\nThumbnail: {} bytes", thumbnail.len())?;
+        }
+        if self.unknown_entries > 0 {
+            write!(f, "\n{} entries of unknown type", self.unknown_entries)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_exif(order: ByteOrder) -> Exif {
+        let mut exif = Exif::new(order);
+        exif.set_tag("Make", "Canon").unwrap();
+        exif.set_tag("Model", "EOS 5D Mark IV").unwrap();
+        exif.set_tag("Orientation", "6").unwrap();
+        exif.set_tag("DateTimeOriginal", "2021:10:19 12:00:00")
+            .unwrap();
+        exif.set_tag("GPSLatitudeRef", "S").unwrap();
+        exif.set_tag("GPSLatitude", "33/1,52/1,1800/100").unwrap();
+        exif.set_tag("GPSLongitudeRef", "E").unwrap();
+        exif.set_tag("GPSLongitude", "151/1,12/1,36/1").unwrap();
+        exif
+    }
+
+    #[test]
+    fn test_exif_roundtrip() {
+        for &order in [ByteOrder::LittleEndian, ByteOrder::BigEndian].iter() {
+            let exif = testing_exif(order);
+            let parsed = Exif::try_from(&exif.to_chunk()).unwrap();
+            assert_eq!(parsed.make().as_deref(), Some("Canon"));
+            assert_eq!(parsed.model().as_deref(), Some("EOS 5D Mark IV"));
+            assert_eq!(parsed.orientation(), Some(6));
+            assert_eq!(
+                parsed.date_time_original().as_deref(),
+                Some("2021:10:19 12:00:00")
+            );
+            assert_eq!(parsed.as_bytes(), exif.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_gps_coordinates() {
+        let exif = testing_exif(ByteOrder::BigEndian);
+        let (lat, lon) = exif.gps_coordinates().unwrap();
+        assert!((lat + 33.871_667).abs() < 1e-5);
+        assert!((lon - 151.21).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_remove_gps() {
+        let mut exif = testing_exif(ByteOrder::LittleEndian);
+        assert!(exif.remove_gps());
+        let parsed = Exif::try_from(&exif.as_bytes()[..]).unwrap();
+        assert!(!parsed.has_gps());
+        assert!(parsed.gps_coordinates().is_none());
+        assert_eq!(parsed.make().as_deref(), Some("Canon"));
+    }
+
+    #[test]
+    fn test_thumbnail_preserved() {
+        let mut exif = testing_exif(ByteOrder::LittleEndian);
+        let mut ifd1 = Ifd::default();
+        ifd1.entries
+            .push(Entry::parse(THUMBNAIL_LENGTH, LONG, "5", exif.order).unwrap());
+        ifd1.thumbnail = Some(vec![0xff, 0xd8, 1, 2, 3]);
+        exif.ifd1 = Some(ifd1);
+        let parsed = Exif::try_from(&exif.as_bytes()[..]).unwrap();
+        assert_eq!(
+            parsed.ifd1().unwrap().thumbnail.as_deref(),
+            Some(&[0xff, 0xd8, 1, 2, 3][..])
+        );
+    }
+
+    #[test]
+    fn test_invalid_exif() {
+        assert!(Exif::try_from(&b"JUNK\x00\x00\x00\x08"[..]).is_err());
+        assert!(Exif::try_from(&b"II\x2a\x00\xff\x00\x00\x00"[..]).is_err());
+        // IFD0 whose next pointer refers back to itself.
+        let looping = b"II\x2a\x00\x08\x00\x00\x00\x00\x00\x08\x00\x00\x00";
+        assert!(Exif::try_from(&looping[..]).is_err());
+    }
+
+    #[test]
+    fn test_ifd_nesting_limit() {
+        // Each IFD holds one Exif IFD pointer to the next, 18 bytes on.
+        let mut bytes = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+        for i in 0..64u32 {
+            let next = 8 + 18 * (i + 1);
+            bytes.extend(&[1, 0, 0x69, 0x87, 4, 0, 1, 0, 0, 0]);
+            bytes.extend(&next.to_le_bytes());
+            bytes.extend(&[0; 4]);
+        }
+        bytes.extend(&[0; 6]);
+        let err = Exif::try_from(&bytes[..]).unwrap_err();
+        assert!(err.to_string().contains("nested too deeply"));
+    }
+
+    #[test]
+    fn test_unknown_field_type_skipped() {
+        // IFD0 with an entry of type 99 followed by Orientation = 6.
+        let bytes = b"II\x2a\x00\x08\x00\x00\x00\x02\x00\
+            \x99\x99\x63\x00\x01\x00\x00\x00\x00\x00\x00\x00\
+            \x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00\
+            \x00\x00\x00\x00";
+        let exif = Exif::try_from(&bytes[..]).unwrap();
+        assert_eq!(exif.orientation(), Some(6));
+        assert_eq!(exif.entries().len(), 1);
+        assert_eq!(exif.unknown_entries(), 1);
+        assert!(exif.check_rewritable().is_err());
+    }
+
+    #[test]
+    fn test_unknown_tag() {
+        let mut exif = Exif::new(ByteOrder::LittleEndian);
+        assert!(exif.set_tag("NotATag", "1").is_err());
+        assert!(exif.set_tag("Orientation", "sideways").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngErr};
+use crate::{Error, Result};
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum IhdrErr {
+    WrongChunkType(String),
+    InvalidLength(usize),
+    InvalidColorType(u8),
+    InvalidBitDepth(ColorType, u8),
+    ZeroDimension,
+    UnknownMethod(&'static str, u8),
+}
+impl std::error::Error for IhdrErr {}
+impl Display for IhdrErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            IhdrErr::WrongChunkType(t) => write!(f, "Expected IHDR chunk, got {}", t),
+            IhdrErr::InvalidLength(len) => write!(f, "IHDR must be 13 bytes, got {}", len),
+            IhdrErr::InvalidColorType(c) => write!(f, "Invalid color type {}", c),
+            IhdrErr::InvalidBitDepth(c, d) => {
+                write!(f, "Bit depth {} not allowed for color type {}", d, c)
+            }
+            IhdrErr::ZeroDimension => write!(f, "Image width and height must be non zero"),
+            IhdrErr::UnknownMethod(what, m) => write!(f, "Unknown {} method {}", what, m),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// Number of samples per pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            _ => &[8, 16],
+        }
+    }
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+}
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(Box::new(IhdrErr::InvalidColorType(value))),
+        }
+    }
+}
+impl From<ColorType> for u8 {
+    fn from(color_type: ColorType) -> Self {
+        match color_type {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+}
+impl Display for ColorType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ColorType::Grayscale => write!(f, "grayscale"),
+            ColorType::Rgb => write!(f, "rgb"),
+            ColorType::Indexed => write!(f, "indexed"),
+            ColorType::GrayscaleAlpha => write!(f, "grayscale+alpha"),
+            ColorType::Rgba => write!(f, "rgba"),
+        }
+    }
+}
+
+/// Image header from the `IHDR` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl Ihdr {
+    pub fn new(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> Result<Self> {
+        let ihdr = Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let chunk = png
+            .chunk_by_type("IHDR")
+            .ok_or(PngErr::PngChunkTypeAbsent)?;
+        let ihdr = Ihdr::try_from(chunk)?;
+        png.limits().check_dimensions(ihdr.width, ihdr.height)?;
+        Ok(ihdr)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(Box::new(IhdrErr::ZeroDimension));
+        }
+        if !self
+            .color_type
+            .allowed_bit_depths()
+            .contains(&self.bit_depth)
+        {
+            return Err(Box::new(IhdrErr::InvalidBitDepth(
+                self.color_type,
+                self.bit_depth,
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes in one unfiltered scanline of `width` pixels, excluding the
+    /// filter type byte.
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(13);
+        data.extend(self.width.to_be_bytes());
+        data.extend(self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(u8::from(self.color_type));
+        data.push(0);
+        data.push(0);
+        data.push(self.interlaced as u8);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+}
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if chunk.chunk_type().to_string() != "IHDR" {
+            return Err(Box::new(IhdrErr::WrongChunkType(
+                chunk.chunk_type().to_string(),
+            )));
+        }
+        let data = chunk.data();
+        if data.len() != 13 {
+            return Err(Box::new(IhdrErr::InvalidLength(data.len())));
+        }
+        let (compression, filter, interlace) = (data[10], data[11], data[12]);
+        if compression != 0 {
+            return Err(Box::new(IhdrErr::UnknownMethod("compression", compression)));
+        }
+        if filter != 0 {
+            return Err(Box::new(IhdrErr::UnknownMethod("filter", filter)));
+        }
+        if interlace > 1 {
+            return Err(Box::new(IhdrErr::UnknownMethod("interlace", interlace)));
+        }
+        let ihdr = Self {
+            width: u32::from_be_bytes(data[0..4].try_into()?),
+            height: u32::from_be_bytes(data[4..8].try_into()?),
+            bit_depth: data[8],
+            color_type: ColorType::try_from(data[9])?,
+            interlaced: interlace == 1,
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+}
+impl Display for Ihdr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}x{} {}-bit {}{}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            if self.interlaced { " interlaced" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ihdr_roundtrip() {
+        let ihdr = Ihdr::new(50, 40, 8, ColorType::Rgba).unwrap();
+        let chunk = ihdr.to_chunk();
+        assert_eq!(chunk.length(), 13);
+        assert_eq!(Ihdr::try_from(&chunk).unwrap(), ihdr);
+        assert_eq!(ihdr.row_bytes(ihdr.width), 200);
+    }
+
+    #[test]
+    fn test_invalid_bit_depth() {
+        assert!(Ihdr::new(1, 1, 16, ColorType::Indexed).is_err());
+        assert!(Ihdr::new(1, 1, 4, ColorType::Rgb).is_err());
+        assert!(Ihdr::new(0, 1, 8, ColorType::Rgb).is_err());
+    }
+
+    #[test]
+    fn test_row_bytes_sub_byte() {
+        let ihdr = Ihdr::new(10, 1, 2, ColorType::Indexed).unwrap();
+        assert_eq!(ihdr.row_bytes(10), 3);
+    }
+}
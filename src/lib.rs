@@ -0,0 +1,30 @@
+pub mod ancillary;
+pub mod apng;
+pub mod args;
+pub mod atomic;
+pub mod batch;
+pub mod chunk;
+pub mod chunk_type;
+pub mod color;
+pub mod commands;
+pub mod copy;
+pub mod diff;
+pub mod dump;
+pub mod exif;
+pub mod ihdr;
+pub mod json;
+pub mod limits;
+pub mod optimize;
+pub mod palette;
+pub mod payload;
+pub mod pixels;
+pub mod png;
+pub mod reduce;
+pub mod repair;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod strip;
+pub mod zlib;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
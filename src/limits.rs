@@ -0,0 +1,131 @@
+use crate::chunk::Chunk;
+use crate::Result;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum LimitErr {
+    FileTooLarge(u64, u64),
+    ChunkTooLong(u32, u32),
+    TooManyChunks(usize),
+    DecompressedTooLarge(usize),
+    ImageTooLarge(u32, u32),
+}
+impl std::error::Error for LimitErr {}
+impl Display for LimitErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            LimitErr::FileTooLarge(size, max) => {
+                write!(f, "File is {} bytes, the limit is {}", size, max)
+            }
+            LimitErr::ChunkTooLong(length, max) => {
+                write!(f, "Chunk length {} exceeds the limit of {}", length, max)
+            }
+            LimitErr::TooManyChunks(max) => write!(f, "File has more than {} chunks", max),
+            LimitErr::DecompressedTooLarge(max) => {
+                write!(f, "Compressed data inflates to more than {} bytes", max)
+            }
+            LimitErr::ImageTooLarge(width, height) => {
+                write!(f, "Image dimensions {}x{} exceed the limit", width, height)
+            }
+        }
+    }
+}
+
+/// Bounds on what parsing and decompression will allocate, so that a
+/// crafted file fails with a [`LimitErr`] instead of exhausting memory.
+/// Unlike the damage [`crate::png::ParseOptions`] can recover from, a limit
+/// is always an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_file_size: u64,
+    pub max_chunk_length: u32,
+    pub max_chunks: usize,
+    /// Applies to each zlib stream PNGme inflates: the image data of `IDAT`
+    /// and `fdAT` chunks and the profile of an `iCCP` chunk.
+    pub max_decompressed_size: usize,
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 1 << 30,
+            max_chunk_length: 1 << 28,
+            max_chunks: 1 << 20,
+            max_decompressed_size: 1 << 30,
+            max_width: 1 << 14,
+            max_height: 1 << 14,
+        }
+    }
+}
+
+impl Limits {
+    /// No limits beyond what the format itself allows.
+    pub fn none() -> Self {
+        Self {
+            max_file_size: u64::MAX,
+            max_chunk_length: Chunk::MAX_LENGTH,
+            max_chunks: usize::MAX,
+            max_decompressed_size: usize::MAX,
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+        }
+    }
+
+    pub fn check_file_size(&self, size: u64) -> Result<()> {
+        if size > self.max_file_size {
+            return Err(Box::new(LimitErr::FileTooLarge(size, self.max_file_size)));
+        }
+        Ok(())
+    }
+
+    pub fn check_chunk_length(&self, length: u32) -> Result<()> {
+        if length > self.max_chunk_length {
+            return Err(Box::new(LimitErr::ChunkTooLong(
+                length,
+                self.max_chunk_length,
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn check_chunks(&self, count: usize) -> Result<()> {
+        if count > self.max_chunks {
+            return Err(Box::new(LimitErr::TooManyChunks(self.max_chunks)));
+        }
+        Ok(())
+    }
+
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<()> {
+        if width > self.max_width || height > self.max_height {
+            return Err(Box::new(LimitErr::ImageTooLarge(width, height)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_checks() {
+        let limits = Limits {
+            max_file_size: 10,
+            max_chunk_length: 10,
+            max_chunks: 2,
+            max_width: 4,
+            max_height: 4,
+            ..Limits::default()
+        };
+        assert!(limits.check_file_size(10).is_ok());
+        assert!(limits.check_file_size(11).is_err());
+        assert!(limits.check_chunk_length(11).is_err());
+        assert!(limits.check_chunks(2).is_ok());
+        assert!(limits.check_chunks(3).is_err());
+        assert!(limits.check_dimensions(4, 4).is_ok());
+        assert!(limits.check_dimensions(4, 5).is_err());
+        assert!(Limits::none().check_file_size(u64::MAX).is_ok());
+    }
+}
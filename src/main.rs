@@ -1,15 +1,29 @@
-#![allow(unused_imports)]
-#![allow(dead_code)]
-mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+use pngme::{args, commands, json};
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
-
-fn main() -> Result<()> {
-    commands::Command::run(args::Config::parse_args()?)?;
-    Ok(())
+fn main() {
+    let (config, args) = match args::Config::parse_args() {
+        Ok((config, args::Invocation::Batch(commands))) => {
+            std::process::exit(commands::Command::run_batch(&config, commands))
+        }
+        Ok((config, args::Invocation::Single(args))) => (config, args),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let error_status = args.error_status();
+    match commands::Command::run(&config, args) {
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            if config.json {
+                println!(
+                    "{}",
+                    json::Json::object(vec![("error", e.to_string().into())])
+                );
+            } else {
+                eprintln!("Error: {}", e);
+            }
+            std::process::exit(error_status);
+        }
+    }
 }
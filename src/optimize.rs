@@ -0,0 +1,370 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::pixels::{Filter, FilterStrategy, RawImage};
+use crate::png::{Png, PngErr};
+use crate::reduce;
+use crate::zlib;
+use crate::Result;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum OptimizeErr {
+    InterlacedAnimation,
+    InvalidLevel(u32),
+    InvalidIdatSize(usize),
+    PixelsDiffer,
+}
+impl std::error::Error for OptimizeErr {}
+impl Display for OptimizeErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            OptimizeErr::InterlacedAnimation => write!(
+                f,
+                "Interlaced animated pngs cannot be optimized, their frames share the interlacing"
+            ),
+            OptimizeErr::InvalidLevel(level) => {
+                write!(f, "Deflate level {} is not between 0 and 9", level)
+            }
+            OptimizeErr::InvalidIdatSize(size) => {
+                write!(
+                    f,
+                    "IDAT size {} must be between 1 and the chunk limit",
+                    size
+                )
+            }
+            OptimizeErr::PixelsDiffer => {
+                write!(
+                    f,
+                    "Recompressed image data does not decode to the same pixels"
+                )
+            }
+        }
+    }
+}
+
+/// What [`optimize`] tries. Every filter strategy is tried at every level.
+#[derive(Debug, Clone)]
+pub struct OptimizeOptions {
+    pub strategies: Vec<FilterStrategy>,
+    /// Deflate levels, from 0 (store) to 9 (best). flate2 does not expose
+    /// zlib's strategies, so the level is the only deflate setting tried.
+    pub levels: Vec<u32>,
+    /// Splits the image data over `IDAT` chunks of at most this many
+    /// bytes; `None` merges it into one.
+    pub idat_size: Option<usize>,
+    /// Also tries the smaller lossless color types and bit depths found by
+    /// [`reduce::reductions`].
+    pub reduce: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            strategies: Filter::ALL
+                .iter()
+                .map(|&filter| FilterStrategy::Fixed(filter))
+                .chain(std::iter::once(FilterStrategy::Adaptive))
+                .collect(),
+            levels: vec![6, 9],
+            idat_size: None,
+            reduce: false,
+        }
+    }
+}
+
+/// The outcome of [`optimize`].
+pub struct Optimized {
+    /// The smallest encoding found, or the input unchanged if none of the
+    /// candidates beat it.
+    pub png: Png,
+    /// Filter strategy and level of the new image data, `None` if the input
+    /// was kept.
+    pub encoding: Option<(FilterStrategy, u32)>,
+    /// Color type and bit depth the image was reduced to, if it was.
+    pub reduced: Option<(ColorType, u8)>,
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+/// Image data to encode, and the `PLTE` and `tRNS` chunks to go with it.
+struct Candidate {
+    raw: RawImage,
+    /// `None` keeps the chunks of the input.
+    palette_chunks: Option<Vec<Chunk>>,
+}
+
+/// Decodes the `IDAT` data of `png` and re-encodes it with every
+/// combination in `options`, keeping the smallest file. Interlaced images
+/// come out non-interlaced. The pixels of the result are checked against
+/// the original before it is returned.
+pub fn optimize(png: &Png, options: &OptimizeOptions) -> Result<Optimized> {
+    if let Some(&level) = options.levels.iter().find(|&&level| level > 9) {
+        return Err(Box::new(OptimizeErr::InvalidLevel(level)));
+    }
+    if let Some(size) = options
+        .idat_size
+        .filter(|&size| size == 0 || size > Chunk::MAX_LENGTH as usize)
+    {
+        return Err(Box::new(OptimizeErr::InvalidIdatSize(size)));
+    }
+    let ihdr = Ihdr::from_png(png)?;
+    let animated = png.chunk_by_type("acTL").is_some();
+    if ihdr.interlaced && animated {
+        return Err(Box::new(OptimizeErr::InterlacedAnimation));
+    }
+    let bytes_before = png.encoded_len();
+    let original = reduce::rgba16(png)?;
+
+    let mut candidates = vec![Candidate {
+        raw: RawImage::from_png(png)?,
+        palette_chunks: None,
+    }];
+    if options.reduce && can_reduce(png) {
+        let kind = (ihdr.color_type, ihdr.bit_depth);
+        candidates.extend(
+            reduce::reductions(ihdr.width, ihdr.height, &original)
+                .into_iter()
+                .filter(|r| (r.raw.color_type, r.raw.bit_depth) != kind)
+                .map(|r| Candidate {
+                    raw: r.raw,
+                    palette_chunks: Some(match r.palette {
+                        Some(palette) => std::iter::once(palette.plte_chunk())
+                            .chain(palette.trns_chunk())
+                            .collect(),
+                        None => Vec::new(),
+                    }),
+                }),
+        );
+    }
+
+    let mut best: Option<(Png, usize, FilterStrategy, u32, bool)> = None;
+    for (i, candidate) in candidates.iter().enumerate() {
+        let mut smallest: Option<(Vec<u8>, FilterStrategy, u32)> = None;
+        for &strategy in &options.strategies {
+            let filtered = candidate.raw.filter(strategy);
+            for &level in &options.levels {
+                let data = zlib::deflate_level(&filtered, level)?;
+                if smallest
+                    .as_ref()
+                    .map_or(true, |(d, _, _)| data.len() < d.len())
+                {
+                    smallest = Some((data, strategy, level));
+                }
+            }
+        }
+        let (data, strategy, level) = match smallest {
+            Some(smallest) => smallest,
+            None => continue,
+        };
+        let result = with_image_data(png, candidate, &data, options.idat_size)?;
+        let size = result.encoded_len();
+        if best.as_ref().map_or(true, |(_, s, _, _, _)| size < *s) {
+            best = Some((result, size, strategy, level, i > 0));
+        }
+    }
+    let (result, bytes_after, strategy, level, reduced) = match best {
+        Some(best) if best.1 < bytes_before => best,
+        _ => return Ok(unchanged(png, bytes_before)),
+    };
+    if reduce::rgba16(&result)? != original {
+        return Err(Box::new(OptimizeErr::PixelsDiffer));
+    }
+    let reduced = if reduced {
+        let ihdr = Ihdr::from_png(&result)?;
+        Some((ihdr.color_type, ihdr.bit_depth))
+    } else {
+        None
+    };
+    Ok(Optimized {
+        png: result,
+        encoding: Some((strategy, level)),
+        reduced,
+        bytes_before,
+        bytes_after,
+    })
+}
+
+/// Reductions rewrite `IHDR`, so they are not tried on animations, whose
+/// frames share it, nor when chunks that depend on the color type are
+/// present.
+fn can_reduce(png: &Png) -> bool {
+    png.chunk_by_type("acTL").is_none()
+        && reduce::DEPENDENT_CHUNK_TYPES
+            .iter()
+            .all(|t| png.chunk_by_type(t).is_none())
+}
+
+fn unchanged(png: &Png, bytes: usize) -> Optimized {
+    Optimized {
+        png: png.clone(),
+        encoding: None,
+        reduced: None,
+        bytes_before: bytes,
+        bytes_after: bytes,
+    }
+}
+
+/// `png` with its header and `IDAT` chunks replaced by those of
+/// `candidate`, the image data split at `idat_size` and put where the first
+/// `IDAT` was, and its `PLTE` and `tRNS` replaced if the candidate has its
+/// own.
+fn with_image_data(
+    png: &Png,
+    candidate: &Candidate,
+    data: &[u8],
+    idat_size: Option<usize>,
+) -> Result<Png> {
+    let idat = ChunkType::from_str("IDAT")?;
+    let replaced = |c: &Chunk| {
+        *c.chunk_type() == idat
+            || candidate.palette_chunks.is_some()
+                && matches!(c.chunk_type().to_string().as_str(), "PLTE" | "tRNS")
+    };
+    let mut chunks: Vec<Chunk> = Vec::with_capacity(png.chunks().len());
+    for chunk in png.chunks() {
+        if *chunk.chunk_type() == idat && !chunks.iter().any(|c| *c.chunk_type() == idat) {
+            chunks.extend(candidate.palette_chunks.iter().flatten().cloned());
+            chunks.extend(
+                data.chunks(idat_size.unwrap_or(data.len().max(1)))
+                    .map(|piece| Chunk::new(idat.clone(), piece.to_vec())),
+            );
+        }
+        if !replaced(chunk) {
+            chunks.push(chunk.clone());
+        }
+    }
+    let ihdr_index = chunks
+        .iter()
+        .position(|c| c.chunk_type().to_string() == "IHDR")
+        .ok_or(PngErr::PngChunkTypeAbsent)?;
+    chunks[ihdr_index] = candidate.raw.ihdr().to_chunk();
+    let mut optimized = Png::from_chunks(chunks).with_limits(png.limits().clone());
+    optimized.set_unsafe_chunk_policy(png.unsafe_chunk_policy());
+    optimized.mark_critical_modified();
+    Ok(optimized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::RgbaImage;
+    use crate::png::tests::PNG_FILE;
+    use std::convert::TryFrom;
+
+    /// A gradient stored without compression over several `IDAT` chunks.
+    fn bloated_png() -> Png {
+        let mut image = RgbaImage::new(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                image.set_pixel(x, y, [x as u8 * 8, y as u8 * 8, 128, 255]);
+            }
+        }
+        let raw = image.into_raw();
+        let data = raw.encode(FilterStrategy::Fixed(Filter::None), 0).unwrap();
+        let mut chunks = vec![raw.ihdr().to_chunk()];
+        for piece in data.chunks(1000) {
+            chunks.push(Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                piece.to_vec(),
+            ));
+        }
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_optimize_shrinks_and_keeps_pixels() {
+        let png = bloated_png();
+        let optimized = optimize(&png, &OptimizeOptions::default()).unwrap();
+        assert!(optimized.bytes_after < optimized.bytes_before / 2);
+        assert!(optimized.encoding.is_some());
+        let idats = optimized
+            .png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .count();
+        assert_eq!(idats, 1);
+        assert_eq!(
+            RgbaImage::from_png(&optimized.png).unwrap(),
+            RgbaImage::from_png(&png).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_optimize_splits_idat() {
+        let options = OptimizeOptions {
+            idat_size: Some(20),
+            ..OptimizeOptions::default()
+        };
+        let optimized = optimize(&bloated_png(), &options).unwrap();
+        let idats: Vec<usize> = optimized
+            .png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .map(|c| c.data().len())
+            .collect();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|&len| len <= 20));
+        assert_eq!(optimized.png.chunks().len(), idats.len() + 2);
+    }
+
+    #[test]
+    fn test_optimize_keeps_smaller_original() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let options = OptimizeOptions {
+            strategies: vec![FilterStrategy::Fixed(Filter::None)],
+            levels: vec![0],
+            ..OptimizeOptions::default()
+        };
+        let optimized = optimize(&png, &options).unwrap();
+        assert!(optimized.encoding.is_none());
+        assert_eq!(optimized.png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_optimize_reduce() {
+        let mut image = RgbaImage::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let v = if (x + y) % 2 == 0 { 0 } else { 255 };
+                image.set_pixel(x, y, [v, v, v, 255]);
+            }
+        }
+        let png = image.to_png().unwrap();
+        let options = OptimizeOptions {
+            reduce: true,
+            ..OptimizeOptions::default()
+        };
+        let optimized = optimize(&png, &options).unwrap();
+        let (color_type, depth) = optimized.reduced.unwrap();
+        assert!(matches!(
+            color_type,
+            ColorType::Grayscale | ColorType::Indexed
+        ));
+        assert_eq!(depth, 1);
+        assert_eq!(RgbaImage::from_png(&optimized.png).unwrap(), image);
+        assert!(optimize(&png, &OptimizeOptions::default())
+            .unwrap()
+            .reduced
+            .is_none());
+    }
+
+    #[test]
+    fn test_optimize_invalid_options() {
+        let png = bloated_png();
+        let options = OptimizeOptions {
+            levels: vec![10],
+            ..OptimizeOptions::default()
+        };
+        assert!(optimize(&png, &options).is_err());
+        let options = OptimizeOptions {
+            idat_size: Some(0),
+            ..OptimizeOptions::default()
+        };
+        assert!(optimize(&png, &options).is_err());
+    }
+}
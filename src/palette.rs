@@ -0,0 +1,312 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::{Png, PngErr};
+use crate::Result;
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum PaletteErr {
+    InvalidLength(usize),
+    TooManyEntries(usize, u8),
+    TooManyAlphas(usize, usize),
+    IndexOutOfRange(usize, usize),
+    InvalidColor(String),
+    NotIndexed(ColorType),
+}
+impl std::error::Error for PaletteErr {}
+impl Display for PaletteErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            PaletteErr::InvalidLength(len) => {
+                write!(f, "PLTE length {} is not a non zero multiple of 3", len)
+            }
+            PaletteErr::TooManyEntries(n, depth) => {
+                write!(f, "{} palette entries exceed bit depth {}", n, depth)
+            }
+            PaletteErr::TooManyAlphas(n, entries) => {
+                write!(f, "tRNS has {} entries but the palette only {}", n, entries)
+            }
+            PaletteErr::IndexOutOfRange(i, n) => {
+                write!(f, "Palette index {} out of range 0..{}", i, n)
+            }
+            PaletteErr::InvalidColor(s) => {
+                write!(f, "Invalid color {}, expected #rrggbb or #rrggbbaa", s)
+            }
+            PaletteErr::NotIndexed(c) => write!(f, "Image is {}, not indexed", c),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+impl FromStr for PaletteEntry {
+    type Err = crate::Error;
+    /// Parses `#rrggbb` or `#rrggbbaa`; alpha defaults to opaque.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Box::new(PaletteErr::InvalidColor(s.to_string())) as crate::Error;
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Self {
+            red: channel(0)?,
+            green: channel(2)?,
+            blue: channel(4)?,
+            alpha: if hex.len() == 8 { channel(6)? } else { 255 },
+        })
+    }
+}
+impl Display for PaletteEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "#{:02x}{:02x}{:02x}{:02x}",
+            self.red, self.green, self.blue, self.alpha
+        )
+    }
+}
+
+/// An indexed image's palette from `PLTE`, with the per-entry alpha from
+/// `tRNS` merged in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    entries: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    pub fn new(entries: Vec<PaletteEntry>) -> Self {
+        Self { entries }
+    }
+
+    pub fn from_chunks(plte: &Chunk, trns: Option<&Chunk>) -> Result<Self> {
+        let data = plte.data();
+        if data.is_empty() || data.len() % 3 != 0 || data.len() > 256 * 3 {
+            return Err(Box::new(PaletteErr::InvalidLength(data.len())));
+        }
+        let mut entries: Vec<_> = data
+            .chunks_exact(3)
+            .map(|rgb| PaletteEntry {
+                red: rgb[0],
+                green: rgb[1],
+                blue: rgb[2],
+                alpha: 255,
+            })
+            .collect();
+        if let Some(trns) = trns {
+            if trns.data().len() > entries.len() {
+                return Err(Box::new(PaletteErr::TooManyAlphas(
+                    trns.data().len(),
+                    entries.len(),
+                )));
+            }
+            for (entry, &alpha) in entries.iter_mut().zip(trns.data()) {
+                entry.alpha = alpha;
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// Reads the palette of an indexed png, validating it against `IHDR`.
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let ihdr = Ihdr::from_png(png)?;
+        if ihdr.color_type != ColorType::Indexed {
+            return Err(Box::new(PaletteErr::NotIndexed(ihdr.color_type)));
+        }
+        let plte = png
+            .chunk_by_type("PLTE")
+            .ok_or(PngErr::PngChunkTypeAbsent)?;
+        let palette = Palette::from_chunks(plte, png.chunk_by_type("tRNS"))?;
+        palette.validate(&ihdr)?;
+        Ok(palette)
+    }
+
+    pub fn validate(&self, ihdr: &Ihdr) -> Result<()> {
+        if ihdr.color_type == ColorType::Indexed && self.len() > 1 << ihdr.bit_depth {
+            return Err(Box::new(PaletteErr::TooManyEntries(
+                self.len(),
+                ihdr.bit_depth,
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[PaletteEntry] {
+        &self.entries
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn set_entry(&mut self, index: usize, entry: PaletteEntry) -> Result<()> {
+        let len = self.len();
+        let slot = self
+            .entries
+            .get_mut(index)
+            .ok_or(PaletteErr::IndexOutOfRange(index, len))?;
+        *slot = entry;
+        Ok(())
+    }
+
+    pub fn plte_chunk(&self) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("PLTE").unwrap(),
+            self.entries
+                .iter()
+                .flat_map(|e| [e.red, e.green, e.blue])
+                .collect(),
+        )
+    }
+
+    /// The `tRNS` chunk for this palette, trimmed after the last non opaque
+    /// entry, or `None` when every entry is opaque.
+    pub fn trns_chunk(&self) -> Option<Chunk> {
+        let len = self.entries.iter().rposition(|e| e.alpha != 255)? + 1;
+        Some(Chunk::new(
+            ChunkType::from_str("tRNS").unwrap(),
+            self.entries[..len].iter().map(|e| e.alpha).collect(),
+        ))
+    }
+
+    /// Writes this palette back into `png`, replacing `PLTE` in place and
+    /// placing `tRNS` right after it.
+    pub fn apply(&self, png: &mut Png) -> Result<()> {
+        if png.replace_chunk(self.plte_chunk()).is_none() {
+            png.insert_before_image_data(self.plte_chunk());
+        }
+        let _ = png.remove_chunk("tRNS");
+        if let Some(trns) = self.trns_chunk() {
+            let idx = png.position("PLTE").ok_or(PngErr::PngChunkTypeAbsent)?;
+            png.insert_chunk(idx + 1, trns);
+        }
+        Ok(())
+    }
+
+    /// GIMP palette format.
+    pub fn to_gpl(&self, name: &str) -> String {
+        let mut out = format!("GIMP Palette\nName: {}\nColumns: 16\n#\n", name);
+        for (i, e) in self.entries.iter().enumerate() {
+            out.push_str(&format!(
+                "{:3} {:3} {:3}\tIndex {} alpha {}\n",
+                e.red, e.green, e.blue, i, e.alpha
+            ));
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                format!(
+                    "  {{\"index\": {}, \"red\": {}, \"green\": {}, \"blue\": {}, \"alpha\": {}}}",
+                    i, e.red, e.green, e.blue, e.alpha
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+impl Display for Palette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{} entries", self.len())?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            write!(f, "\n{:3}: {}", i, entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png(bit_depth: u8) -> Png {
+        Png::from_chunks(vec![
+            Ihdr::new(2, 2, bit_depth, ColorType::Indexed)
+                .unwrap()
+                .to_chunk(),
+            Chunk::new(
+                ChunkType::from_str("PLTE").unwrap(),
+                vec![255, 0, 0, 0, 255, 0, 0, 0, 255],
+            ),
+            Chunk::new(ChunkType::from_str("tRNS").unwrap(), vec![0, 128]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_palette_from_png() {
+        let palette = Palette::from_png(&testing_png(8)).unwrap();
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette.entries()[0].alpha, 0);
+        assert_eq!(palette.entries()[1].alpha, 128);
+        assert_eq!(palette.entries()[2].alpha, 255);
+        assert_eq!(palette.entries()[2].blue, 255);
+    }
+
+    #[test]
+    fn test_palette_too_many_entries_for_depth() {
+        assert!(Palette::from_png(&testing_png(1)).is_err());
+        assert!(Palette::from_png(&testing_png(2)).is_ok());
+    }
+
+    #[test]
+    fn test_set_entry_and_apply() {
+        let mut png = testing_png(8);
+        let mut palette = Palette::from_png(&png).unwrap();
+        palette
+            .set_entry(0, PaletteEntry::from_str("#102030").unwrap())
+            .unwrap();
+        palette
+            .set_entry(1, PaletteEntry::from_str("#405060ff").unwrap())
+            .unwrap();
+        assert!(palette
+            .set_entry(3, PaletteEntry::from_str("#000000").unwrap())
+            .is_err());
+        palette.apply(&mut png).unwrap();
+        // Every entry is opaque now, so tRNS is dropped.
+        assert!(png.chunk_by_type("tRNS").is_none());
+        assert_eq!(Palette::from_png(&png).unwrap(), palette);
+
+        palette
+            .set_entry(2, PaletteEntry::from_str("#00000080").unwrap())
+            .unwrap();
+        palette.apply(&mut png).unwrap();
+        assert_eq!(png.chunks()[2].chunk_type().to_string(), "tRNS");
+        assert_eq!(png.chunks()[2].data(), &[255, 255, 128]);
+    }
+
+    #[test]
+    fn test_invalid_color() {
+        assert!(PaletteEntry::from_str("#12345").is_err());
+        assert!(PaletteEntry::from_str("#gggggg").is_err());
+    }
+
+    #[test]
+    fn test_exports() {
+        let palette = Palette::from_png(&testing_png(8)).unwrap();
+        assert!(palette
+            .to_gpl("test")
+            .starts_with("GIMP Palette\nName: test\n"));
+        assert!(palette
+            .to_json()
+            .contains("{\"index\": 1, \"red\": 0, \"green\": 255, \"blue\": 0, \"alpha\": 128}"));
+    }
+}
@@ -0,0 +1,770 @@
+use crate::apng::{frame_data, frame_data_chunk, Frame};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::limits::Limits;
+use crate::pixels::{FilterStrategy, RawImage};
+use crate::png::Png;
+use crate::Result;
+use crc32fast::Hasher;
+use std::{
+    convert::TryInto,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum PayloadErr {
+    NotAnimated,
+    UnknownSpread(String),
+    UnsupportedImage(&'static str),
+    TooLarge(usize, usize),
+    InvalidManifest,
+    MissingPiece(u32),
+    PieceLength(u32, usize, usize),
+    ChecksumMismatch(u32, u32),
+    ChunkSizeOutOfRange(usize),
+    MissingChunk(u32, u32, u32),
+    DuplicateChunk(u32, u32),
+    InconsistentChunks(u32),
+    IdsExhausted(String),
+}
+impl std::error::Error for PayloadErr {}
+impl Display for PayloadErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            PayloadErr::NotAnimated => write!(f, "Png has no acTL chunk"),
+            PayloadErr::UnknownSpread(s) => {
+                write!(f, "Unknown frame spread {}, expected chunks or pixels", s)
+            }
+            PayloadErr::UnsupportedImage(why) => {
+                write!(f, "Cannot hide data in the pixels of {} images", why)
+            }
+            PayloadErr::TooLarge(len, capacity) => write!(
+                f,
+                "Payload of {} bytes exceeds the {} bytes the frames can hold",
+                len, capacity
+            ),
+            PayloadErr::InvalidManifest => write!(f, "Payload manifest is malformed"),
+            PayloadErr::MissingPiece(seq) => {
+                write!(f, "Payload piece for frame {} is missing", seq)
+            }
+            PayloadErr::PieceLength(seq, expected, got) => write!(
+                f,
+                "Payload piece for frame {} should be {} bytes, found {}",
+                seq, expected, got
+            ),
+            PayloadErr::ChecksumMismatch(expected, got) => write!(
+                f,
+                "Payload checksum {:08x} does not match manifest {:08x}",
+                got, expected
+            ),
+            PayloadErr::ChunkSizeOutOfRange(size) => write!(
+                f,
+                "Maximum chunk size {} must be between {} and {}",
+                size,
+                SPLIT_HEADER_LEN + 1,
+                Chunk::MAX_LENGTH
+            ),
+            PayloadErr::MissingChunk(id, index, total) => {
+                write!(
+                    f,
+                    "Payload {} is missing chunk {} of {}",
+                    id,
+                    index + 1,
+                    total
+                )
+            }
+            PayloadErr::DuplicateChunk(id, index) => {
+                write!(f, "Payload {} has chunk {} more than once", id, index + 1)
+            }
+            PayloadErr::InconsistentChunks(id) => write!(
+                f,
+                "Chunks of payload {} disagree on chunk count or checksum",
+                id
+            ),
+            PayloadErr::IdsExhausted(chunk_type) => {
+                write!(f, "No payload id is left for {} chunks", chunk_type)
+            }
+        }
+    }
+}
+
+const MANIFEST_MAGIC: &[u8; 4] = b"FRM\0";
+const PIECE_MAGIC: &[u8; 4] = b"FRP\0";
+const SPLIT_MAGIC: &[u8; 4] = b"SPL\0";
+/// Magic, payload id, index, total and checksum.
+const SPLIT_HEADER_LEN: usize = 20;
+
+/// Where the pieces of a payload spread across animation frames are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSpread {
+    /// One private chunk right after each frame's `fcTL`.
+    Chunks,
+    /// The least significant bit of every 8-bit sample, or of the low byte
+    /// of every 16-bit sample, of each frame.
+    Pixels,
+}
+
+impl FromStr for FrameSpread {
+    type Err = PayloadErr;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "chunks" => Ok(FrameSpread::Chunks),
+            "pixels" => Ok(FrameSpread::Pixels),
+            _ => Err(PayloadErr::UnknownSpread(s.to_string())),
+        }
+    }
+}
+
+/// Records how a payload was split: its total length and CRC and, in
+/// payload order, the `fcTL` sequence number and length of each piece.
+/// Keying pieces by sequence number rather than by position lets the
+/// payload be reassembled after frames are reordered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub spread: FrameSpread,
+    pub length: u32,
+    pub crc: u32,
+    pub pieces: Vec<(u32, u32)>,
+}
+
+impl Manifest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MANIFEST_MAGIC.to_vec();
+        data.push(match self.spread {
+            FrameSpread::Chunks => 0,
+            FrameSpread::Pixels => 1,
+        });
+        data.extend(self.length.to_be_bytes());
+        data.extend(self.crc.to_be_bytes());
+        data.extend((self.pieces.len() as u32).to_be_bytes());
+        for (seq, len) in &self.pieces {
+            data.extend(seq.to_be_bytes());
+            data.extend(len.to_be_bytes());
+        }
+        data
+    }
+
+    /// Parses manifest chunk data, returning `None` if it lacks the
+    /// manifest magic.
+    pub fn parse(data: &[u8]) -> Result<Option<Self>> {
+        if !data.starts_with(MANIFEST_MAGIC) {
+            return Ok(None);
+        }
+        let data = &data[MANIFEST_MAGIC.len()..];
+        if data.len() < 13 {
+            return Err(Box::new(PayloadErr::InvalidManifest));
+        }
+        let spread = match data[0] {
+            0 => FrameSpread::Chunks,
+            1 => FrameSpread::Pixels,
+            _ => return Err(Box::new(PayloadErr::InvalidManifest)),
+        };
+        let count = u32_at(data, 9) as usize;
+        if data.len() != 13 + count * 8 {
+            return Err(Box::new(PayloadErr::InvalidManifest));
+        }
+        Ok(Some(Self {
+            spread,
+            length: u32_at(data, 1),
+            crc: u32_at(data, 5),
+            pieces: (0..count)
+                .map(|i| (u32_at(data, 13 + i * 8), u32_at(data, 17 + i * 8)))
+                .collect(),
+        }))
+    }
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes(data[i..i + 4].try_into().unwrap())
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Splits `payload` as evenly as possible into at most `n` pieces.
+fn split_evenly(payload: &[u8], n: usize) -> Vec<&[u8]> {
+    let piece_len = payload.len().div_ceil(n).max(1);
+    payload.chunks(piece_len).collect()
+}
+
+/// Hides `payload` across the frames of an animated png, replacing any
+/// payload previously stored under `chunk_type`. The manifest is kept in a
+/// `chunk_type` chunk before the image data.
+pub fn hide_in_frames(
+    png: &mut Png,
+    chunk_type: &ChunkType,
+    payload: &[u8],
+    spread: FrameSpread,
+) -> Result<()> {
+    let animation = png.animation()?.ok_or(PayloadErr::NotAnimated)?;
+    let limits = png.limits().clone();
+    png.remove_chunks(|c| c.chunk_type() == chunk_type);
+
+    let pieces = split_evenly(payload, animation.frames.len());
+    let mut manifest = Manifest {
+        spread,
+        length: payload.len() as u32,
+        crc: checksum(payload),
+        pieces: Vec::with_capacity(pieces.len()),
+    };
+    match spread {
+        FrameSpread::Chunks => {
+            for (frame, piece) in animation.frames.iter().zip(&pieces) {
+                let seq = frame.control.sequence_number;
+                let mut data = PIECE_MAGIC.to_vec();
+                data.extend(seq.to_be_bytes());
+                data.extend(*piece);
+                let idx = fctl_position(png, seq).ok_or(PayloadErr::MissingPiece(seq))?;
+                png.insert_chunk(idx + 1, Chunk::new(chunk_type.clone(), data));
+                manifest.pieces.push((seq, piece.len() as u32));
+            }
+        }
+        FrameSpread::Pixels => {
+            let ihdr = check_pixel_carrier(png)?;
+            let capacity = animation
+                .frames
+                .iter()
+                .map(|f| carriers(&ihdr, f) / 8)
+                .min()
+                .unwrap_or(0);
+            let largest = pieces.iter().map(|p| p.len()).max().unwrap_or(0);
+            if largest > capacity {
+                return Err(Box::new(PayloadErr::TooLarge(
+                    payload.len(),
+                    capacity * animation.frames.len(),
+                )));
+            }
+            let mut chunks = png.chunks().to_vec();
+            for (frame, piece) in animation.frames.iter().zip(&pieces) {
+                let mut raw = decode_frame(&ihdr, frame, &limits)?;
+                let stride = ihdr.bit_depth as usize / 8;
+                for (i, carrier) in raw
+                    .data
+                    .iter_mut()
+                    .skip(stride - 1)
+                    .step_by(stride)
+                    .enumerate()
+                {
+                    match piece.get(i / 8) {
+                        Some(byte) => *carrier = (*carrier & !1) | ((byte >> (7 - i % 8)) & 1),
+                        None => break,
+                    }
+                }
+                let data = raw.encode(FilterStrategy::Adaptive, 6)?;
+                replace_frame_data(&mut chunks, frame.control.sequence_number, &data)?;
+                manifest
+                    .pieces
+                    .push((frame.control.sequence_number, piece.len() as u32));
+            }
+            let mut rebuilt = Png::from_chunks(chunks).with_limits(limits);
+            rebuilt.set_unsafe_chunk_policy(png.unsafe_chunk_policy());
+            rebuilt.mark_critical_modified();
+            *png = rebuilt;
+        }
+    }
+    png.insert_before_image_data(Chunk::new(chunk_type.clone(), manifest.to_bytes()));
+    Ok(())
+}
+
+/// Reassembles a payload hidden by [`hide_in_frames`]. Returns `None` if no
+/// `chunk_type` chunk holds a manifest.
+pub fn reveal_from_frames(png: &Png, chunk_type: &ChunkType) -> Result<Option<Vec<u8>>> {
+    let type_name = chunk_type.to_string();
+    let chunks: Vec<&Chunk> = png
+        .chunks()
+        .iter()
+        .filter(|c| c.chunk_type().to_string() == type_name)
+        .collect();
+    let mut manifest = None;
+    for chunk in &chunks {
+        if let Some(m) = Manifest::parse(chunk.data())? {
+            manifest = Some(m);
+            break;
+        }
+    }
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => return Ok(None),
+    };
+
+    let mut payload = Vec::with_capacity(manifest.length as usize);
+    match manifest.spread {
+        FrameSpread::Chunks => {
+            for &(seq, len) in &manifest.pieces {
+                let piece = chunks
+                    .iter()
+                    .map(|c| c.data())
+                    .find(|d| d.starts_with(PIECE_MAGIC) && d.len() >= 8 && u32_at(d, 4) == seq)
+                    .map(|d| &d[8..])
+                    .ok_or(PayloadErr::MissingPiece(seq))?;
+                if piece.len() != len as usize {
+                    return Err(Box::new(PayloadErr::PieceLength(
+                        seq,
+                        len as usize,
+                        piece.len(),
+                    )));
+                }
+                payload.extend(piece);
+            }
+        }
+        FrameSpread::Pixels => {
+            let ihdr = check_pixel_carrier(png)?;
+            let animation = png.animation()?.ok_or(PayloadErr::NotAnimated)?;
+            for &(seq, len) in &manifest.pieces {
+                let frame = animation
+                    .frames
+                    .iter()
+                    .find(|f| f.control.sequence_number == seq)
+                    .ok_or(PayloadErr::MissingPiece(seq))?;
+                let available = carriers(&ihdr, frame) / 8;
+                if available < len as usize {
+                    return Err(Box::new(PayloadErr::PieceLength(
+                        seq,
+                        len as usize,
+                        available,
+                    )));
+                }
+                let raw = decode_frame(&ihdr, frame, png.limits())?;
+                let stride = ihdr.bit_depth as usize / 8;
+                let bits: Vec<u8> = raw
+                    .data
+                    .iter()
+                    .skip(stride - 1)
+                    .step_by(stride)
+                    .take(len as usize * 8)
+                    .map(|b| b & 1)
+                    .collect();
+                payload.extend(
+                    bits.chunks(8)
+                        .map(|byte| byte.iter().fold(0, |acc, b| acc << 1 | b)),
+                );
+            }
+        }
+    }
+    let crc = checksum(&payload);
+    if payload.len() != manifest.length as usize || crc != manifest.crc {
+        return Err(Box::new(PayloadErr::ChecksumMismatch(manifest.crc, crc)));
+    }
+    Ok(Some(payload))
+}
+
+/// Header carried by each chunk of a payload split by [`hide_in_chunks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitHeader {
+    pub payload_id: u32,
+    pub index: u32,
+    pub total: u32,
+    /// CRC of the whole payload.
+    pub checksum: u32,
+}
+
+impl SplitHeader {
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut data = SPLIT_MAGIC.to_vec();
+        for v in [self.payload_id, self.index, self.total, self.checksum].iter() {
+            data.extend(v.to_be_bytes());
+        }
+        data
+    }
+
+    /// Splits chunk data into its header and piece, returning `None` if it
+    /// does not start with a split header.
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        if data.len() < SPLIT_HEADER_LEN || !data.starts_with(SPLIT_MAGIC) {
+            return None;
+        }
+        let header = Self {
+            payload_id: u32_at(data, 4),
+            index: u32_at(data, 8),
+            total: u32_at(data, 12),
+            checksum: u32_at(data, 16),
+        };
+        Some((header, &data[SPLIT_HEADER_LEN..]))
+    }
+}
+
+/// Appends `payload` to `png` as `chunk_type` chunks whose data, header
+/// included, is at most `max_chunk_size` bytes. Returns the payload id,
+/// which is one more than the highest id already stored under `chunk_type`.
+pub fn hide_in_chunks(
+    png: &mut Png,
+    chunk_type: &ChunkType,
+    payload: &[u8],
+    max_chunk_size: usize,
+) -> Result<u32> {
+    if max_chunk_size <= SPLIT_HEADER_LEN || max_chunk_size > Chunk::MAX_LENGTH as usize {
+        return Err(Box::new(PayloadErr::ChunkSizeOutOfRange(max_chunk_size)));
+    }
+    let type_name = chunk_type.to_string();
+    let payload_id = png
+        .chunks()
+        .iter()
+        .filter(|c| c.chunk_type().to_string() == type_name)
+        .filter_map(|c| SplitHeader::parse(c.data()))
+        .map(|(header, _)| header.payload_id)
+        .max()
+        .map_or(Some(0), |id| id.checked_add(1))
+        .ok_or_else(|| PayloadErr::IdsExhausted(type_name.clone()))?;
+    let pieces: Vec<&[u8]> = if payload.is_empty() {
+        vec![&[]]
+    } else {
+        payload.chunks(max_chunk_size - SPLIT_HEADER_LEN).collect()
+    };
+    let checksum = checksum(payload);
+    for (index, piece) in pieces.iter().enumerate() {
+        let mut data = SplitHeader {
+            payload_id,
+            index: index as u32,
+            total: pieces.len() as u32,
+            checksum,
+        }
+        .to_bytes();
+        data.extend(*piece);
+        let end = png.position("IEND").unwrap_or_else(|| png.chunks().len());
+        png.insert_chunk(end, Chunk::new(chunk_type.clone(), data));
+    }
+    Ok(payload_id)
+}
+
+/// Reassembles and verifies every payload split by [`hide_in_chunks`] under
+/// `chunk_type`, ordered by payload id. Chunks may appear in any order.
+pub fn reveal_from_chunks(png: &Png, chunk_type: &ChunkType) -> Result<Vec<(u32, Vec<u8>)>> {
+    let type_name = chunk_type.to_string();
+    // Pieces are kept with their index rather than in `total` slots, as
+    // `total` is read from the file and may be anything.
+    let mut payloads: Vec<(SplitHeader, Vec<_>)> = Vec::new();
+    for chunk in png.chunks() {
+        if chunk.chunk_type().to_string() != type_name {
+            continue;
+        }
+        let (header, piece) = match SplitHeader::parse(chunk.data()) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        let id = header.payload_id;
+        let pos = match payloads.iter().position(|(h, _)| h.payload_id == id) {
+            Some(pos) => pos,
+            None => {
+                payloads.push((header, Vec::new()));
+                payloads.len() - 1
+            }
+        };
+        let (first, pieces) = &mut payloads[pos];
+        if (first.total, first.checksum) != (header.total, header.checksum)
+            || header.index >= header.total
+        {
+            return Err(Box::new(PayloadErr::InconsistentChunks(id)));
+        }
+        pieces.push((header.index, piece));
+    }
+    payloads.sort_by_key(|(header, _)| header.payload_id);
+
+    let mut out = Vec::with_capacity(payloads.len());
+    for (header, mut pieces) in payloads {
+        let id = header.payload_id;
+        pieces.sort_by_key(|&(index, _)| index);
+        if let Some(pair) = pieces.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(Box::new(PayloadErr::DuplicateChunk(id, pair[0].0)));
+        }
+        if header.total as usize > pieces.len() {
+            let missing = (0..)
+                .zip(&pieces)
+                .find(|&(expected, &(index, _))| index != expected)
+                .map_or(pieces.len() as u32, |(expected, _)| expected);
+            return Err(Box::new(PayloadErr::MissingChunk(
+                id,
+                missing,
+                header.total,
+            )));
+        }
+        let mut payload = Vec::new();
+        for (_, piece) in pieces {
+            payload.extend(piece);
+        }
+        let crc = checksum(&payload);
+        if crc != header.checksum {
+            return Err(Box::new(PayloadErr::ChecksumMismatch(header.checksum, crc)));
+        }
+        out.push((id, payload));
+    }
+    Ok(out)
+}
+
+fn fctl_position(png: &Png, seq: u32) -> Option<usize> {
+    png.chunks().iter().position(|c| {
+        c.chunk_type().to_string() == "fcTL" && c.data().len() >= 4 && u32_at(c.data(), 0) == seq
+    })
+}
+
+/// Checks the image has whole byte samples without a palette, which is
+/// what pixel hiding needs, and returns its header.
+fn check_pixel_carrier(png: &Png) -> Result<Ihdr> {
+    let ihdr = Ihdr::from_png(png)?;
+    if ihdr.color_type == ColorType::Indexed {
+        return Err(Box::new(PayloadErr::UnsupportedImage("indexed")));
+    }
+    if ihdr.bit_depth < 8 {
+        return Err(Box::new(PayloadErr::UnsupportedImage("sub byte depth")));
+    }
+    if ihdr.interlaced {
+        return Err(Box::new(PayloadErr::UnsupportedImage("interlaced")));
+    }
+    Ok(ihdr)
+}
+
+/// Number of bits a frame can carry, one per sample.
+fn carriers(ihdr: &Ihdr, frame: &Frame) -> usize {
+    let c = &frame.control;
+    c.width as usize * c.height as usize * ihdr.color_type.channels()
+}
+
+fn decode_frame(ihdr: &Ihdr, frame: &Frame, limits: &Limits) -> Result<RawImage> {
+    let frame_ihdr = Ihdr {
+        width: frame.control.width,
+        height: frame.control.height,
+        ..*ihdr
+    };
+    RawImage::decode(&frame_ihdr, &frame.data, limits)
+}
+
+/// Replaces the `IDAT` or `fdAT` data of the frame whose `fcTL` has
+/// sequence number `seq` with `data`, spread over the same number of chunks
+/// so that `fdAT` sequence numbers stay contiguous.
+fn replace_frame_data(chunks: &mut [Chunk], seq: u32, data: &[u8]) -> Result<()> {
+    let mut current = None;
+    let mut slots = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        match chunk.chunk_type().to_string().as_str() {
+            "fcTL" => current = chunk.data().get(..4).map(|d| u32_at(d, 0)),
+            "IDAT" | "fdAT" if current == Some(seq) => slots.push(i),
+            _ => {}
+        }
+    }
+    if slots.is_empty() {
+        return Err(Box::new(PayloadErr::MissingPiece(seq)));
+    }
+    let part_len = data.len().div_ceil(slots.len()).max(1);
+    let mut parts = data.chunks(part_len);
+    for i in slots {
+        let part = parts.next().unwrap_or(&[]);
+        chunks[i] = if chunks[i].chunk_type().to_string() == "IDAT" {
+            Chunk::new(chunks[i].chunk_type().clone(), part.to_vec())
+        } else {
+            frame_data_chunk(frame_data(&chunks[i])?.0, part)
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::Animation;
+    use crate::pixels::RgbaImage;
+    use std::convert::TryFrom;
+
+    fn animation() -> Png {
+        let frames: Vec<RgbaImage> = (0..3u8)
+            .map(|i| {
+                let mut image = RgbaImage::new(8, 4);
+                for y in 0..4 {
+                    for x in 0..8 {
+                        image.set_pixel(x, y, [i * 40, x as u8 * 20, y as u8 * 50, 255]);
+                    }
+                }
+                image
+            })
+            .collect();
+        Animation::build(&frames, &[100, 100, 100], 0).unwrap()
+    }
+
+    fn chunk_type() -> ChunkType {
+        ChunkType::from_str("ruSt").unwrap()
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let manifest = Manifest {
+            spread: FrameSpread::Pixels,
+            length: 10,
+            crc: 0xdeadbeef,
+            pieces: vec![(0, 4), (1, 4), (3, 2)],
+        };
+        assert_eq!(
+            Manifest::parse(&manifest.to_bytes()).unwrap(),
+            Some(manifest)
+        );
+        assert_eq!(Manifest::parse(b"plain message").unwrap(), None);
+    }
+
+    #[test]
+    fn test_hide_in_chunks() {
+        let mut png = animation();
+        let payload = b"This is a secret message spread over frames";
+        hide_in_frames(&mut png, &chunk_type(), payload, FrameSpread::Chunks).unwrap();
+        // The animation itself is untouched and still validates.
+        assert_eq!(png.animation().unwrap().unwrap().frames.len(), 3);
+        let revealed = reveal_from_frames(&png, &chunk_type()).unwrap().unwrap();
+        assert_eq!(revealed, payload);
+    }
+
+    #[test]
+    fn test_hide_in_pixels() {
+        let mut png = animation();
+        let payload = b"hidden in the low bits";
+        hide_in_frames(&mut png, &chunk_type(), payload, FrameSpread::Pixels).unwrap();
+        let revealed = reveal_from_frames(&png, &chunk_type()).unwrap().unwrap();
+        assert_eq!(revealed, payload);
+    }
+
+    #[test]
+    fn test_hide_in_pixels_drops_stale_chunks() {
+        let mut png = animation();
+        let private = Chunk::new(ChunkType::from_str("prIV").unwrap(), b"offsets".to_vec());
+        png.insert_chunk(1, private);
+        hide_in_frames(&mut png, &chunk_type(), b"secret", FrameSpread::Pixels).unwrap();
+        assert!(png.critical_modified());
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert!(reparsed.chunk_by_type("prIV").is_none());
+        let revealed = reveal_from_frames(&reparsed, &chunk_type()).unwrap();
+        assert_eq!(revealed.unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_reveal_after_frame_reorder() {
+        let mut png = animation();
+        let payload = b"order matters";
+        hide_in_frames(&mut png, &chunk_type(), payload, FrameSpread::Chunks).unwrap();
+        // Swap the last two frames along with their pieces.
+        let chunks = png.chunks().to_vec();
+        let second = fctl_position(&png, 1).unwrap();
+        let third = fctl_position(&png, 3).unwrap();
+        let end = png.position("IEND").unwrap();
+        let mut reordered = chunks[..second].to_vec();
+        reordered.extend_from_slice(&chunks[third..end]);
+        reordered.extend_from_slice(&chunks[second..third]);
+        reordered.extend_from_slice(&chunks[end..]);
+        let png = Png::from_chunks(reordered);
+        let revealed = reveal_from_frames(&png, &chunk_type()).unwrap().unwrap();
+        assert_eq!(revealed, payload);
+    }
+
+    #[test]
+    fn test_reveal_missing_piece() {
+        let mut png = animation();
+        hide_in_frames(&mut png, &chunk_type(), b"abcdef", FrameSpread::Chunks).unwrap();
+        let mut chunks = png.chunks().to_vec();
+        let piece = fctl_position(&png, 1).unwrap() + 1;
+        chunks.remove(piece);
+        let png = Png::from_chunks(chunks);
+        assert!(reveal_from_frames(&png, &chunk_type()).is_err());
+    }
+
+    #[test]
+    fn test_hide_in_pixels_too_large() {
+        let mut png = animation();
+        let payload = vec![0u8; 8 * 4 * 4 * 3];
+        assert!(hide_in_frames(&mut png, &chunk_type(), &payload, FrameSpread::Pixels).is_err());
+    }
+
+    #[test]
+    fn test_split_roundtrip() {
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let id = hide_in_chunks(&mut png, &chunk_type(), &payload, 120).unwrap();
+        assert_eq!(id, 0);
+        let pieces = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type() == &chunk_type())
+            .count();
+        assert_eq!(pieces, 10);
+        assert!(png
+            .chunks()
+            .iter()
+            .all(|c| c.chunk_type() != &chunk_type() || c.length() <= 120));
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+
+        let second = hide_in_chunks(&mut png, &chunk_type(), b"second", 120).unwrap();
+        assert_eq!(second, 1);
+        let revealed = reveal_from_chunks(&png, &chunk_type()).unwrap();
+        assert_eq!(revealed, vec![(0, payload), (1, b"second".to_vec())]);
+    }
+
+    #[test]
+    fn test_split_reordered_chunks() {
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        hide_in_chunks(&mut png, &chunk_type(), b"abcdefghij", 23).unwrap();
+        let mut chunks = png.chunks().to_vec();
+        let end = chunks.len() - 1;
+        chunks[2..end].reverse();
+        let png = Png::from_chunks(chunks);
+        let revealed = reveal_from_chunks(&png, &chunk_type()).unwrap();
+        assert_eq!(revealed, vec![(0, b"abcdefghij".to_vec())]);
+    }
+
+    #[test]
+    fn test_split_missing_chunk() {
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        hide_in_chunks(&mut png, &chunk_type(), b"abcdefghij", 23).unwrap();
+        let mut chunks = png.chunks().to_vec();
+        chunks.remove(3);
+        let png = Png::from_chunks(chunks);
+        let err = reveal_from_chunks(&png, &chunk_type()).unwrap_err();
+        assert_eq!(err.to_string(), "Payload 0 is missing chunk 2 of 4");
+    }
+
+    #[test]
+    fn test_split_huge_total() {
+        let mut data = SplitHeader {
+            payload_id: 0,
+            index: 0,
+            total: u32::MAX,
+            checksum: 0,
+        }
+        .to_bytes();
+        data.extend(b"abc");
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        png.append_chunk(Chunk::new(chunk_type(), data));
+        let err = reveal_from_chunks(&png, &chunk_type()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Payload 0 is missing chunk 2 of {}", u32::MAX)
+        );
+    }
+
+    #[test]
+    fn test_split_ids_exhausted() {
+        let data = SplitHeader {
+            payload_id: u32::MAX,
+            index: 0,
+            total: 1,
+            checksum: 0,
+        }
+        .to_bytes();
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        png.append_chunk(Chunk::new(chunk_type(), data));
+        assert!(hide_in_chunks(&mut png, &chunk_type(), b"abc", 120).is_err());
+    }
+
+    #[test]
+    fn test_split_chunk_size_out_of_range() {
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        assert!(hide_in_chunks(&mut png, &chunk_type(), b"abc", SPLIT_HEADER_LEN).is_err());
+    }
+
+    #[test]
+    fn test_hide_requires_animation() {
+        let mut png = RgbaImage::new(2, 2).to_png().unwrap();
+        assert!(hide_in_frames(&mut png, &chunk_type(), b"x", FrameSpread::Chunks).is_err());
+        assert_eq!(reveal_from_frames(&png, &chunk_type()).unwrap(), None);
+    }
+}
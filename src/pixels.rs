@@ -0,0 +1,655 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::limits::Limits;
+use crate::palette::Palette;
+use crate::png::Png;
+use crate::zlib;
+use crate::Result;
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub enum PixelErr {
+    DataLength(usize, usize),
+    InvalidFilter(u8),
+    UnknownFilterStrategy(String),
+    MissingPalette,
+    IndexOutOfRange(u16, usize),
+    SizeMismatch(u32, u32, u32, u32),
+}
+impl std::error::Error for PixelErr {}
+impl Display for PixelErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            PixelErr::DataLength(expected, got) => write!(
+                f,
+                "Expected {} bytes of decompressed image data, got {}",
+                expected, got
+            ),
+            PixelErr::InvalidFilter(t) => write!(f, "Invalid scanline filter type {}", t),
+            PixelErr::UnknownFilterStrategy(s) => write!(f, "Unknown filter strategy {}", s),
+            PixelErr::MissingPalette => write!(f, "Indexed image has no PLTE chunk"),
+            PixelErr::IndexOutOfRange(i, len) => {
+                write!(f, "Pixel palette index {} out of range 0..{}", i, len)
+            }
+            PixelErr::SizeMismatch(w, h, ew, eh) => {
+                write!(f, "Image is {}x{}, expected {}x{}", w, h, ew, eh)
+            }
+        }
+    }
+}
+
+/// Scanline filter types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::None,
+        Filter::Sub,
+        Filter::Up,
+        Filter::Average,
+        Filter::Paeth,
+    ];
+}
+
+/// How the encoder picks a filter for each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    Fixed(Filter),
+    /// Per row, the filter with the smallest sum of absolute differences.
+    Adaptive,
+}
+
+impl FromStr for FilterStrategy {
+    type Err = crate::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(FilterStrategy::Fixed(Filter::None)),
+            "sub" => Ok(FilterStrategy::Fixed(Filter::Sub)),
+            "up" => Ok(FilterStrategy::Fixed(Filter::Up)),
+            "average" => Ok(FilterStrategy::Fixed(Filter::Average)),
+            "paeth" => Ok(FilterStrategy::Fixed(Filter::Paeth)),
+            "adaptive" => Ok(FilterStrategy::Adaptive),
+            _ => Err(Box::new(PixelErr::UnknownFilterStrategy(s.to_string()))),
+        }
+    }
+}
+impl Display for FilterStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            FilterStrategy::Fixed(filter) => write!(f, "{:?}", filter),
+            FilterStrategy::Adaptive => write!(f, "Adaptive"),
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the filter of one scanline in place. `bpp` is the filter's byte
+/// distance: bytes per complete pixel, rounded up to one.
+fn unfilter_row(filter: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> Result<()> {
+    match filter {
+        0 => {}
+        1 => {
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        2 => {
+            for (x, &up) in row.iter_mut().zip(prev) {
+                *x = x.wrapping_add(up);
+            }
+        }
+        3 => {
+            for i in 0..row.len() {
+                let left = if i >= bpp { row[i - bpp] as u16 } else { 0 };
+                row[i] = row[i].wrapping_add(((left + prev[i] as u16) / 2) as u8);
+            }
+        }
+        4 => {
+            for i in 0..row.len() {
+                let (left, upper_left) = if i >= bpp {
+                    (row[i - bpp], prev[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth(left, prev[i], upper_left));
+            }
+        }
+        t => return Err(Box::new(PixelErr::InvalidFilter(t))),
+    }
+    Ok(())
+}
+
+fn filter_row(filter: Filter, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(filter as u8);
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let upper_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match filter {
+            Filter::None => 0,
+            Filter::Sub => left,
+            Filter::Up => prev[i],
+            Filter::Average => ((left as u16 + prev[i] as u16) / 2) as u8,
+            Filter::Paeth => paeth(left, prev[i], upper_left),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+/// Adam7 passes as (x start, y start, x step, y step).
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Unfiltered samples of a whole image, packed into non-interlaced
+/// scanlines without filter type bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawImage {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub data: Vec<u8>,
+}
+
+impl RawImage {
+    pub fn ihdr(&self) -> Ihdr {
+        Ihdr {
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            interlaced: false,
+        }
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.ihdr().bits_per_pixel()
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        self.ihdr().row_bytes(self.width)
+    }
+
+    /// Decodes the concatenated `IDAT` data of `png`.
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let ihdr = Ihdr::from_png(png)?;
+        let data: Vec<u8> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        RawImage::decode(&ihdr, &data, png.limits())
+    }
+
+    /// Inflates and unfilters a zlib stream of image data laid out as `ihdr`
+    /// describes, deinterlacing Adam7 images.
+    pub fn decode(ihdr: &Ihdr, zlib_data: &[u8], limits: &Limits) -> Result<Self> {
+        limits.check_dimensions(ihdr.width, ihdr.height)?;
+        let raw = zlib::inflate(zlib_data, limits.max_decompressed_size)?;
+        let bits = ihdr.bits_per_pixel();
+        let bpp = bits.div_ceil(8);
+        let row_bytes = ihdr.row_bytes(ihdr.width);
+        let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+        let passes: Vec<_> = if ihdr.interlaced {
+            ADAM7.to_vec()
+        } else {
+            vec![(0, 0, 1, 1)]
+        };
+        let expected: usize = passes
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let (pw, ph) = pass_size(width, height, x0, y0, dx, dy);
+                if pw == 0 || ph == 0 {
+                    0
+                } else {
+                    (ihdr.row_bytes(pw as u32) + 1) * ph
+                }
+            })
+            .sum();
+        if raw.len() < expected {
+            return Err(Box::new(PixelErr::DataLength(expected, raw.len())));
+        }
+        // Only allocated once the inflated data shows the dimensions are real.
+        let mut image = Self {
+            width: ihdr.width,
+            height: ihdr.height,
+            bit_depth: ihdr.bit_depth,
+            color_type: ihdr.color_type,
+            data: vec![0; row_bytes * height],
+        };
+
+        let mut offset = 0;
+        for (x0, y0, dx, dy) in passes {
+            let (pw, ph) = pass_size(width, height, x0, y0, dx, dy);
+            if pw == 0 || ph == 0 {
+                continue;
+            }
+            let pass_row_bytes = ihdr.row_bytes(pw as u32);
+            let mut prev = vec![0; pass_row_bytes];
+            for py in 0..ph {
+                let filter = raw[offset];
+                let mut row = raw[offset + 1..offset + 1 + pass_row_bytes].to_vec();
+                offset += pass_row_bytes + 1;
+                unfilter_row(filter, &mut row, &prev, bpp)?;
+                let y = y0 + py * dy;
+                let dest = &mut image.data[y * row_bytes..(y + 1) * row_bytes];
+                if dx == 1 {
+                    dest.copy_from_slice(&row);
+                } else {
+                    for px in 0..pw {
+                        let value = get_bits(&row, px, bits);
+                        set_bits(dest, x0 + px * dx, bits, value);
+                    }
+                }
+                prev = row;
+            }
+        }
+        Ok(image)
+    }
+
+    /// Filters and deflates the image into a non-interlaced zlib stream.
+    pub fn encode(&self, strategy: FilterStrategy, level: u32) -> Result<Vec<u8>> {
+        zlib::deflate_level(&self.filter(strategy), level)
+    }
+
+    /// Filtered scanlines, each prefixed with its filter type byte.
+    pub fn filter(&self, strategy: FilterStrategy) -> Vec<u8> {
+        let row_bytes = self.row_bytes();
+        let bpp = self.bits_per_pixel().div_ceil(8);
+        let mut out = Vec::with_capacity((row_bytes + 1) * self.height as usize);
+        let zero = vec![0; row_bytes];
+        let mut candidate = Vec::with_capacity(row_bytes + 1);
+        for y in 0..self.height as usize {
+            let row = &self.data[y * row_bytes..(y + 1) * row_bytes];
+            let prev = if y == 0 {
+                &zero[..]
+            } else {
+                &self.data[(y - 1) * row_bytes..y * row_bytes]
+            };
+            match strategy {
+                FilterStrategy::Fixed(filter) => filter_row(filter, row, prev, bpp, &mut out),
+                FilterStrategy::Adaptive => {
+                    let best = Filter::ALL
+                        .iter()
+                        .min_by_key(|&&filter| {
+                            candidate.clear();
+                            filter_row(filter, row, prev, bpp, &mut candidate);
+                            candidate[1..]
+                                .iter()
+                                .map(|&b| (b as i8).unsigned_abs() as u64)
+                                .sum::<u64>()
+                        })
+                        .copied()
+                        .unwrap_or(Filter::None);
+                    filter_row(best, row, prev, bpp, &mut out);
+                }
+            }
+        }
+        out
+    }
+
+    /// Packs `samples`, one per channel of each pixel in row order, into an
+    /// image laid out as `ihdr` describes.
+    pub fn from_samples(ihdr: &Ihdr, samples: &[u16]) -> Self {
+        let row_bytes = ihdr.row_bytes(ihdr.width);
+        let per_row = ihdr.width as usize * ihdr.color_type.channels();
+        let mut data = vec![0; row_bytes * ihdr.height as usize];
+        for (row, values) in data.chunks_mut(row_bytes).zip(samples.chunks(per_row)) {
+            for (i, &value) in values.iter().enumerate() {
+                set_bits(row, i, ihdr.bit_depth as usize, value as u64);
+            }
+        }
+        Self {
+            width: ihdr.width,
+            height: ihdr.height,
+            bit_depth: ihdr.bit_depth,
+            color_type: ihdr.color_type,
+            data,
+        }
+    }
+
+    /// Raw samples of the pixel at (x, y), one per channel.
+    pub fn samples(&self, x: usize, y: usize) -> Vec<u16> {
+        let row = &self.data[y * self.row_bytes()..(y + 1) * self.row_bytes()];
+        let channels = self.color_type.channels();
+        match self.bit_depth {
+            16 => (0..channels)
+                .map(|c| {
+                    let i = (x * channels + c) * 2;
+                    u16::from_be_bytes([row[i], row[i + 1]])
+                })
+                .collect(),
+            8 => (0..channels)
+                .map(|c| row[x * channels + c] as u16)
+                .collect(),
+            depth => vec![get_bits(row, x, depth as usize) as u16],
+        }
+    }
+
+    /// Converts to 8-bit RGBA, resolving palette indices and `tRNS`
+    /// transparency. 16-bit samples keep their high byte.
+    pub fn to_rgba8(&self, palette: Option<&Palette>, trns: Option<&Chunk>) -> Result<RgbaImage> {
+        let depth = self.bit_depth;
+        let max = ((1u32 << depth) - 1) as u16;
+        let scale = |v: u16| match depth {
+            16 => (v >> 8) as u8,
+            8 => v as u8,
+            _ => (v as u32 * 255 / max as u32) as u8,
+        };
+        let key: Option<Vec<u16>> = match self.color_type {
+            ColorType::Grayscale | ColorType::Rgb => trns.map(|c| {
+                c.data()
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect()
+            }),
+            _ => None,
+        };
+        let mut pixels = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height as usize {
+            for x in 0..self.width as usize {
+                let s = self.samples(x, y);
+                let opaque = |s: &[u16]| match &key {
+                    Some(key) if key.as_slice() == s => 0,
+                    _ => 255,
+                };
+                let rgba = match self.color_type {
+                    ColorType::Grayscale => {
+                        let g = scale(s[0]);
+                        [g, g, g, opaque(&s)]
+                    }
+                    ColorType::GrayscaleAlpha => {
+                        let g = scale(s[0]);
+                        [g, g, g, scale(s[1])]
+                    }
+                    ColorType::Rgb => [scale(s[0]), scale(s[1]), scale(s[2]), opaque(&s)],
+                    ColorType::Rgba => [scale(s[0]), scale(s[1]), scale(s[2]), scale(s[3])],
+                    ColorType::Indexed => {
+                        let palette = palette.ok_or(PixelErr::MissingPalette)?;
+                        let e = palette
+                            .entries()
+                            .get(s[0] as usize)
+                            .ok_or(PixelErr::IndexOutOfRange(s[0], palette.len()))?;
+                        [e.red, e.green, e.blue, e.alpha]
+                    }
+                };
+                pixels.extend(rgba);
+            }
+        }
+        Ok(RgbaImage {
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+}
+
+fn pass_size(
+    width: usize,
+    height: usize,
+    x0: usize,
+    y0: usize,
+    dx: usize,
+    dy: usize,
+) -> (usize, usize) {
+    (
+        (width + dx - 1).saturating_sub(x0) / dx,
+        (height + dy - 1).saturating_sub(y0) / dy,
+    )
+}
+
+/// Reads pixel `x` of `bits` bits from a packed row.
+fn get_bits(row: &[u8], x: usize, bits: usize) -> u64 {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        row[x * bytes..(x + 1) * bytes]
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as u64)
+    } else {
+        let bit = x * bits;
+        let shift = 8 - bits - bit % 8;
+        ((row[bit / 8] >> shift) & ((1 << bits) - 1) as u8) as u64
+    }
+}
+
+fn set_bits(row: &mut [u8], x: usize, bits: usize, value: u64) {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        for (i, b) in row[x * bytes..(x + 1) * bytes].iter_mut().enumerate() {
+            *b = (value >> (8 * (bytes - 1 - i))) as u8;
+        }
+    } else {
+        let bit = x * bits;
+        let shift = 8 - bits - bit % 8;
+        let mask = (((1u16 << bits) - 1) as u8) << shift;
+        row[bit / 8] = (row[bit / 8] & !mask) | (((value as u8) << shift) & mask);
+    }
+}
+
+/// An 8-bit RGBA pixel buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// A fully transparent image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Decodes any png into 8-bit RGBA.
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let raw = RawImage::from_png(png)?;
+        let palette = match raw.color_type {
+            ColorType::Indexed => Some(Palette::from_png(png)?),
+            _ => None,
+        };
+        raw.to_rgba8(palette.as_ref(), png.chunk_by_type("tRNS"))
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    pub fn into_raw(self) -> RawImage {
+        RawImage {
+            width: self.width,
+            height: self.height,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            data: self.pixels,
+        }
+    }
+
+    /// Encodes as a minimal RGBA png: `IHDR`, one `IDAT` and `IEND`.
+    pub fn to_png(&self) -> Result<Png> {
+        let raw = self.clone().into_raw();
+        Ok(Png::from_chunks(vec![
+            raw.ihdr().to_chunk(),
+            Chunk::new(
+                ChunkType::from_str("IDAT")?,
+                raw.encode(FilterStrategy::Adaptive, 6)?,
+            ),
+            Chunk::new(ChunkType::from_str("IEND")?, vec![]),
+        ]))
+    }
+}
+
+impl TryFrom<&Png> for RgbaImage {
+    type Error = crate::Error;
+    fn try_from(png: &Png) -> Result<Self> {
+        RgbaImage::from_png(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_raw(bit_depth: u8, color_type: ColorType, width: u32, height: u32) -> RawImage {
+        let ihdr = Ihdr::new(width, height, bit_depth, color_type).unwrap();
+        let row_bytes = ihdr.row_bytes(width);
+        RawImage {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            data: (0..row_bytes * height as usize)
+                .map(|i| (i * 37 % 251) as u8)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_filter_roundtrip() {
+        let raw = testing_raw(8, ColorType::Rgba, 7, 5);
+        for &filter in Filter::ALL.iter() {
+            let encoded = raw.encode(FilterStrategy::Fixed(filter), 6).unwrap();
+            assert_eq!(
+                RawImage::decode(&raw.ihdr(), &encoded, &Limits::default()).unwrap(),
+                raw
+            );
+        }
+        let encoded = raw.encode(FilterStrategy::Adaptive, 9).unwrap();
+        assert_eq!(
+            RawImage::decode(&raw.ihdr(), &encoded, &Limits::default()).unwrap(),
+            raw
+        );
+    }
+
+    #[test]
+    fn test_sub_byte_depth_roundtrip() {
+        let raw = testing_raw(2, ColorType::Grayscale, 9, 3);
+        let encoded = raw.encode(FilterStrategy::Fixed(Filter::Paeth), 6).unwrap();
+        assert_eq!(
+            RawImage::decode(&raw.ihdr(), &encoded, &Limits::default()).unwrap(),
+            raw
+        );
+        assert_eq!(raw.samples(0, 0), vec![0]);
+    }
+
+    #[test]
+    fn test_adam7_deinterlace() {
+        // Interlace a known image by hand and check it decodes to the same
+        // pixels as the non-interlaced layout.
+        let raw = testing_raw(8, ColorType::Rgb, 11, 9);
+        let bits = raw.bits_per_pixel();
+        let mut interlaced = Vec::new();
+        for &(x0, y0, dx, dy) in ADAM7.iter() {
+            let (pw, ph) = pass_size(11, 9, x0, y0, dx, dy);
+            for py in 0..ph {
+                interlaced.push(0);
+                let y = y0 + py * dy;
+                let row = &raw.data[y * raw.row_bytes()..(y + 1) * raw.row_bytes()];
+                let mut pass_row = vec![0; raw.ihdr().row_bytes(pw as u32)];
+                for px in 0..pw {
+                    set_bits(&mut pass_row, px, bits, get_bits(row, x0 + px * dx, bits));
+                }
+                interlaced.extend(pass_row);
+            }
+        }
+        let mut ihdr = raw.ihdr();
+        ihdr.interlaced = true;
+        let decoded = RawImage::decode(
+            &ihdr,
+            &zlib::deflate(&interlaced).unwrap(),
+            &Limits::default(),
+        )
+        .unwrap();
+        assert_eq!(decoded, raw);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let raw = testing_raw(8, ColorType::Rgb, 4, 4);
+        let short = zlib::deflate(&raw.filter(FilterStrategy::Adaptive)[..10]).unwrap();
+        assert!(RawImage::decode(&raw.ihdr(), &short, &Limits::default()).is_err());
+    }
+
+    #[test]
+    fn test_declared_size_not_allocated() {
+        let ihdr = Ihdr::new(1 << 14, 1 << 14, 16, ColorType::Rgba).unwrap();
+        let tiny = zlib::deflate(&[0; 16]).unwrap();
+        let err = RawImage::decode(&ihdr, &tiny, &Limits::default()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PixelErr>(),
+            Some(PixelErr::DataLength(_, 16))
+        ));
+    }
+
+    #[test]
+    fn test_rgba8_from_image_file() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let image = RgbaImage::from_png(&png).unwrap();
+        assert_eq!((image.width, image.height), (50, 50));
+        let reencoded = RgbaImage::from_png(&image.to_png().unwrap()).unwrap();
+        assert_eq!(reencoded, image);
+    }
+
+    #[test]
+    fn test_grayscale_transparency_key() {
+        let raw = RawImage {
+            width: 2,
+            height: 1,
+            bit_depth: 4,
+            color_type: ColorType::Grayscale,
+            data: vec![0x0f],
+        };
+        let trns = Chunk::new(ChunkType::from_str("tRNS").unwrap(), vec![0, 15]);
+        let image = raw.to_rgba8(None, Some(&trns)).unwrap();
+        assert_eq!(image.pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(image.pixel(1, 0), [255, 255, 255, 0]);
+    }
+}
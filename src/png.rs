@@ -1,16 +1,23 @@
 use std::convert::{TryFrom, TryInto};
 use std::fmt::{Display, Formatter};
-use std::fs::{File, OpenOptions};
+use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::path::Path;
 
-use crate::chunk::Chunk;
+use crate::ancillary::{self, ImageContext};
+use crate::apng::Animation;
+use crate::atomic::{self, WriteOptions};
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_type::ChunkType;
+use crate::limits::Limits;
 use crate::{Error, Result};
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum PngErr {
     PngChunkTypeAbsent,
     PngHeaderLengthLess,
     PngHeaderInvalid,
+    PngDamaged(Diagnostic),
 }
 impl std::error::Error for PngErr {}
 impl Display for PngErr {
@@ -19,71 +26,584 @@ impl Display for PngErr {
             PngErr::PngChunkTypeAbsent => write!(f, "Png chunk type not present in chunks"),
             PngErr::PngHeaderLengthLess => write!(f, "Png header absent"),
             PngErr::PngHeaderInvalid => write!(f, "Png header invalid"),
+            PngErr::PngDamaged(diagnostic) => write!(f, "Png damaged: {}", diagnostic),
         }
     }
 }
+
+/// How [`Png::from_bytes_with`] treats damaged input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Fails on the first anomaly. Otherwise anomalies are recorded in
+    /// [`Png::diagnostics`] and as much of the file as possible is kept.
+    pub strict: bool,
+    /// A length field above this is taken as damage rather than a chunk,
+    /// so a lenient parse skips it as [`Diagnostic::ChunkTooLarge`]. It is
+    /// checked first; a chunk within it but above
+    /// [`Limits::max_chunk_length`] fails the parse even when lenient. By
+    /// default this is the format's maximum of 2^31-1, so lengths above
+    /// the 256 MiB limit are errors.
+    pub max_chunk_size: u32,
+    /// Accepts bytes after `IEND` that are not whole chunks even when
+    /// strict. They are dropped. Whole chunks after `IEND` are always kept.
+    pub allow_trailing_data: bool,
+    /// When not strict, skips garbage by scanning for the next chunk whose
+    /// crc matches instead of giving up on the rest of the file.
+    pub resync: bool,
+    /// Enforced whatever `strict` says, and kept by the parsed png for
+    /// decoding its image and compressed chunks.
+    pub limits: Limits,
+}
+
+impl Default for ParseOptions {
+    /// Strict parsing, as `Png::try_from` does.
+    fn default() -> Self {
+        Self {
+            strict: true,
+            max_chunk_size: Chunk::MAX_LENGTH,
+            allow_trailing_data: false,
+            resync: false,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Recovers from every anomaly it can.
+    pub fn lenient() -> Self {
+        Self {
+            strict: false,
+            allow_trailing_data: true,
+            resync: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// An anomaly met while parsing. Offsets are from the start of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The chunk is kept with the crc as stored.
+    CrcMismatch {
+        offset: usize,
+        chunk_type: String,
+        stored: u32,
+        computed: u32,
+    },
+    /// No chunk header at `offset`; `skipped` bytes up to the next chunk
+    /// or the end of the file were dropped.
+    InvalidChunk { offset: usize, skipped: usize },
+    /// The length field exceeds `max_chunk_size`; `skipped` bytes up to the
+    /// next chunk or the end of the file were dropped.
+    ChunkTooLarge {
+        offset: usize,
+        length: u32,
+        skipped: usize,
+    },
+    /// The file ends inside the chunk; the data present is kept.
+    Truncated {
+        offset: usize,
+        chunk_type: String,
+        length: u32,
+        present: usize,
+    },
+    /// Bytes after `IEND`, dropped.
+    TrailingData { offset: usize, length: usize },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Diagnostic::CrcMismatch {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "{} chunk at offset {} has crc {:08x}, expected {:08x}",
+                chunk_type, offset, stored, computed
+            ),
+            Diagnostic::InvalidChunk { offset, skipped } => write!(
+                f,
+                "No chunk at offset {}, skipped {} bytes",
+                offset, skipped
+            ),
+            Diagnostic::ChunkTooLarge {
+                offset,
+                length,
+                skipped,
+            } => write!(
+                f,
+                "Chunk at offset {} declares {} bytes, skipped {} bytes",
+                offset, length, skipped
+            ),
+            Diagnostic::Truncated {
+                offset,
+                chunk_type,
+                length,
+                present,
+            } => write!(
+                f,
+                "{} chunk at offset {} is truncated, {} of {} data bytes present",
+                chunk_type, offset, present, length
+            ),
+            Diagnostic::TrailingData { offset, length } => {
+                write!(f, "{} bytes after IEND at offset {}", length, offset)
+            }
+        }
+    }
+}
+
+/// What serializing a png does with the unsafe-to-copy chunks it does not
+/// understand once its critical chunks have changed. The spec requires
+/// dropping them, since they may describe the image data as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnsafeChunkPolicy {
+    #[default]
+    Drop,
+    Keep,
+}
+
 #[derive(Clone)]
 pub struct Png {
     chunks: Vec<Chunk>, // used vec instead of box as don't wanna worry about push
+    diagnostics: Vec<Diagnostic>,
+    limits: Limits,
+    /// Set when a critical chunk is added, removed or changed through the
+    /// methods below.
+    critical_modified: bool,
+    unsafe_chunk_policy: UnsafeChunkPolicy,
 }
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(bytes: &[u8]) -> Result<Self> {
+        Png::from_bytes_with(bytes, &ParseOptions::default())
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Whether a chunk header with an alphabetic type and an acceptable length
+/// starts at `offset`.
+fn is_chunk_header(bytes: &[u8], offset: usize, max_chunk_size: u32) -> bool {
+    offset + 8 <= bytes.len()
+        && bytes[offset + 4..offset + 8]
+            .iter()
+            .all(u8::is_ascii_alphabetic)
+        && u32_at(bytes, offset) <= max_chunk_size
+}
+
+/// Whether the bytes from `from` to the end are a run of whole chunks,
+/// as when chunks have been appended after `IEND`.
+fn chunks_to_end(bytes: &[u8], from: usize, max_chunk_size: u32) -> bool {
+    let mut offset = from;
+    while offset < bytes.len() {
+        if !is_chunk_header(bytes, offset, max_chunk_size) {
+            return false;
+        }
+        offset += 12 + u32_at(bytes, offset) as usize;
+    }
+    offset == bytes.len()
+}
+
+/// Offset of the first whole chunk with a matching crc at or after `from`.
+fn resync(bytes: &[u8], from: usize, max_chunk_size: u32) -> Option<usize> {
+    (from..bytes.len().saturating_sub(11)).find(|&offset| {
+        if !is_chunk_header(bytes, offset, max_chunk_size) {
+            return false;
+        }
+        let end = offset + 12 + u32_at(bytes, offset) as usize;
+        end <= bytes.len()
+            && Chunk::parse(&bytes[offset..end], false).is_ok_and(|chunk| chunk.crc_ok())
+    })
+}
+
+impl Png {
+    /// Parses a whole file, handling damage as `options` say.
+    pub fn from_bytes_with(bytes: &[u8], options: &ParseOptions) -> Result<Self> {
         if bytes.len() < 8 {
             return Err(Box::new(PngErr::PngHeaderLengthLess));
         }
         if Png::STANDARD_HEADER != bytes[..8] {
             return Err(Box::new(PngErr::PngHeaderInvalid));
         }
-        let mut index = 8;
-        let mut chunks = Vec::new();
-        while index < bytes.len() {
-            let length = u32::from_be_bytes(bytes[index..index + 4].try_into()?) as usize;
-            let chunk = Chunk::try_from(&bytes[index..index + length + 12])?;
-            index += length + 12;
+        let limits = &options.limits;
+        limits.check_file_size(bytes.len() as u64)?;
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut report = |diagnostic: Diagnostic, fatal: bool| -> Result<()> {
+            if fatal {
+                return Err(Box::new(PngErr::PngDamaged(diagnostic)));
+            }
+            diagnostics.push(diagnostic);
+            Ok(())
+        };
+        let max = options.max_chunk_size;
+        let mut offset = 8;
+        while offset < bytes.len() {
+            if chunks
+                .last()
+                .is_some_and(|c| c.chunk_type().bytes() == *b"IEND")
+                && !chunks_to_end(bytes, offset, max)
+            {
+                let length = bytes.len() - offset;
+                let fatal = options.strict && !options.allow_trailing_data;
+                report(Diagnostic::TrailingData { offset, length }, fatal)?;
+                break;
+            }
+            // Where parsing picks up again if the chunk here is unusable.
+            let next = || {
+                Some(offset + 1)
+                    .filter(|_| options.resync)
+                    .and_then(|from| resync(bytes, from, max))
+                    .unwrap_or(bytes.len())
+            };
+            if !is_chunk_header(bytes, offset, u32::MAX) {
+                let next = next();
+                let skipped = next - offset;
+                report(Diagnostic::InvalidChunk { offset, skipped }, options.strict)?;
+                offset = next;
+                continue;
+            }
+            let length = u32_at(bytes, offset);
+            if length > max {
+                let next = next();
+                let skipped = next - offset;
+                let diagnostic = Diagnostic::ChunkTooLarge {
+                    offset,
+                    length,
+                    skipped,
+                };
+                report(diagnostic, options.strict)?;
+                offset = next;
+                continue;
+            }
+            let end = offset + 12 + length as usize;
+            if end > bytes.len() {
+                let next = next();
+                if next < bytes.len() {
+                    // A later chunk is intact, so the length is what is wrong.
+                    let skipped = next - offset;
+                    report(Diagnostic::InvalidChunk { offset, skipped }, options.strict)?;
+                    offset = next;
+                    continue;
+                }
+                let type_bytes: [u8; 4] = bytes[offset + 4..offset + 8].try_into()?;
+                let chunk_type = ChunkType::try_from(type_bytes)?;
+                let data = &bytes[offset + 8..bytes.len().min(end - 4)];
+                let diagnostic = Diagnostic::Truncated {
+                    offset,
+                    chunk_type: chunk_type.to_string(),
+                    length,
+                    present: data.len(),
+                };
+                report(diagnostic, options.strict)?;
+                limits.check_chunk_length(length)?;
+                limits.check_chunks(chunks.len() + 1)?;
+                chunks.push(Chunk::new(chunk_type, data.to_vec()));
+                break;
+            }
+            limits.check_chunk_length(length)?;
+            limits.check_chunks(chunks.len() + 1)?;
+            let chunk = Chunk::parse(&bytes[offset..end], false)?;
+            if chunk.chunk_type().bytes() == *b"IHDR" && chunk.data().len() >= 8 {
+                let data = chunk.data();
+                limits.check_dimensions(
+                    u32::from_be_bytes(data[..4].try_into()?),
+                    u32::from_be_bytes(data[4..8].try_into()?),
+                )?;
+            }
+            if !chunk.crc_ok() {
+                let diagnostic = Diagnostic::CrcMismatch {
+                    offset,
+                    chunk_type: chunk.chunk_type().to_string(),
+                    stored: chunk.crc(),
+                    computed: chunk.computed_crc(),
+                };
+                report(diagnostic, options.strict)?;
+            }
             chunks.push(chunk);
+            offset = end;
+        }
+
+        Ok(Png {
+            chunks,
+            diagnostics,
+            limits: options.limits.clone(),
+            critical_modified: false,
+            unsafe_chunk_policy: UnsafeChunkPolicy::default(),
+        })
+    }
+}
+/// One row of the chunk table shown by `print`.
+pub struct ChunkInfo<'a> {
+    pub index: usize,
+    /// Offset of the chunk's length field from the start of the file.
+    pub offset: usize,
+    pub chunk: ChunkRef<'a>,
+    /// Decoded summary for chunk types PNGme knows, otherwise the start of
+    /// the data as text or hex.
+    pub preview: String,
+}
+
+impl ChunkInfo<'_> {
+    /// Critical, public and safe-to-copy flags as `C`, `P` and `S`, with
+    /// `-` for each flag that is clear.
+    pub fn flags(&self) -> String {
+        let t = self.chunk.chunk_type();
+        [
+            (t.is_critical(), 'C'),
+            (t.is_public(), 'P'),
+            (t.is_safe_to_copy(), 'S'),
+        ]
+        .iter()
+        .map(|&(set, c)| if set { c } else { '-' })
+        .collect()
+    }
+}
+
+/// Rows for `chunks`, each paired with the owned chunk known types are
+/// described from, which for a [`PngRef`] may hold only part of the data.
+fn chunk_infos<'a, 'b>(
+    chunks: impl Iterator<Item = (ChunkRef<'a>, &'b Chunk)>,
+    ctx: &ImageContext,
+) -> Vec<ChunkInfo<'a>> {
+    let mut offset = Png::STANDARD_HEADER.len();
+    chunks
+        .enumerate()
+        .map(|(index, (chunk, described))| {
+            let info = ChunkInfo {
+                index,
+                offset,
+                preview: preview(described, chunk.data(), ctx),
+                chunk,
+            };
+            offset += info.chunk.length() as usize + 12;
+            info
+        })
+        .collect()
+}
+
+fn preview(described: &Chunk, data: &[u8], ctx: &ImageContext) -> String {
+    match ancillary::describe(described, ctx) {
+        Some(Ok(description)) => return description,
+        Some(Err(e)) => return format!("invalid: {}", e),
+        None => {}
+    }
+    if data.is_empty() {
+        return String::new();
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && c != '\n') => {
+            format!("{:?}", truncate(text, 40))
+        }
+        _ => {
+            let hex: String = data.iter().take(12).map(|b| format!("{:02x}", b)).collect();
+            if data.len() > 12 {
+                format!("{}...", hex)
+            } else {
+                hex
+            }
         }
+    }
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    match s.char_indices().nth(max) {
+        Some((i, _)) => format!("{}...", &s[..i]),
+        None => s.to_string(),
+    }
+}
 
-        Ok(Png { chunks })
+/// A table of `infos`, one chunk per line, as `print` shows it.
+pub fn chunk_table(infos: &[ChunkInfo]) -> String {
+    let mut table = format!(
+        "{:>4}  {:>10}  {:4}  {:>10}  {:8}  {:5}  preview",
+        "#", "offset", "type", "length", "crc", "flags"
+    );
+    for info in infos {
+        table.push_str(&format!(
+            "\n{:>4}  {:>10}  {}  {:>10}  {:08x}  {:5}  {}",
+            info.index,
+            info.offset,
+            info.chunk.chunk_type(),
+            info.chunk.length(),
+            info.chunk.crc(),
+            info.flags(),
+            truncate(&info.preview, 60)
+        ));
     }
+    table
 }
+
 impl Display for Png {
+    /// A table of every chunk, one per line.
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        write!(
-            f,
-            "{}",
-            self.chunks.iter().fold(String::new(), |mut acc, chunk| {
-                acc.push_str(" -> ");
-                acc
-            })
-        )
+        f.write_str(&chunk_table(&self.chunk_infos()))
+    }
+}
+impl Display for PngRef<'_> {
+    /// The same table as for [`Png`].
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(&chunk_table(&self.chunk_infos()))
     }
 }
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
-        Self { chunks }
+        Self {
+            chunks,
+            diagnostics: Vec::new(),
+            limits: Limits::default(),
+            critical_modified: false,
+            unsafe_chunk_policy: UnsafeChunkPolicy::default(),
+        }
+    }
+    /// Replaces the limits used when decoding this png's data.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+    /// The limits the png was parsed with, which its decoders enforce.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+    /// Whether a critical chunk changed since the png was parsed or built.
+    pub fn critical_modified(&self) -> bool {
+        self.critical_modified
+    }
+    /// Records a change to the critical chunks made outside the methods
+    /// that track it, such as rebuilding the png around new image data.
+    pub fn mark_critical_modified(&mut self) {
+        self.critical_modified = true;
+    }
+    pub fn set_unsafe_chunk_policy(&mut self, policy: UnsafeChunkPolicy) {
+        self.unsafe_chunk_policy = policy;
+    }
+    pub fn unsafe_chunk_policy(&self) -> UnsafeChunkPolicy {
+        self.unsafe_chunk_policy
+    }
+    /// The ancillary chunks that are unsafe to copy and of no registered
+    /// type, if the critical chunks changed; empty otherwise. Serializing
+    /// drops them unless the policy is [`UnsafeChunkPolicy::Keep`].
+    pub fn stale_chunks(&self) -> Vec<&Chunk> {
+        self.chunks.iter().filter(|c| self.is_stale(c)).collect()
+    }
+    fn is_stale(&self, chunk: &Chunk) -> bool {
+        let t = chunk.chunk_type();
+        self.critical_modified
+            && !t.is_critical()
+            && !t.is_safe_to_copy()
+            && !ancillary::STANDARD_CHUNK_TYPES.contains(&t.to_string().as_str())
+    }
+    /// Parses with [`ParseOptions::lenient`], keeping whatever can be
+    /// recovered; [`Png::diagnostics`] lists what was wrong.
+    pub fn from_bytes_lenient(bytes: &[u8]) -> Result<Self> {
+        Png::from_bytes_with(bytes, &ParseOptions::lenient())
+    }
+    /// Anomalies recorded by a lenient parse, empty otherwise.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+    /// Indices of chunks whose stored crc does not match their contents.
+    pub fn crc_errors(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.crc_ok())
+            .map(|(i, _)| i)
+            .collect()
     }
     pub fn from_file(p: &Path) -> Result<Self> {
-        let mut f = OpenOptions::new().read(true).open(p)?;
+        Png::from_file_with(p, &ParseOptions::default())
+    }
+    pub fn from_file_with(p: &Path, options: &ParseOptions) -> Result<Self> {
+        let f = OpenOptions::new().read(true).open(p)?;
+        options.limits.check_file_size(f.metadata()?.len())?;
+        Png::read_from_with(f, options)
+    }
+    /// Reads a png from a stream such as stdin, up to the end.
+    pub fn read_from<R: Read>(r: R) -> Result<Self> {
+        Png::read_from_with(r, &ParseOptions::default())
+    }
+    /// Like [`Png::read_from`], reading no more than the file size limit
+    /// allows, since a stream's length is not known up front.
+    pub fn read_from_with<R: Read>(r: R, options: &ParseOptions) -> Result<Self> {
         let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer)?;
-        Png::try_from(&buffer[..])
+        r.take(options.limits.max_file_size.saturating_add(1))
+            .read_to_end(&mut buffer)?;
+        options.limits.check_file_size(buffer.len() as u64)?;
+        Png::from_bytes_with(&buffer, options)
     }
-    pub fn to_file(&self, p: &Path) -> Result<()> {
-        let mut f = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(p)?;
-        f.write_all(&self.as_bytes())?;
-        f.flush()?;
+    /// Streams the png's bytes to `w` chunk by chunk, without building
+    /// them in memory first. `w` is best buffered, since every chunk field
+    /// is a separate write.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        w.write_all(self.header())?;
+        for chunk in self.written_chunks() {
+            chunk.write_to(&mut w)?;
+        }
+        w.flush()?;
         Ok(())
     }
+    /// Writes the png to `p` atomically, so that a failure part way
+    /// leaves any file already there untouched.
+    pub fn to_file(&self, p: &Path) -> Result<()> {
+        self.to_file_with(p, &WriteOptions::default())
+    }
+    pub fn to_file_with(&self, p: &Path, options: &WriteOptions) -> Result<()> {
+        atomic::write_with(p, options, |w| self.write_to(w))
+    }
     pub fn append_chunk(&mut self, chunk: Chunk) {
+        self.critical_modified |= chunk.chunk_type().is_critical();
         self.chunks.push(chunk);
     }
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.critical_modified |= chunk.chunk_type().is_critical();
+        self.chunks.insert(index, chunk);
+    }
+    /// Index of the first chunk of `chunk_type`.
+    pub fn position(&self, chunk_type: &str) -> Option<usize> {
+        self.chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == chunk_type)
+    }
+    /// Replaces the first chunk of the same type as `chunk` in place,
+    /// returning the chunk it replaced.
+    pub fn replace_chunk(&mut self, chunk: Chunk) -> Option<Chunk> {
+        let idx = self.position(&chunk.chunk_type().to_string())?;
+        self.critical_modified |=
+            chunk.chunk_type().is_critical() && chunk.data() != self.chunks[idx].data();
+        Some(std::mem::replace(&mut self.chunks[idx], chunk))
+    }
+    /// Inserts `chunk` before the first `PLTE` or `IDAT` chunk, or before
+    /// `IEND` if there is no image data.
+    pub fn insert_before_image_data(&mut self, chunk: Chunk) {
+        let idx = self
+            .chunks
+            .iter()
+            .position(|c| {
+                matches!(
+                    c.chunk_type().bytes(),
+                    [b'P', b'L', b'T', b'E'] | [b'I', b'D', b'A', b'T'] | [b'I', b'E', b'N', b'D']
+                )
+            })
+            .unwrap_or(self.chunks.len());
+        self.insert_chunk(idx, chunk);
+    }
+    /// The frames of an animated png, or `None` if there is no `acTL` chunk.
+    pub fn animation(&self) -> Result<Option<Animation>> {
+        Animation::from_png(self)
+    }
+    /// Index, offset and preview of every chunk.
+    pub fn chunk_infos(&self) -> Vec<ChunkInfo<'_>> {
+        let ctx = ImageContext::from_png(self);
+        chunk_infos(self.chunks.iter().map(|c| (c.view(), c)), &ctx)
+    }
     pub fn header(&self) -> &[u8; 8] {
         &Png::STANDARD_HEADER
     }
@@ -103,31 +623,198 @@ impl Png {
             .iter()
             .position(|c| c.chunk_type().to_string() == chunk_type)
             .ok_or(PngErr::PngChunkTypeAbsent)?;
-        Ok(self.chunks.remove(idx))
+        let chunk = self.chunks.remove(idx);
+        self.critical_modified |= chunk.chunk_type().is_critical();
+        Ok(chunk)
+    }
+    /// Removes every chunk `predicate` holds for, returning them in file
+    /// order.
+    pub fn remove_chunks<F: FnMut(&Chunk) -> bool>(&mut self, mut predicate: F) -> Vec<Chunk> {
+        let (removed, kept): (Vec<Chunk>, _) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|c| predicate(c));
+        self.chunks = kept;
+        self.critical_modified |= removed.iter().any(|c| c.chunk_type().is_critical());
+        removed
     }
+    /// The chunks written out, leaving out [`Png::stale_chunks`] under
+    /// [`UnsafeChunkPolicy::Drop`].
+    fn written_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        let drop_stale = self.unsafe_chunk_policy == UnsafeChunkPolicy::Drop;
+        self.chunks
+            .iter()
+            .filter(move |c| !(drop_stale && self.is_stale(c)))
+    }
+    /// Length of the file [`Png::write_to`] writes.
+    pub fn encoded_len(&self) -> usize {
+        self.header().len() + self.written_chunks().map(Chunk::encoded_len).sum::<usize>()
+    }
+    /// The file's bytes, in a buffer allocated once at its final size.
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.header()
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec does not fail");
+        bytes
+    }
+}
+
+/// A png whose chunks borrow from the buffer it was parsed from, for
+/// reading large files without copying their data. Crcs are only checked
+/// by [`PngRef::verify`].
+#[derive(Debug, Clone)]
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+    limits: Limits,
+}
+
+impl<'a> PngRef<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        PngRef::parse_with(bytes, &Limits::default())
+    }
+    /// Parses as strictly as [`Png::try_from`], failing on the same damage
+    /// except crc mismatches.
+    pub fn parse_with(bytes: &'a [u8], limits: &Limits) -> Result<Self> {
+        if bytes.len() < 8 {
+            return Err(Box::new(PngErr::PngHeaderLengthLess));
+        }
+        if Png::STANDARD_HEADER != bytes[..8] {
+            return Err(Box::new(PngErr::PngHeaderInvalid));
+        }
+        limits.check_file_size(bytes.len() as u64)?;
+        let damaged = |diagnostic| Err(Box::new(PngErr::PngDamaged(diagnostic)) as Error);
+        let mut chunks: Vec<ChunkRef> = Vec::new();
+        let mut offset = 8;
+        while offset < bytes.len() {
+            let skipped = bytes.len() - offset;
+            if chunks
+                .last()
+                .is_some_and(|c| c.chunk_type().bytes() == *b"IEND")
+                && !chunks_to_end(bytes, offset, Chunk::MAX_LENGTH)
+            {
+                let length = skipped;
+                return damaged(Diagnostic::TrailingData { offset, length });
+            }
+            if !is_chunk_header(bytes, offset, u32::MAX) {
+                return damaged(Diagnostic::InvalidChunk { offset, skipped });
+            }
+            let length = u32_at(bytes, offset);
+            if length > Chunk::MAX_LENGTH {
+                return damaged(Diagnostic::ChunkTooLarge {
+                    offset,
+                    length,
+                    skipped,
+                });
+            }
+            let end = offset + 12 + length as usize;
+            if end > bytes.len() {
+                return damaged(Diagnostic::Truncated {
+                    offset,
+                    chunk_type: String::from_utf8_lossy(&bytes[offset + 4..offset + 8])
+                        .into_owned(),
+                    length,
+                    present: bytes.len().min(end - 4) - (offset + 8),
+                });
+            }
+            limits.check_chunk_length(length)?;
+            limits.check_chunks(chunks.len() + 1)?;
+            let chunk = ChunkRef::parse(&bytes[offset..end])?;
+            if chunk.chunk_type().bytes() == *b"IHDR" && chunk.data().len() >= 8 {
+                let data = chunk.data();
+                limits.check_dimensions(
+                    u32::from_be_bytes(data[..4].try_into()?),
+                    u32::from_be_bytes(data[4..8].try_into()?),
+                )?;
+            }
+            chunks.push(chunk);
+            offset = end;
+        }
+        Ok(PngRef {
+            chunks,
+            limits: limits.clone(),
+        })
+    }
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().to_string() == chunk_type)
+    }
+    /// Checks every crc, failing on the first mismatch as a strict parse
+    /// would.
+    pub fn verify(&self) -> Result<()> {
+        let mut offset = Png::STANDARD_HEADER.len();
+        for chunk in &self.chunks {
+            let computed = chunk.computed_crc();
+            if chunk.crc() != computed {
+                return Err(Box::new(PngErr::PngDamaged(Diagnostic::CrcMismatch {
+                    offset,
+                    chunk_type: chunk.chunk_type().to_string(),
+                    stored: chunk.crc(),
+                    computed,
+                })));
+            }
+            offset += chunk.length() as usize + 12;
+        }
+        Ok(())
+    }
+    /// An owned copy of every chunk.
+    pub fn to_png(&self) -> Png {
+        self.select(|_| true)
+    }
+    /// An owned copy of the chunks `predicate` selects, in order.
+    pub fn select<F: FnMut(&ChunkRef<'a>) -> bool>(&self, mut predicate: F) -> Png {
+        Png::from_chunks(
+            self.chunks
+                .iter()
+                .filter(|c| predicate(c))
+                .map(ChunkRef::to_chunk)
+                .collect(),
+        )
+        .with_limits(self.limits.clone())
+    }
+    /// An owned copy with the image data of `IDAT` and `fdAT` chunks cut
+    /// to its first byte, keeping `fdAT` sequence numbers. Chunk order and
+    /// every check short of decoding pixels stay as for [`PngRef::to_png`],
+    /// for a fraction of the memory.
+    pub fn metadata(&self) -> Png {
+        let chunks = self
+            .chunks
             .iter()
-            .chain(
-                self.chunks
-                    .iter()
-                    .flat_map(|chunk| chunk.as_bytes().into_iter())
-                    .collect::<Vec<u8>>()
-                    .iter(),
-            )
-            .cloned()
-            .collect::<Vec<_>>()
+            .map(|c| {
+                let kept = match &c.chunk_type().bytes() {
+                    b"IDAT" => 1,
+                    b"fdAT" => 5,
+                    _ => return c.to_chunk(),
+                };
+                let data = c.data();
+                Chunk::new(
+                    c.chunk_type().clone(),
+                    data[..data.len().min(kept)].to_vec(),
+                )
+            })
+            .collect();
+        Png::from_chunks(chunks).with_limits(self.limits.clone())
+    }
+    /// Index, offset and preview of every chunk, as [`Png::chunk_infos`]
+    /// gives them.
+    pub fn chunk_infos(&self) -> Vec<ChunkInfo<'a>> {
+        let metadata = self.metadata();
+        let ctx = ImageContext::from_png(&metadata);
+        chunk_infos(self.chunks.iter().cloned().zip(metadata.chunks()), &ctx)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
     use std::convert::TryFrom;
     use std::str::FromStr;
 
+    #[allow(clippy::vec_init_then_push)]
     fn testing_chunks() -> Vec<Chunk> {
         let mut chunks = Vec::new();
 
@@ -246,6 +933,55 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunks() {
+        let mut png = testing_png();
+        let removed = png.remove_chunks(|c| !c.chunk_type().is_critical());
+        let types: Vec<String> = removed.iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["miDl"]);
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.remove_chunks(|_| false).is_empty());
+    }
+
+    #[test]
+    fn test_unsafe_chunks_dropped_after_critical_change() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(1, chunk_from_strings("prIV", "offsets").unwrap());
+        png.insert_chunk(1, chunk_from_strings("prIv", "safe").unwrap());
+        let time = vec![0x07, 0xea, 10, 18, 0, 0, 0];
+        png.insert_chunk(1, Chunk::new(ChunkType::from_str("tIME").unwrap(), time));
+        assert!(!png.critical_modified());
+        assert!(png.stale_chunks().is_empty());
+        let length = png.as_bytes().len();
+
+        png.remove_chunk("RuSt").unwrap();
+        assert!(png.critical_modified());
+        let stale: Vec<String> = png
+            .stale_chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        // gAMA, sRGB and tIME are unsafe to copy too, but registered.
+        assert_eq!(stale, ["prIV"]);
+        let reparsed = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert!(reparsed.chunk_by_type("prIV").is_none());
+        assert!(reparsed.chunk_by_type("prIv").is_some());
+
+        png.set_unsafe_chunk_policy(UnsafeChunkPolicy::Keep);
+        let rust = Png::try_from(&PNG_FILE[..]).unwrap().chunks()[5].clone();
+        png.insert_chunk(7, rust);
+        assert_eq!(png.as_bytes().len(), length);
+    }
+
+    #[test]
+    fn test_insert_before_image_data_tracks_critical_change() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_image_data(chunk_from_strings("teXt", "ancillary").unwrap());
+        assert!(!png.critical_modified());
+        png.insert_before_image_data(chunk_from_strings("PLTE", "critical").unwrap());
+        assert!(png.critical_modified());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -253,6 +989,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::iter_cloned_collect)]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
@@ -260,6 +997,19 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_read_from_and_write_to() {
+        let png = Png::read_from(&PNG_FILE[..]).unwrap();
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, PNG_FILE);
+        assert_eq!(png.encoded_len(), PNG_FILE.len());
+
+        let mut options = ParseOptions::default();
+        options.limits.max_file_size = 100;
+        assert!(Png::read_from_with(&PNG_FILE[..], &options).is_err());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -278,8 +1028,230 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    #[test]
+    fn test_from_bytes_lenient() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Flip a bit in the gAMA data.
+        bytes[54] ^= 1;
+        assert!(Png::try_from(&bytes[..]).is_err());
+        let png = Png::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(png.crc_errors(), vec![2]);
+        assert!(matches!(
+            png.diagnostics(),
+            [Diagnostic::CrcMismatch { offset: 46, .. }]
+        ));
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let bytes = &PNG_FILE[..1000];
+        assert!(Png::try_from(bytes).is_err());
+        let png = Png::from_bytes_lenient(bytes).unwrap();
+        assert_eq!(png.chunks().len(), 5);
+        let idat = &png.chunks()[4];
+        assert_eq!(idat.chunk_type().to_string(), "IDAT");
+        assert_eq!(idat.data(), &PNG_FILE[91..1000]);
+        assert!(matches!(
+            png.diagnostics(),
+            [Diagnostic::Truncated {
+                offset: 83,
+                present: 909,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_parse_resync_over_garbage() {
+        let mut bytes = PNG_FILE[..83].to_vec();
+        bytes.extend_from_slice(b"\x00\x01junk\xff");
+        bytes.extend_from_slice(&PNG_FILE[83..]);
+        assert!(Png::try_from(&bytes[..]).is_err());
+
+        let png = Png::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+        assert_eq!(
+            png.diagnostics(),
+            [Diagnostic::InvalidChunk {
+                offset: 83,
+                skipped: 7
+            }]
+        );
+
+        let options = ParseOptions {
+            resync: false,
+            ..ParseOptions::lenient()
+        };
+        let png = Png::from_bytes_with(&bytes, &options).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+    }
+
+    #[test]
+    fn test_parse_max_chunk_size() {
+        let options = ParseOptions {
+            max_chunk_size: 1000,
+            ..ParseOptions::lenient()
+        };
+        let png = Png::from_bytes_with(&PNG_FILE, &options).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "pHYs", "RuSt", "IEND"]);
+        assert!(matches!(
+            png.diagnostics(),
+            [Diagnostic::ChunkTooLarge { offset: 83, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_parse_trailing_data() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailing");
+        assert!(Png::try_from(&bytes[..]).is_err());
+        let options = ParseOptions {
+            allow_trailing_data: true,
+            ..ParseOptions::default()
+        };
+        let png = Png::from_bytes_with(&bytes, &options).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+        assert_eq!(
+            png.diagnostics(),
+            [Diagnostic::TrailingData {
+                offset: PNG_FILE.len(),
+                length: 8
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_chunks_after_iend() {
+        let bytes = include_bytes!("../examples/pricechanges_secret.png");
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert!(png.diagnostics().is_empty());
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .rev()
+            .take(2)
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["DOGE", "IEND"]);
+        assert_eq!(png.as_bytes(), bytes.to_vec());
+    }
+
+    #[test]
+    fn test_png_ref_chunks_after_iend() {
+        let bytes = include_bytes!("../examples/pricechanges_secret.png");
+        let png = PngRef::parse(bytes).unwrap();
+        assert!(png.chunk_by_type("DOGE").is_some());
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailing");
+        assert!(PngRef::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_append_chunk_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("DOGE", "to the moon").unwrap());
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        let chunk = png.chunk_by_type("DOGE").unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "to the moon");
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let parse = |limits: Limits| {
+            let options = ParseOptions {
+                limits,
+                ..ParseOptions::lenient()
+            };
+            Png::from_bytes_with(&PNG_FILE, &options)
+        };
+        assert!(parse(Limits::default()).is_ok());
+        for limits in [
+            Limits {
+                max_file_size: 4802,
+                ..Limits::default()
+            },
+            Limits {
+                max_chunk_length: 4680,
+                ..Limits::default()
+            },
+            Limits {
+                max_chunks: 6,
+                ..Limits::default()
+            },
+            Limits {
+                max_width: 49,
+                ..Limits::default()
+            },
+        ] {
+            assert!(parse(limits).is_err());
+        }
+        let png = parse(Limits {
+            max_decompressed_size: 100,
+            ..Limits::default()
+        })
+        .unwrap();
+        assert!(crate::pixels::RawImage::from_png(&png).is_err());
+    }
+
+    #[test]
+    fn test_chunk_infos() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let infos = png.chunk_infos();
+        assert_eq!(infos.len(), png.chunks().len());
+        assert_eq!(infos[0].offset, 8);
+        assert_eq!(infos[1].offset, 8 + 12 + 13);
+        assert_eq!(infos[0].flags(), "CP-");
+        assert_eq!(infos[0].preview, "50x50 8-bit rgba");
+
+        let png = testing_png();
+        let infos = png.chunk_infos();
+        assert_eq!(infos[1].flags(), "--S");
+        assert_eq!(infos[1].preview, "\"I am another chunk\"");
+        let table = png.to_string();
+        assert_eq!(table.lines().count(), 4);
+        assert!(table.lines().nth(2).unwrap().contains("miDl"));
+    }
+
+    #[test]
+    fn test_png_ref_matches_png() {
+        let view = PngRef::parse(&PNG_FILE).unwrap();
+        view.verify().unwrap();
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(view.to_string(), png.to_string());
+        assert_eq!(view.to_png().as_bytes(), PNG_FILE);
+        assert_eq!(view.chunk_by_type("RuSt").unwrap().data(), b"hey");
+        let idat = view.chunk_by_type("IDAT").unwrap().data();
+        assert!(std::ptr::eq(idat, &PNG_FILE[91..91 + idat.len()]));
+        assert_eq!(view.metadata().chunk_by_type("IDAT").unwrap().length(), 1);
+        assert_eq!(
+            view.select(|c| c.chunk_type().is_critical()).chunks().len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_png_ref_damage() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[40] ^= 1;
+        // The crc is only checked when asked.
+        let view = PngRef::parse(&bytes).unwrap();
+        assert!(view.verify().is_err());
+        assert!(Png::try_from(&bytes[..]).is_err());
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.push(0);
+        assert!(PngRef::parse(&bytes).is_err());
+        assert!(PngRef::parse(&PNG_FILE[..PNG_FILE.len() - 20]).is_err());
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
-    const PNG_FILE: [u8; 4803] = [
+    pub(crate) const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
         6, 0, 0, 0, 30, 63, 136, 177, 0, 0, 0, 1, 115, 82, 71, 66, 0, 174, 206, 28, 233, 0, 0, 0,
         4, 103, 65, 77, 65, 0, 0, 177, 143, 11, 252, 97, 5, 0, 0, 0, 9, 112, 72, 89, 115, 0, 0, 14,
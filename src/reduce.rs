@@ -0,0 +1,260 @@
+use crate::ihdr::{ColorType, Ihdr};
+use crate::palette::{Palette, PaletteEntry};
+use crate::pixels::{PixelErr, RawImage};
+use crate::png::Png;
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Chunks whose layout depends on the color type or palette, which a
+/// reduction would have to rewrite.
+pub const DEPENDENT_CHUNK_TYPES: [&str; 3] = ["bKGD", "sBIT", "hIST"];
+
+/// Every pixel of `png` as 16-bit RGBA, with palettes and `tRNS` applied
+/// and lower bit depths scaled up, so that two encodings of the same
+/// image compare equal.
+pub fn rgba16(png: &Png) -> Result<Vec<[u16; 4]>> {
+    let raw = RawImage::from_png(png)?;
+    let palette = match raw.color_type {
+        ColorType::Indexed => Some(Palette::from_png(png)?),
+        _ => None,
+    };
+    let key: Option<Vec<u16>> = match raw.color_type {
+        ColorType::Grayscale | ColorType::Rgb => png.chunk_by_type("tRNS").map(|c| {
+            c.data()
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect()
+        }),
+        _ => None,
+    };
+    let max = ((1u32 << raw.bit_depth) - 1) as u16;
+    let scale = |v: u16| (v as u32 * 65535 / max as u32) as u16;
+    let mut pixels = Vec::with_capacity(raw.width as usize * raw.height as usize);
+    for y in 0..raw.height as usize {
+        for x in 0..raw.width as usize {
+            let s = raw.samples(x, y);
+            let alpha = if key.as_deref() == Some(&s[..]) {
+                0
+            } else {
+                65535
+            };
+            pixels.push(match raw.color_type {
+                ColorType::Grayscale => [scale(s[0]), scale(s[0]), scale(s[0]), alpha],
+                ColorType::GrayscaleAlpha => [scale(s[0]), scale(s[0]), scale(s[0]), scale(s[1])],
+                ColorType::Rgb => [scale(s[0]), scale(s[1]), scale(s[2]), alpha],
+                ColorType::Rgba => [scale(s[0]), scale(s[1]), scale(s[2]), scale(s[3])],
+                ColorType::Indexed => {
+                    let e = palette
+                        .as_ref()
+                        .and_then(|p| p.entries().get(s[0] as usize))
+                        .ok_or(PixelErr::MissingPalette)?;
+                    [e.red, e.green, e.blue, e.alpha].map(|c| c as u16 * 257)
+                }
+            });
+        }
+    }
+    Ok(pixels)
+}
+
+/// A way of storing an image's pixels.
+pub struct Representation {
+    pub raw: RawImage,
+    /// The `PLTE` and `tRNS` content of an indexed representation.
+    pub palette: Option<Palette>,
+}
+
+/// Smallest bit depth in `depths` that represents every sample in
+/// `values` exactly.
+fn min_depth(depths: &[u8], values: impl Iterator<Item = u16> + Clone) -> u8 {
+    depths
+        .iter()
+        .copied()
+        .find(|&depth| {
+            let step = 65535 / ((1u32 << depth) - 1);
+            values.clone().all(|v| v as u32 % step == 0)
+        })
+        .unwrap_or(16)
+}
+
+/// Every lossless representation of `pixels` as a `width` by `height`
+/// image: grayscale when all pixels are gray, without alpha when all are
+/// opaque, indexed when there are at most 256 colors, each at the lowest
+/// bit depth that holds the samples exactly. RGBA is always included.
+pub fn reductions(width: u32, height: u32, pixels: &[[u16; 4]]) -> Vec<Representation> {
+    let opaque = pixels.iter().all(|p| p[3] == 65535);
+    let gray = pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let samples = pixels.iter().flat_map(|p| p.iter().copied());
+    let depth = min_depth(&[8, 16], samples);
+    let scale = move |v: u16| v / (65535 / ((1u32 << depth) - 1)) as u16;
+    let build = |color_type: ColorType, depth: u8, samples: Vec<u16>| {
+        let ihdr = Ihdr {
+            width,
+            height,
+            bit_depth: depth,
+            color_type,
+            interlaced: false,
+        };
+        Representation {
+            raw: RawImage::from_samples(&ihdr, &samples),
+            palette: None,
+        }
+    };
+
+    let mut out = Vec::new();
+    if gray && opaque {
+        let depth = min_depth(&[1, 2, 4, 8, 16], pixels.iter().map(|p| p[0]));
+        let step = (65535 / ((1u32 << depth) - 1)) as u16;
+        out.push(build(
+            ColorType::Grayscale,
+            depth,
+            pixels.iter().map(|p| p[0] / step).collect(),
+        ));
+    } else if gray {
+        out.push(build(
+            ColorType::GrayscaleAlpha,
+            depth,
+            pixels
+                .iter()
+                .flat_map(|p| [scale(p[0]), scale(p[3])])
+                .collect(),
+        ));
+    }
+    if opaque {
+        out.push(build(
+            ColorType::Rgb,
+            depth,
+            pixels
+                .iter()
+                .flat_map(|p| p[..3].iter().map(|&v| scale(v)))
+                .collect(),
+        ));
+    }
+    out.push(build(
+        ColorType::Rgba,
+        depth,
+        pixels.iter().flat_map(|p| p.map(scale)).collect(),
+    ));
+    if depth == 8 {
+        out.extend(indexed(width, height, pixels));
+    }
+    out
+}
+
+/// The image indexed over its own colors, translucent ones first so that
+/// `tRNS` stays short, or `None` with more than 256 colors.
+fn indexed(width: u32, height: u32, pixels: &[[u16; 4]]) -> Option<Representation> {
+    let mut colors: Vec<[u8; 4]> = Vec::new();
+    let mut seen = HashSet::new();
+    for p in pixels {
+        let color = p.map(|v| (v / 257) as u8);
+        if seen.insert(color) {
+            if colors.len() == 256 {
+                return None;
+            }
+            colors.push(color);
+        }
+    }
+    colors.sort_by_key(|c| c[3] == 255);
+    let index: HashMap<[u8; 4], u16> = colors
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (c, i as u16))
+        .collect();
+    let depth = match colors.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    let ihdr = Ihdr {
+        width,
+        height,
+        bit_depth: depth,
+        color_type: ColorType::Indexed,
+        interlaced: false,
+    };
+    let samples: Vec<u16> = pixels
+        .iter()
+        .map(|p| index[&p.map(|v| (v / 257) as u8)])
+        .collect();
+    Some(Representation {
+        raw: RawImage::from_samples(&ihdr, &samples),
+        palette: Some(Palette::new(
+            colors
+                .iter()
+                .map(|&[red, green, blue, alpha]| PaletteEntry {
+                    red,
+                    green,
+                    blue,
+                    alpha,
+                })
+                .collect(),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::RgbaImage;
+
+    fn kinds(pixels: &[[u16; 4]]) -> Vec<(ColorType, u8)> {
+        reductions(pixels.len() as u32, 1, pixels)
+            .iter()
+            .map(|r| (r.raw.color_type, r.raw.bit_depth))
+            .collect()
+    }
+
+    #[test]
+    fn test_reductions_black_and_white() {
+        let pixels = [[0, 0, 0, 65535], [65535, 65535, 65535, 65535]];
+        assert_eq!(
+            kinds(&pixels),
+            [
+                (ColorType::Grayscale, 1),
+                (ColorType::Rgb, 8),
+                (ColorType::Rgba, 8),
+                (ColorType::Indexed, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reductions_translucent_colors() {
+        let pixels = [[257, 0, 0, 65535], [0, 514, 0, 0], [0, 0, 771, 32896]];
+        assert_eq!(
+            kinds(&pixels),
+            [(ColorType::Rgba, 8), (ColorType::Indexed, 2)]
+        );
+        let indexed = reductions(3, 1, &pixels).pop().unwrap();
+        let alphas: Vec<u8> = indexed
+            .palette
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|e| e.alpha)
+            .collect();
+        assert_eq!(alphas, [0, 128, 255]);
+    }
+
+    #[test]
+    fn test_reductions_sixteen_bit() {
+        let pixels = [[1, 1, 1, 65535], [2, 2, 2, 65535]];
+        assert_eq!(
+            kinds(&pixels),
+            [
+                (ColorType::Grayscale, 16),
+                (ColorType::Rgb, 16),
+                (ColorType::Rgba, 16)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rgba16_matches_rgba8() {
+        let mut image = RgbaImage::new(2, 1);
+        image.set_pixel(1, 0, [10, 20, 30, 40]);
+        let png = image.to_png().unwrap();
+        assert_eq!(rgba16(&png).unwrap(), [[0; 4], [2570, 5140, 7710, 10280]]);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+use crc32fast::Hasher;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum RepairErr {
+    NotPng,
+    InvalidChunkType(usize),
+    AmbiguousLength(usize, u32),
+}
+impl std::error::Error for RepairErr {}
+impl Display for RepairErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            RepairErr::NotPng => write!(f, "File does not start with the png signature"),
+            RepairErr::InvalidChunkType(offset) => {
+                write!(f, "Invalid chunk type in chunk at offset {}", offset)
+            }
+            RepairErr::AmbiguousLength(offset, length) => write!(
+                f,
+                "Length {} of chunk at offset {} is wrong and no single length fits",
+                length, offset
+            ),
+        }
+    }
+}
+
+/// One change made by [`repair`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fix {
+    Crc {
+        index: usize,
+        chunk_type: String,
+        stored: u32,
+        computed: u32,
+    },
+    Length {
+        index: usize,
+        chunk_type: String,
+        stored: u32,
+        actual: u32,
+    },
+    /// Bytes after the last chunk too few to hold another one.
+    TrailingBytes { offset: usize, count: usize },
+}
+impl Display for Fix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Fix::Crc {
+                index,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Chunk {} ({}): crc {:08x} replaced with {:08x}",
+                index, chunk_type, stored, computed
+            ),
+            Fix::Length {
+                index,
+                chunk_type,
+                stored,
+                actual,
+            } => write!(
+                f,
+                "Chunk {} ({}): length {} replaced with {}",
+                index, chunk_type, stored, actual
+            ),
+            Fix::TrailingBytes { offset, count } => {
+                write!(f, "Dropped {} trailing bytes at offset {}", count, offset)
+            }
+        }
+    }
+}
+
+fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap())
+}
+
+/// Whether a chunk could start at `offset`: either too few bytes are left
+/// for another chunk or a chunk header with an alphabetic type follows.
+fn plausible_chunk_start(bytes: &[u8], offset: usize) -> bool {
+    offset + 12 > bytes.len()
+        || bytes[offset + 4..offset + 8]
+            .iter()
+            .all(u8::is_ascii_alphabetic)
+}
+
+/// Finds the data length of the chunk at `offset` when its length field
+/// cannot be right, as the one length whose trailing crc matches.
+fn find_length(bytes: &[u8], offset: usize) -> Option<u32> {
+    let max = bytes.len() - offset - 12;
+    let mut hasher = Hasher::new();
+    hasher.update(&bytes[offset + 4..offset + 8]);
+    let mut matches = Vec::new();
+    for length in 0..=max {
+        let end = offset + 8 + length;
+        if hasher.clone().finalize() == u32_at(bytes, end) {
+            matches.push(length as u32);
+        }
+        if length < max {
+            hasher.update(&bytes[end..end + 1]);
+        }
+    }
+    match matches.as_slice() {
+        [length] => Some(*length),
+        _ => None,
+    }
+}
+
+/// Rebuilds a png from damaged bytes, recomputing every wrong crc and
+/// correcting length fields that do not fit when exactly one length gives
+/// a matching crc. A chunk with both a wrong length and a wrong crc cannot
+/// be told apart from its neighbours and is an error.
+/// Returns the repaired png and what was changed, which is empty for an
+/// intact file.
+pub fn repair(bytes: &[u8]) -> Result<(Png, Vec<Fix>)> {
+    if bytes.len() < 8 || bytes[..8] != Png::STANDARD_HEADER {
+        return Err(Box::new(RepairErr::NotPng));
+    }
+    let mut chunks = Vec::new();
+    let mut fixes = Vec::new();
+    let mut offset = 8;
+    while offset < bytes.len() {
+        if offset + 12 > bytes.len() {
+            fixes.push(Fix::TrailingBytes {
+                offset,
+                count: bytes.len() - offset,
+            });
+            break;
+        }
+        let type_bytes: [u8; 4] = bytes[offset + 4..offset + 8].try_into().unwrap();
+        if !type_bytes.iter().all(u8::is_ascii_alphabetic) {
+            return Err(Box::new(RepairErr::InvalidChunkType(offset)));
+        }
+        let chunk_type = ChunkType::try_from(type_bytes)?;
+        let stored_length = u32_at(bytes, offset);
+        let end = (offset + 12).saturating_add(stored_length as usize);
+        let length = if end <= bytes.len() && plausible_chunk_start(bytes, end)
+            || find_length(bytes, offset) == Some(stored_length)
+        {
+            stored_length
+        } else {
+            let actual = find_length(bytes, offset)
+                .ok_or(RepairErr::AmbiguousLength(offset, stored_length))?;
+            fixes.push(Fix::Length {
+                index: chunks.len(),
+                chunk_type: chunk_type.to_string(),
+                stored: stored_length,
+                actual,
+            });
+            actual
+        } as usize;
+
+        let data = bytes[offset + 8..offset + 8 + length].to_vec();
+        let stored_crc = u32_at(bytes, offset + 8 + length);
+        let chunk = Chunk::new(chunk_type, data);
+        if chunk.crc() != stored_crc {
+            fixes.push(Fix::Crc {
+                index: chunks.len(),
+                chunk_type: chunk.chunk_type().to_string(),
+                stored: stored_crc,
+                computed: chunk.crc(),
+            });
+        }
+        chunks.push(chunk);
+        offset += length + 12;
+    }
+    Ok((Png::from_chunks(chunks), fixes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_repair_intact() {
+        let (png, fixes) = repair(&PNG_FILE).unwrap();
+        assert!(fixes.is_empty());
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_repair_crc() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Corrupt the stored crc of sRGB.
+        bytes[42] ^= 0xff;
+        let (png, fixes) = repair(&bytes).unwrap();
+        assert_eq!(
+            fixes,
+            vec![Fix::Crc {
+                index: 1,
+                chunk_type: "sRGB".to_string(),
+                stored: u32_at(&bytes, 42),
+                computed: u32_at(&PNG_FILE, 42),
+            }]
+        );
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_repair_length() {
+        let mut bytes = PNG_FILE.to_vec();
+        // gAMA declares 4 data bytes; claim 400.
+        bytes[46..50].copy_from_slice(&400u32.to_be_bytes());
+        assert!(Png::try_from(&bytes[..]).is_err());
+        let (png, fixes) = repair(&bytes).unwrap();
+        assert_eq!(
+            fixes,
+            vec![Fix::Length {
+                index: 2,
+                chunk_type: "gAMA".to_string(),
+                stored: 400,
+                actual: 4,
+            }]
+        );
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_repair_length_and_crc_is_ambiguous() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[46..50].copy_from_slice(&400u32.to_be_bytes());
+        bytes[58] ^= 0xff;
+        assert!(repair(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_repair_trailing_bytes() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend([0, 1, 2]);
+        let (png, fixes) = repair(&bytes).unwrap();
+        assert_eq!(
+            fixes,
+            vec![Fix::TrailingBytes {
+                offset: PNG_FILE.len(),
+                count: 3
+            }]
+        );
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+}
@@ -0,0 +1,129 @@
+//! `Serialize` and `Deserialize` for `ChunkType`, `Chunk` and `Png`, behind
+//! the `serde` feature.
+//!
+//! A chunk type is its 4 character string. A chunk is an object with its
+//! `type`, its `data` in standard base64 and its `crc`; the crc is checked
+//! when deserializing. A png is an object with a `chunks` array.
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+impl Serialize for ChunkType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for ChunkType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        ChunkType::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkRepr {
+    #[serde(rename = "type")]
+    chunk_type: ChunkType,
+    data: String,
+    crc: u32,
+}
+
+impl Serialize for Chunk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChunkRepr {
+            chunk_type: self.chunk_type().clone(),
+            data: base64::encode(self.data()),
+            crc: self.crc(),
+        }
+        .serialize(serializer)
+    }
+}
+impl<'de> Deserialize<'de> for Chunk {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ChunkRepr::deserialize(deserializer)?;
+        let data = base64::decode(&repr.data).map_err(de::Error::custom)?;
+        let chunk = Chunk::new(repr.chunk_type, data);
+        if chunk.crc() != repr.crc {
+            return Err(de::Error::custom(format!(
+                "{} chunk crc {:08x} does not match its data, expected {:08x}",
+                chunk.chunk_type(),
+                repr.crc,
+                chunk.crc()
+            )));
+        }
+        Ok(chunk)
+    }
+}
+
+#[derive(Serialize)]
+struct PngRef<'a> {
+    chunks: &'a [Chunk],
+}
+#[derive(Deserialize)]
+struct PngRepr {
+    chunks: Vec<Chunk>,
+}
+
+impl Serialize for Png {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PngRef {
+            chunks: self.chunks(),
+        }
+        .serialize(serializer)
+    }
+}
+impl<'de> Deserialize<'de> for Png {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Png::from_chunks(PngRepr::deserialize(deserializer)?.chunks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_chunk_type_json() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let json = serde_json::to_string(&chunk_type).unwrap();
+        assert_eq!(json, "\"RuSt\"");
+        assert_eq!(
+            serde_json::from_str::<ChunkType>(&json).unwrap(),
+            chunk_type
+        );
+        assert!(serde_json::from_str::<ChunkType>("\"Ru1t\"").is_err());
+    }
+
+    #[test]
+    fn test_chunk_json() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hi".to_vec());
+        let json = serde_json::to_string(&chunk).unwrap();
+        assert_eq!(
+            json,
+            format!(r#"{{"type":"RuSt","data":"aGk=","crc":{}}}"#, chunk.crc())
+        );
+        let parsed: Chunk = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.as_bytes(), chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_json_bad_crc() {
+        let json = r#"{"type":"RuSt","data":"aGk=","crc":1}"#;
+        assert!(serde_json::from_str::<Chunk>(json).is_err());
+    }
+
+    #[test]
+    fn test_png_json_roundtrip() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let json = serde_json::to_string(&png).unwrap();
+        let parsed: Png = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.as_bytes(), PNG_FILE.to_vec());
+    }
+}
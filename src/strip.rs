@@ -0,0 +1,107 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// Which ancillary chunks `strip` removes. Critical chunks are never
+/// removed, since the image cannot be decoded without them.
+#[derive(Debug, Clone, Default)]
+pub struct StripOptions {
+    /// Every ancillary chunk not listed in `keep`.
+    pub all_ancillary: bool,
+    /// Kept whatever else is selected. Giving any implies `all_ancillary`.
+    pub keep: Vec<ChunkType>,
+    /// Ancillary chunks with the private bit set.
+    pub remove_private: bool,
+    /// Limits removal to chunks whose safe-to-copy bit is set, that is
+    /// chunks an editor may carry over without understanding them. On its
+    /// own it selects every such ancillary chunk.
+    pub safe: bool,
+}
+
+impl StripOptions {
+    /// Whether `chunk` is to be removed.
+    pub fn matches(&self, chunk: &Chunk) -> bool {
+        let t = chunk.chunk_type();
+        if t.is_critical() || self.keep.contains(t) {
+            return false;
+        }
+        let all = self.all_ancillary || !self.keep.is_empty();
+        let selected = if all || self.remove_private {
+            all || !t.is_public()
+        } else {
+            self.safe
+        };
+        selected && (!self.safe || t.is_safe_to_copy())
+    }
+}
+
+/// Removes the chunks `options` select, returning them in file order.
+pub fn strip(png: &mut Png, options: &StripOptions) -> Vec<Chunk> {
+    png.remove_chunks(|c| options.matches(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn removed_types(options: StripOptions) -> Vec<String> {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        strip(&mut png, &options)
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_all_ancillary() {
+        let options = StripOptions {
+            all_ancillary: true,
+            ..StripOptions::default()
+        };
+        assert_eq!(removed_types(options), ["sRGB", "gAMA", "pHYs"]);
+    }
+
+    #[test]
+    fn test_strip_keep() {
+        let options = StripOptions {
+            keep: vec![ChunkType::from_str("pHYs").unwrap()],
+            ..StripOptions::default()
+        };
+        assert_eq!(removed_types(options), ["sRGB", "gAMA"]);
+    }
+
+    #[test]
+    fn test_strip_private_and_safe() {
+        let private = Chunk::new(ChunkType::from_str("prIv").unwrap(), vec![]);
+        let unsafe_private = Chunk::new(ChunkType::from_str("prIV").unwrap(), vec![]);
+        let options = StripOptions {
+            remove_private: true,
+            ..StripOptions::default()
+        };
+        assert!(options.matches(&private));
+        assert!(options.matches(&unsafe_private));
+        assert!(removed_types(options).is_empty());
+
+        let options = StripOptions {
+            remove_private: true,
+            safe: true,
+            ..StripOptions::default()
+        };
+        assert!(options.matches(&private));
+        assert!(!options.matches(&unsafe_private));
+
+        let options = StripOptions {
+            safe: true,
+            ..StripOptions::default()
+        };
+        assert_eq!(removed_types(options), ["pHYs"]);
+    }
+
+    #[test]
+    fn test_strip_nothing_selected() {
+        assert!(removed_types(StripOptions::default()).is_empty());
+    }
+}
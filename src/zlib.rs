@@ -0,0 +1,56 @@
+use crate::limits::LimitErr;
+use crate::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Inflates a zlib stream as found in `IDAT`, `fdAT` and `iCCP` chunks,
+/// failing once the output would exceed `max_size` bytes.
+pub fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut decoder = ZlibDecoder::new(data).take((max_size as u64).saturating_add(1));
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    if out.len() > max_size {
+        return Err(Box::new(LimitErr::DecompressedTooLarge(max_size)));
+    }
+    Ok(out)
+}
+
+/// Deflates `data` into a zlib stream using the default compression level.
+pub fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    deflate_level(data, Compression::default().level())
+}
+
+/// Deflates `data` at `level`, from 0 (store) to 9 (best).
+pub fn deflate_level(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deflate_inflate_roundtrip() {
+        let data = b"The quick brown fox jumps over the lazy dog".repeat(10);
+        let compressed = deflate(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(inflate(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_inflate_garbage() {
+        assert!(inflate(&[1, 2, 3, 4], usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_inflate_limit() {
+        let bomb = deflate(&vec![0; 1 << 20]).unwrap();
+        assert!(bomb.len() < 2048);
+        assert!(inflate(&bomb, 1 << 20).is_ok());
+        assert!(inflate(&bomb, (1 << 20) - 1).is_err());
+    }
+}